@@ -0,0 +1,122 @@
+//! Dedup set throughput under concurrent inserts: `DashSet` against a
+//! sharded `Mutex<FxHashSet>`, with a single-threaded `HashSet` as the
+//! baseline. Matches are rare with the usual patterns, but
+//! a loose mask or predicate can make them frequent enough for the dedup
+//! structure to matter.
+//!
+//! Usage: cargo bench --bench dedup
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use dashmap::DashSet;
+use parking_lot::Mutex;
+use rustc_hash::{FxBuildHasher, FxHashSet};
+use std::{collections::HashSet, hash::BuildHasher, thread};
+
+const FINGERPRINTS_PER_THREAD: usize = 10_000;
+const THREAD_COUNTS: [usize; 4] = [1, 4, 8, 16];
+const SHARDS: usize = 64;
+
+/// Distinct 40-digit hex fingerprints, from a xorshift so every run of the
+/// bench sees the same ones.
+fn fingerprints(count: usize, seed: u64) -> Vec<String> {
+    let mut state = seed | 1;
+    let mut next = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    (0..count)
+        .map(|_| format!("{:016X}{:016X}{:08X}", next(), next(), next() as u32))
+        .collect()
+}
+
+struct ShardedSet {
+    shards: Vec<Mutex<FxHashSet<String>>>,
+}
+
+impl ShardedSet {
+    fn new() -> Self {
+        Self {
+            shards: (0..SHARDS)
+                .map(|_| Mutex::new(FxHashSet::default()))
+                .collect(),
+        }
+    }
+
+    /// Inserts `fingerprint`, returning whether it was new, as
+    /// `DashSet::insert` does.
+    fn insert(&self, fingerprint: &str) -> bool {
+        let shard = FxBuildHasher.hash_one(fingerprint) as usize % SHARDS;
+        let mut set = self.shards[shard].lock();
+        !set.contains(fingerprint) && set.insert(fingerprint.to_string())
+    }
+}
+
+/// Inserts each thread's fingerprints from its own thread, the way workers
+/// deduplicate matches.
+fn insert_concurrently(batches: &[Vec<String>], insert: impl Fn(&str) -> bool + Sync) {
+    thread::scope(|scope| {
+        for batch in batches {
+            let insert = &insert;
+            scope.spawn(move || {
+                for fingerprint in batch {
+                    assert!(insert(fingerprint));
+                }
+            });
+        }
+    });
+}
+
+fn dedup(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dedup");
+    for threads in THREAD_COUNTS {
+        let batches: Vec<Vec<String>> = (0..threads)
+            .map(|thread| fingerprints(FINGERPRINTS_PER_THREAD, thread as u64 + 1))
+            .collect();
+        group.throughput(Throughput::Elements(
+            (threads * FINGERPRINTS_PER_THREAD) as u64,
+        ));
+
+        group.bench_with_input(
+            BenchmarkId::new("dashset", threads),
+            &batches,
+            |b, batches| {
+                b.iter(|| {
+                    let set: DashSet<String> = DashSet::new();
+                    insert_concurrently(batches, |fingerprint| set.insert(fingerprint.to_string()));
+                })
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("sharded", threads),
+            &batches,
+            |b, batches| {
+                b.iter(|| {
+                    let set = ShardedSet::new();
+                    insert_concurrently(batches, |fingerprint| set.insert(fingerprint));
+                })
+            },
+        );
+
+        // Every match funnelled through one thread: no locking at all, but
+        // no parallelism either.
+        group.bench_with_input(
+            BenchmarkId::new("writer", threads),
+            &batches,
+            |b, batches| {
+                b.iter(|| {
+                    let mut set = HashSet::new();
+                    for fingerprint in batches.iter().flatten() {
+                        assert!(set.insert(fingerprint.clone()));
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, dedup);
+criterion_main!(benches);
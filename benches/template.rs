@@ -0,0 +1,96 @@
+//! Heap traffic of `generate_key`, which clones a per-thread `CertBuilder`
+//! template, against setting up a fresh builder for every key as it used
+//! to. Allocations are counted by a wrapper around the system allocator,
+//! so the figures don't depend on jemalloc's size classes.
+//!
+//! Usage: cargo bench --bench template
+
+use sequoia_openpgp::{
+    cert::{CertBuilder, CipherSuite},
+    packet::UserID,
+    types::KeyFlags,
+};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+use vanity_pgp_miner::{config::EncryptionUsage, generate_key, keygen::KeyLayout};
+
+const KEYS: usize = 2_000;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// The builder `generate_key` set up for every key of the default layout
+/// before the template.
+fn fresh_builder(uid: &UserID) -> CertBuilder<'static> {
+    CertBuilder::new()
+        .add_userid(uid.clone())
+        .set_cipher_suite(CipherSuite::Cv25519)
+        .set_primary_key_flags(KeyFlags::empty().set_certification().set_signing())
+        .add_subkey(
+            KeyFlags::empty()
+                .set_transport_encryption()
+                .set_storage_encryption(),
+            None,
+            CipherSuite::Cv25519,
+        )
+}
+
+/// Generates `KEYS` keys with `generate` and prints the allocations, bytes
+/// allocated and time per key.
+fn measure(name: &str, mut generate: impl FnMut()) {
+    // The first key sets up the template, so it isn't counted.
+    generate();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let bytes = BYTES.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..KEYS {
+        generate();
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{:<10} {:>8.1} allocations/key {:>10.0} bytes/key {:>8.1} us/key",
+        name,
+        (ALLOCATIONS.load(Ordering::Relaxed) - allocations) as f64 / KEYS as f64,
+        (BYTES.load(Ordering::Relaxed) - bytes) as f64 / KEYS as f64,
+        elapsed.as_secs_f64() * 1e6 / KEYS as f64
+    );
+}
+
+fn main() {
+    let uid = UserID::from("Vanity Bench <bench@example.com>");
+    let layout = KeyLayout::Standard(EncryptionUsage::Both);
+
+    measure("fresh", || {
+        black_box(fresh_builder(&uid).generate().unwrap());
+    });
+    measure("template", || {
+        black_box(generate_key(&uid, layout, CipherSuite::Cv25519).unwrap());
+    });
+}
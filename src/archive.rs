@@ -0,0 +1,122 @@
+//! `--archive`: a run's keys and log collected in memory and written as a
+//! single tar or zip file when the run ends, instead of loose files.
+
+use crate::export::replace_file;
+use sequoia_openpgp::{crypto::mem::Protected, Error, Result};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use zip::write::SimpleFileOptions;
+
+const PUBLIC_MODE: u32 = 0o644;
+/// Private keys are marked owner-only, so extracting the archive doesn't
+/// leave them readable by everyone.
+const PRIVATE_MODE: u32 = 0o600;
+
+/// A file to archive. Its data is `Protected`, so private keys held until
+/// the end of the run are wiped from memory once written.
+struct Entry {
+    name: String,
+    data: Protected,
+    private: bool,
+}
+
+pub struct Archive {
+    path: PathBuf,
+    /// Permissions of the archive file itself, which holds private keys.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    file_mode: u32,
+    entries: Vec<Entry>,
+    /// found_keys.txt, added as the last entry.
+    pub log: Vec<u8>,
+}
+
+impl Archive {
+    pub fn new(path: PathBuf, file_mode: u32) -> Self {
+        Self {
+            path,
+            file_mode,
+            entries: Vec::new(),
+            log: Vec::new(),
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.iter().any(|entry| entry.name == name)
+    }
+
+    pub fn add(&mut self, name: String, data: Protected, private: bool) {
+        self.entries.push(Entry {
+            name,
+            data,
+            private,
+        });
+    }
+
+    fn all_entries(&self) -> impl Iterator<Item = (&str, &[u8], u32)> {
+        self.entries
+            .iter()
+            .map(|entry| {
+                let mode = if entry.private {
+                    PRIVATE_MODE
+                } else {
+                    PUBLIC_MODE
+                };
+                (entry.name.as_str(), &entry.data[..], mode)
+            })
+            .chain([("found_keys.txt", self.log.as_slice(), PUBLIC_MODE)])
+    }
+
+    /// Writes the archive, as zip if the path ends in `.zip` and tar
+    /// otherwise. It's written under a temporary name and renamed into
+    /// place, so a failed write never leaves a truncated archive.
+    pub fn write(&self) -> Result<()> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, self.file_mode);
+        replace_file(&self.path, &options, |file| {
+            let file = BufWriter::new(file);
+            if self.path.extension().is_some_and(|ext| ext == "zip") {
+                self.write_zip(file)
+            } else {
+                self.write_tar(file)
+            }
+        })
+    }
+
+    fn write_tar(&self, file: BufWriter<File>) -> Result<()> {
+        let mtime = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let mut builder = tar::Builder::new(file);
+        for (name, data, mode) in self.all_entries() {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(mode);
+            header.set_mtime(mtime);
+            header.set_cksum();
+            builder.append_data(&mut header, name, data)?;
+        }
+        builder.into_inner()?.flush()?;
+        Ok(())
+    }
+
+    fn write_zip(&self, file: BufWriter<File>) -> Result<()> {
+        let mut writer = zip::ZipWriter::new(file);
+        for (name, data, mode) in self.all_entries() {
+            writer
+                .start_file(name, SimpleFileOptions::default().unix_permissions(mode))
+                .map_err(|e| Error::InvalidOperation(e.to_string()))?;
+            writer.write_all(data)?;
+        }
+        writer
+            .finish()
+            .map_err(|e| Error::InvalidOperation(e.to_string()))?
+            .flush()?;
+        Ok(())
+    }
+}
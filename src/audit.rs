@@ -0,0 +1,68 @@
+//! `--audit-log`: one timestamped JSON line per significant event of a run,
+//! so there is a record of what was searched for and found that outlives
+//! the terminal.
+
+use chrono::{SecondsFormat, Utc};
+use parking_lot::Mutex;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    sync::OnceLock,
+};
+
+/// Set once by [`open`]. Warnings come from all over the miner, so this is
+/// global rather than threaded through every call that might emit one.
+static AUDIT_LOG: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Starts recording to `path`, appending if it exists. Until this is
+/// called, [`record`] does nothing.
+pub fn open(path: &Path) -> io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let _ = AUDIT_LOG.set(Mutex::new(file));
+    Ok(())
+}
+
+/// Appends `{"time":...,"event":...,<fields>}`, where `fields` is already
+/// rendered JSON members, or empty. The file is unbuffered, so each event
+/// reaches the OS as soon as it happens and survives a crash.
+pub fn record(event: &str, fields: &str) {
+    let Some(file) = AUDIT_LOG.get() else {
+        return;
+    };
+    let line = format!(
+        "{{\"time\":\"{}\",\"event\":\"{}\"{}{}}}\n",
+        Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        event,
+        if fields.is_empty() { "" } else { "," },
+        fields
+    );
+    let mut file = file.lock();
+    if let Err(e) = file.write_all(line.as_bytes()).and_then(|()| file.flush()) {
+        eprintln!("\nError writing audit log: {}", e);
+    }
+}
+
+pub fn warning(message: &str) {
+    record("warning", &format!("\"message\":{}", json_string(message)));
+}
+
+pub fn error(message: &str) {
+    record("error", &format!("\"message\":{}", json_string(message)));
+}
+
+/// `s` as a quoted JSON string.
+pub fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
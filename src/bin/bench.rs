@@ -0,0 +1,95 @@
+//! One-shot throughput check: generates and matches keys for a fixed
+//! wall-clock window on every core, exactly as the miner does, and reports
+//! keys/sec without saving anything.
+//!
+//! Usage: cargo run --release --bin bench -- [--seconds N] [--anywhere] [--pin-threads]
+
+#[global_allocator]
+#[cfg(not(target_env = "msvc"))]
+static GLOBAL: jemallocator::Jemalloc = jemallocator::Jemalloc;
+
+#[cfg(target_env = "msvc")]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+use sequoia_openpgp::{cert::CipherSuite, packet::UserID, Error, Result};
+use std::time::{Duration, Instant};
+use vanity_pgp_miner::{
+    config::EncryptionUsage, generate_key, generate_patterns, keygen::KeyLayout,
+    miner::build_thread_pool, Matcher, PatternCache,
+};
+
+const DEFAULT_SECONDS: u64 = 10;
+
+fn main() -> Result<()> {
+    let mut seconds = DEFAULT_SECONDS;
+    let mut anywhere = false;
+    let mut pin_threads = false;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seconds" => {
+                seconds = match args.next().and_then(|s| s.parse().ok()) {
+                    Some(seconds) if seconds > 0 => seconds,
+                    _ => {
+                        eprintln!("--seconds expects a positive integer");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--anywhere" => anywhere = true,
+            "--pin-threads" => pin_threads = true,
+            other => {
+                eprintln!(
+                    "Usage: bench [--seconds N] [--anywhere] [--pin-threads] (got {})",
+                    other
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let uid = UserID::from("Vanity Bench <bench@example.com>");
+    let matcher =
+        PatternCache::new(generate_patterns(), anywhere).map_err(Error::InvalidArgument)?;
+    let pool = build_thread_pool(None, pin_threads)?;
+    let window = Duration::from_secs(seconds);
+
+    println!(
+        "Benchmarking for {}s on {} threads...",
+        seconds,
+        pool.current_num_threads()
+    );
+
+    let start = Instant::now();
+    let per_thread: Vec<(usize, usize)> = pool.broadcast(|_| {
+        let (mut generated, mut matched) = (0, 0);
+        while start.elapsed() < window {
+            if let Ok((_, _, key_id)) = generate_key(
+                &uid,
+                KeyLayout::Standard(EncryptionUsage::Both),
+                CipherSuite::Cv25519,
+            ) {
+                generated += 1;
+                if matcher.find(&key_id).is_some() {
+                    matched += 1;
+                }
+            }
+        }
+        (generated, matched)
+    });
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let generated: usize = per_thread.iter().map(|(generated, _)| generated).sum();
+    let matched: usize = per_thread.iter().map(|(_, matched)| matched).sum();
+    println!(
+        "{} keys in {:.2}s: {:.0} keys/s ({} matches)",
+        generated,
+        elapsed,
+        generated as f64 / elapsed,
+        matched
+    );
+
+    Ok(())
+}
@@ -0,0 +1,1974 @@
+use crate::{
+    error::MinerError,
+    export::check_log_template,
+    keygen::{parse_compression, parse_hash, parse_symmetric, rsa_suite, KeyLayout},
+    matcher::{
+        FingerprintSet, MaskMatcher, PatternCache, WholePredicate, FINGERPRINT_HEX_LEN, GROUP_LEN,
+        PATTERN_LEN,
+    },
+    schedule::Schedule,
+    score::Scorer,
+    transform::build_transform,
+};
+use sequoia_openpgp::cert::CipherSuite;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fs, path::PathBuf, str::FromStr, time::Duration};
+
+pub const DEFAULT_TOTAL_KEYS: usize = 2_000_000;
+/// `total_keys` at or above this means no limit: the run goes on until
+/// `--max-matches` or a signal stops it. 2^53 is the largest count that
+/// stays exact in the progress maths and in JSON, and would take centuries
+/// to check anyway. `unlimited` on the command line sets exactly this, and
+/// larger values are brought down to it so the config stays valid TOML.
+pub const UNLIMITED_TOTAL_KEYS: usize = 1 << 53;
+pub const DEFAULT_SAVE_QUEUE_DEPTH: usize = 64;
+pub const DEFAULT_DIR_MODE: u32 = 0o700;
+pub const DEFAULT_FILE_MODE: u32 = 0o600;
+const FAST_INSECURE_COMMENT: &str = "INSECURE TEST KEY";
+/// `--armor-width` values allowed. RFC 4880 caps armor lines at 76
+/// characters, and anything much shorter than 16 only bloats the file.
+const ARMOR_WIDTHS: std::ops::RangeInclusive<usize> = 16..=76;
+pub const NAME_ENV: &str = "VANITY_NAME";
+pub const EMAIL_ENV: &str = "VANITY_EMAIL";
+
+const OPTIONS: &[(&str, &str)] = &[
+    (
+        "--identities <path>",
+        "Mine keys for each `Name <email>` line of this file in turn",
+    ),
+    (
+        "--comment <text>",
+        "Add a comment to the user ID: Name (text) <email>",
+    ),
+    (
+        "--config <path>",
+        "Load options from a TOML file (CLI flags win)",
+    ),
+    (
+        "--dump-config",
+        "Print the effective config as TOML and exit",
+    ),
+    (
+        "--anywhere",
+        "Match patterns at any offset, not just the fixed window",
+    ),
+    (
+        "--pattern <hex>",
+        "Search for this 4-16 digit pattern instead of the built-ins (repeatable)",
+    ),
+    (
+        "--mask <mask>",
+        "Search for a hex mask such as DE?DBE?F instead of the pattern set",
+    ),
+    (
+        "--dont-care <list>",
+        "Treat these positions (0-7) of the fixed window as wildcards",
+    ),
+    (
+        "--fingerprint-set <path>",
+        "Match only the exact 40-digit fingerprints listed in this file",
+    ),
+    (
+        "--whole-fingerprint-predicate <p>",
+        "Match whole fingerprints: no-<digit>, repeats:<n> or digits-only",
+    ),
+    (
+        "--min-pattern-length <n>",
+        "Drop patterns shorter than n characters",
+    ),
+    (
+        "--max-pattern-length <n>",
+        "Drop patterns longer than n characters",
+    ),
+    (
+        "--prefer-common <x>",
+        "Drop patterns more than x times less likely than the likeliest",
+    ),
+    (
+        "--max-matches <n>",
+        "Stop once this many matching keys have been found",
+    ),
+    (
+        "--collide-short-id <id>",
+        "Research: mine until a key's short key ID equals this 8-digit one",
+    ),
+    (
+        "--stop-when-exhausted",
+        "Stop once every pattern (or --fingerprint-set entry) has matched at least once",
+    ),
+    (
+        "--once",
+        "Find a single matching key and exit (--max-matches 1)",
+    ),
+    (
+        "--min-score <n>",
+        "Only keep matches scoring at least n under the score rules",
+    ),
+    (
+        "--score-rules <path>",
+        "Load --min-score rules from a TOML file",
+    ),
+    (
+        "--run-between <HH:MM-HH:MM>",
+        "Only mine during this daily local-time window",
+    ),
+    (
+        "--delay-start <seconds>",
+        "Count down this long before mining, to start several machines together",
+    ),
+    (
+        "--debug-dump-first",
+        "Print the packet structure of a generated key to stderr first",
+    ),
+    (
+        "--x509-out",
+        "Also save PKCS#8 and self-signed X.509 files (feature x509)",
+    ),
+    (
+        "--qr",
+        "Print each match's fingerprint as a QR code and save qr_N.png (feature qr)",
+    ),
+    (
+        "--state <path>",
+        "Checkpoint progress here and resume from it on the next run",
+    ),
+    (
+        "--transform <none|base32|reverse>",
+        "Match the identifier after re-encoding it, e.g. as base32",
+    ),
+    (
+        "--reverse",
+        "Match the identifier read backwards (--transform reverse)",
+    ),
+    (
+        "--group <n>",
+        "Start the match window at the nth 4-digit fingerprint group",
+    ),
+    (
+        "--best-practice",
+        "Certify-only primary with separate signing and encryption subkeys",
+    ),
+    (
+        "--monolithic",
+        "One RSA primary key that certifies, signs and encrypts, with no subkeys",
+    ),
+    (
+        "--rsa-bits <bits>",
+        "Generate RSA keys of 2048, 3072 or 4096 bits instead of Curve25519",
+    ),
+    (
+        "--encryption-usage <use>",
+        "Encryption subkey flags: both, transport or storage (default: both)",
+    ),
+    (
+        "--match-key <key>",
+        "Match the primary, encryption (subkey) or signing subkey",
+    ),
+    (
+        "--id-form <form>",
+        "Match and log the fingerprint, long or short key ID, or keygrip",
+    ),
+    (
+        "--no-odds-warning",
+        "Don't warn when total_keys is too low to expect a match",
+    ),
+    (
+        "--auth-subkey",
+        "Add an Ed25519 authentication subkey to each match",
+    ),
+    (
+        "--ssh-out <dir>",
+        "Save the auth subkey as OpenSSH id_vanity_N files (feature ssh)",
+    ),
+    (
+        "--archive <file>",
+        "Write keys and log into one .tar or .zip at the end (feature archive)",
+    ),
+    (
+        "--webhook <url>",
+        "POST a JSON notification per match to url (feature webhook)",
+    ),
+    (
+        "--watts <w>",
+        "Print a rough time and energy estimate per match for a machine drawing w watts",
+    ),
+    (
+        "--price-per-kwh <price>",
+        "With --watts, also estimate the electricity cost per match",
+    ),
+    (
+        "--serve <address:port>",
+        "Stay up and mine pattern sets sent over a loopback socket, reusing warm workers",
+    ),
+    (
+        "--coordinator <url>",
+        "Report to a `coordinate` server and stop when it has enough (feature coordinator)",
+    ),
+    (
+        "--name-by <index|fingerprint>",
+        "Name exported files by index or by short key ID",
+    ),
+    (
+        "--max-files-per-dir <n>",
+        "Save at most n keys per numbered subdirectory of the export dir",
+    ),
+    (
+        "--fallback-dir <path>",
+        "Save keys here when the export dir's disk is full",
+    ),
+    (
+        "--hex-case <upper|lower>",
+        "Case of fingerprints in match notices, the log and file names",
+    ),
+    (
+        "--save-queue-depth <n>",
+        "Matches queued for saving before mining waits (default 64)",
+    ),
+    (
+        "--profile",
+        "Write a flamegraph of the run to flamegraph.svg (feature profile)",
+    ),
+    (
+        "--gpg-import",
+        "Import each match into your GnuPG keyring (needs gpg on PATH)",
+    ),
+    (
+        "--stdout",
+        "Write each match's armored public key to stdout instead of files",
+    ),
+    (
+        "--stdout-private",
+        "With --stdout, also write each secret key",
+    ),
+    (
+        "--stats-file <path>",
+        "Keep a JSON snapshot of progress in this file, updated every second",
+    ),
+    (
+        "--audit-log <path>",
+        "Append a timestamped JSON line for each start, match, warning, error and finish",
+    ),
+    (
+        "--progress-format <bar|json|plain>",
+        "How to report progress; json prints one object per line for wrapping tools",
+    ),
+    (
+        "--plain-progress",
+        "Print a timestamped progress line every second instead of the bar",
+    ),
+    (
+        "--threads <n>",
+        "Number of mining threads (default: one per core)",
+    ),
+    (
+        "--pin-threads",
+        "Pin each mining thread to its own core for steadier throughput",
+    ),
+    (
+        "--warmup",
+        "Generate a few throwaway keys per thread before the clock starts",
+    ),
+    (
+        "--max-attempts-per-match <x>",
+        "Warn after x times the expected keys per match without one",
+    ),
+    (
+        "--max-error-rate <0-1>",
+        "Stop if more than this fraction of key generations fail (default: 0.5)",
+    ),
+    (
+        "--min-rate <keys/s>",
+        "Warn when throughput drops below this many keys/s",
+    ),
+    (
+        "--on-low-rate <warn|abort>",
+        "What --min-rate does about a low rate (default: warn)",
+    ),
+    (
+        "--signature-hash <name>",
+        "Digest for matched keys' self-signatures: sha224, sha256, sha384, sha512",
+    ),
+    (
+        "--symmetric-prefs <list>",
+        "Comma-separated symmetric algorithms to advertise, most preferred first",
+    ),
+    (
+        "--compression-prefs <list>",
+        "Comma-separated compression algorithms to advertise, most preferred first",
+    ),
+    (
+        "--armor-comment <text>",
+        "Add a Comment line to the armor headers of exported keys",
+    ),
+    (
+        "--log-template <template>",
+        "Format found_keys.txt lines from {index}, {fpr}, {pattern}, {offset} and {time}",
+    ),
+    (
+        "--armor-width <n>",
+        "Wrap the armored body of exported keys at n characters (16-76, default 64)",
+    ),
+    (
+        "--dir-mode <octal>",
+        "Permissions for directories the miner creates (default: 700)",
+    ),
+    (
+        "--file-mode <octal>",
+        "Permissions for private key files (default: 600)",
+    ),
+    (
+        "--no-dedup",
+        "Don't track matched fingerprints to skip repeats",
+    ),
+    (
+        "--exclude-keyring <path>",
+        "Skip matches whose fingerprint is already in this keyring file",
+    ),
+    (
+        "--no-revocation",
+        "Don't export a revocation certificate per match",
+    ),
+    (
+        "--compliance",
+        "Skip matches that aren't valid under sequoia's StandardPolicy",
+    ),
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressFormat {
+    /// An interactive indicatif progress bar.
+    Bar,
+    /// A start handshake then one JSON object per line, for wrapping tools.
+    Json,
+    /// A timestamped line of text per interval, for logs.
+    Plain,
+}
+
+impl FromStr for ProgressFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bar" => Ok(Self::Bar),
+            "json" => Ok(Self::Json),
+            "plain" => Ok(Self::Plain),
+            _ => Err(format!(
+                "Unknown progress format {:?}, expected bar, json or plain",
+                s
+            )),
+        }
+    }
+}
+
+/// Which key's fingerprint is matched against.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchKey {
+    Primary,
+    /// The encryption subkey.
+    Subkey,
+    /// The signing subkey, which only `--best-practice` keys have.
+    Signing,
+}
+
+impl FromStr for MatchKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "primary" => Ok(Self::Primary),
+            "subkey" => Ok(Self::Subkey),
+            "signing" => Ok(Self::Signing),
+            _ => Err(format!(
+                "Unknown key {:?}, expected primary, subkey or signing",
+                s
+            )),
+        }
+    }
+}
+
+/// What the encryption subkey may be used for, per RFC 4880's split
+/// between data in transit and data at rest.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EncryptionUsage {
+    /// Both, as sequoia and GnuPG default to.
+    Both,
+    /// Communications only.
+    Transport,
+    /// Storage only.
+    Storage,
+}
+
+impl FromStr for EncryptionUsage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "both" => Ok(Self::Both),
+            "transport" => Ok(Self::Transport),
+            "storage" => Ok(Self::Storage),
+            _ => Err(format!(
+                "Unknown encryption usage {:?}, expected both, transport or storage",
+                s
+            )),
+        }
+    }
+}
+
+/// Which form of the matched key's identifier patterns are searched in
+/// and logged as.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdForm {
+    /// The full 40-digit fingerprint.
+    Fingerprint,
+    /// The 16-digit long key ID, the fingerprint's last 16 digits.
+    Long,
+    /// The 8-digit short key ID, the fingerprint's last 8 digits.
+    Short,
+    /// The 40-digit GnuPG keygrip, a hash of the public key parameters
+    /// alone. Unrelated to the fingerprint.
+    Keygrip,
+}
+
+impl IdForm {
+    pub fn hex_len(self) -> usize {
+        match self {
+            Self::Fingerprint => FINGERPRINT_HEX_LEN,
+            Self::Long => 16,
+            Self::Short => 8,
+            Self::Keygrip => FINGERPRINT_HEX_LEN,
+        }
+    }
+}
+
+impl FromStr for IdForm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fingerprint" => Ok(Self::Fingerprint),
+            "long" => Ok(Self::Long),
+            "short" => Ok(Self::Short),
+            "keygrip" => Ok(Self::Keygrip),
+            _ => Err(format!(
+                "Unknown id form {:?}, expected fingerprint, long, short or keygrip",
+                s
+            )),
+        }
+    }
+}
+
+/// Which `--transform` identifiers go through before matching.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransformKind {
+    /// Match the hex identifier as it is.
+    None,
+    Base32,
+    /// The hex identifier reversed.
+    Reverse,
+}
+
+impl TransformKind {
+    /// Whether identifiers leave hex, ruling out the built-in patterns and
+    /// `--dont-care`.
+    pub fn changes_alphabet(self) -> bool {
+        self == Self::Base32
+    }
+}
+
+impl FromStr for TransformKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "base32" => Ok(Self::Base32),
+            "reverse" => Ok(Self::Reverse),
+            _ => Err(format!(
+                "Unknown transform {:?}, expected none, base32 or reverse",
+                s
+            )),
+        }
+    }
+}
+
+/// How exported key files are named.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NameBy {
+    /// `public_key_N.asc`, numbered in the order keys were saved.
+    Index,
+    /// `pub_SHORTID.asc`, by the primary key's short key ID.
+    Fingerprint,
+}
+
+impl FromStr for NameBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "index" => Ok(Self::Index),
+            "fingerprint" => Ok(Self::Fingerprint),
+            _ => Err(format!(
+                "Unknown naming {:?}, expected index or fingerprint",
+                s
+            )),
+        }
+    }
+}
+
+/// What `--min-rate` does when the rate drops below it.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LowRateAction {
+    /// Print a warning, once until the rate recovers.
+    Warn,
+    /// Stop the run with an error.
+    Abort,
+}
+
+impl FromStr for LowRateAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(Self::Warn),
+            "abort" => Ok(Self::Abort),
+            _ => Err(format!(
+                "Unknown low-rate action {:?}, expected warn or abort",
+                s
+            )),
+        }
+    }
+}
+
+/// Letter case of fingerprints and identifiers in output. Matching always
+/// uses the uppercase form.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HexCase {
+    /// As sequoia and `gpg --fingerprint` print them.
+    Upper,
+    /// As in `gpg --with-colons` output.
+    Lower,
+}
+
+impl HexCase {
+    pub fn apply(self, hex: &str) -> String {
+        match self {
+            Self::Upper => hex.to_ascii_uppercase(),
+            Self::Lower => hex.to_ascii_lowercase(),
+        }
+    }
+}
+
+impl FromStr for HexCase {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "upper" => Ok(Self::Upper),
+            "lower" => Ok(Self::Lower),
+            _ => Err(format!("Unknown case {:?}, expected upper or lower", s)),
+        }
+    }
+}
+
+/// A named set of patterns mined for alongside the others until it has
+/// `max_matches` matches of its own. Only settable in a `--config` file,
+/// as `[[pattern_groups]]` tables.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PatternGroup {
+    pub name: String,
+    pub patterns: Vec<String>,
+    pub max_matches: usize,
+}
+
+/// Everything a run needs. It can be loaded from a TOML file with
+/// `--config`, where every field is optional and defaults as below.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub name: String,
+    pub email: String,
+    pub comment: Option<String>,
+    /// `Name <email>` user IDs to mine keys for in turn, instead of `name`
+    /// and `email`. `--identities` reads them from a file.
+    pub identities: Vec<String>,
+    pub export_dir: PathBuf,
+    /// Keys to check before stopping; see [`UNLIMITED_TOTAL_KEYS`].
+    pub total_keys: usize,
+    /// Warn at startup when `total_keys` gives under a 1% chance of any
+    /// match at all.
+    pub odds_warning: bool,
+    /// Stop as soon as this many keys have matched, rather than running
+    /// through `total_keys`. With `identities`, each one needs this many.
+    pub max_matches: Option<usize>,
+    /// Stop once every pattern in the set has matched at least once.
+    pub stop_when_exhausted: bool,
+    /// `--collide-short-id`: the short key ID being collided with. It takes
+    /// the place of `patterns` and `id_form` rather than setting them, so
+    /// read those through [`Config::pattern_list`] and
+    /// [`Config::match_id_form`].
+    #[serde(skip)]
+    pub collide_short_id: Option<String>,
+    pub match_anywhere: bool,
+    /// The 1-based four-digit group, as GnuPG spaces fingerprints, that the
+    /// fixed window starts at instead of the default position.
+    pub group: Option<usize>,
+    pub match_key: MatchKey,
+    /// Generate a certification-only primary key with separate signing and
+    /// encryption subkeys.
+    pub best_practice: bool,
+    /// Generate a single certify+sign+encrypt primary key and no subkeys.
+    pub monolithic: bool,
+    /// RSA modulus size for every key, instead of Ed25519 and Cv25519.
+    pub rsa_bits: Option<u32>,
+    /// Key flags of the encryption subkey.
+    pub encryption_usage: EncryptionUsage,
+    pub id_form: IdForm,
+    pub transform: TransformKind,
+    /// Drop patterns more than this many times less likely than the
+    /// likeliest one before mining.
+    pub prefer_common: Option<f64>,
+    /// Drop patterns shorter or longer than these before mining.
+    pub min_pattern_len: Option<usize>,
+    pub max_pattern_len: Option<usize>,
+    /// Patterns to search for instead of the built-in set.
+    pub patterns: Option<Vec<String>>,
+    /// Independently limited pattern sets, searched for instead of
+    /// `patterns`. Mining stops once every group has met its target.
+    pub pattern_groups: Vec<PatternGroup>,
+    /// Search for this hex mask or range spec instead of the built-in
+    /// patterns; see [`crate::matcher::parse_mask`].
+    pub mask: Option<String>,
+    /// File of exact fingerprints to search for instead of
+    /// patterns.
+    pub fingerprint_set: Option<PathBuf>,
+    /// Built-in condition on the entire fingerprint to search for instead
+    /// of patterns; see [`crate::matcher::WholePredicate`].
+    pub whole_fingerprint_predicate: Option<String>,
+    /// Positions within the fixed 8-digit window that match any digit when
+    /// comparing against the pattern set.
+    pub dont_care: Vec<usize>,
+    /// Whether the workers remember matched fingerprints so a repeat is
+    /// not saved twice. The set grows by one entry per match for the whole
+    /// run; with a CSPRNG a repeated fingerprint is astronomically unlikely,
+    /// so long runs with loose patterns can turn this off to keep memory
+    /// flat.
+    pub dedup: bool,
+    /// Keyring whose certs' fingerprints seed the dedup set, so keys
+    /// already in it are never saved again.
+    pub exclude_keyring: Option<PathBuf>,
+    /// Skip matches whose fingerprint scores below this under the
+    /// `score_rules` file, or the built-in repetition rules without one.
+    pub min_score: Option<u32>,
+    pub score_rules: Option<PathBuf>,
+    pub revocation: bool,
+    /// Also export each match as PKCS#8 and a self-signed X.509
+    /// certificate. Needs the `x509` cargo feature.
+    pub x509_out: bool,
+    /// Print each match's fingerprint as a QR code and save it as
+    /// `qr_N.png`. Needs the `qr` cargo feature.
+    pub qr: bool,
+    /// Import each saved match into the user's keyring with `gpg --import`.
+    pub gpg_import: bool,
+    /// Add an authentication subkey to each matched key.
+    pub auth_subkey: bool,
+    /// Directory to export the authentication subkey to in OpenSSH format.
+    /// Needs the `ssh` cargo feature.
+    pub ssh_out: Option<PathBuf>,
+    /// Tar or zip file to collect the run's keys and log into, instead of
+    /// loose files in `export_dir`. Needs the `archive` cargo feature.
+    pub archive: Option<PathBuf>,
+    /// URL to POST a JSON notification to for each match. Needs the
+    /// `webhook` cargo feature.
+    pub webhook: Option<String>,
+    /// Power draw of the machine while mining, for the startup cost
+    /// estimate.
+    pub watts: Option<f64>,
+    /// Electricity price per kWh for the cost estimate, in whatever
+    /// currency the user thinks in.
+    pub price_per_kwh: Option<f64>,
+    /// Address to serve `MINE` requests on instead of mining once; see
+    /// [`crate::server`].
+    #[serde(skip)]
+    pub serve: Option<String>,
+    /// Base URL of a `coordinate` server to report to, with the
+    /// `coordinator` cargo feature.
+    pub coordinator: Option<String>,
+    /// Warn when this many times the expected attempts per match pass
+    /// without one.
+    pub max_attempts_per_match: Option<f64>,
+    /// Fraction of key generations that may fail before the run is stopped
+    /// with the underlying error.
+    pub max_error_rate: f64,
+    /// Keys/s the run is expected to keep up, checked over a sliding
+    /// window after a grace period.
+    pub min_rate: Option<u64>,
+    pub on_low_rate: LowRateAction,
+    /// Worker threads to mine with; one per core when unset.
+    pub threads: Option<usize>,
+    pub pin_threads: bool,
+    /// Daily local-time window, e.g. `22:00-06:00`, outside which the
+    /// workers sleep instead of mining.
+    pub run_between: Option<String>,
+    /// Seconds to count down before mining starts.
+    pub delay_start: Option<u64>,
+    pub warmup: bool,
+    pub compliance: bool,
+    /// Stream armored keys to stdout instead of writing `export_dir`; all
+    /// status output moves to stderr.
+    pub stdout: bool,
+    pub stdout_private: bool,
+    pub progress_format: ProgressFormat,
+    /// File the progress thread keeps rewritten with the latest counts.
+    pub stats_file: Option<PathBuf>,
+    /// JSON-lines record of the run's events; see [`crate::audit`].
+    pub audit_log: Option<PathBuf>,
+    /// Keys per numbered subdirectory of `export_dir`, so high-yield runs
+    /// don't pile thousands of files into one directory.
+    pub max_files_per_dir: Option<usize>,
+    pub name_by: NameBy,
+    /// Case of fingerprints in the match notices, `found_keys.txt` and
+    /// file names.
+    pub hex_case: HexCase,
+    /// Secondary directory for keys that can't be saved because the disk
+    /// holding `export_dir` is full.
+    pub fallback_dir: Option<PathBuf>,
+    /// How many matches may wait for the writer before workers that find
+    /// another block until it catches up.
+    pub save_queue_depth: usize,
+    /// Unix permissions for directories the miner creates and for files
+    /// holding secret keys. Public files keep the umask default.
+    pub dir_mode: u32,
+    pub file_mode: u32,
+    /// File the run's counts are checkpointed to and resumed from.
+    pub state_file: Option<PathBuf>,
+    /// Preferred symmetric and compression algorithm names, most preferred
+    /// first. `None` keeps sequoia's defaults.
+    pub symmetric_prefs: Option<Vec<String>>,
+    pub compression_prefs: Option<Vec<String>>,
+    /// Digest for the self-signatures, e.g. `sha512`. `None` keeps
+    /// sequoia's default.
+    pub signature_hash: Option<String>,
+    /// Extra `Comment:` armor header for exported keys, after sequoia's own.
+    pub armor_comment: Option<String>,
+    /// Format of found_keys.txt lines, replacing the built-in one; see
+    /// [`crate::export::check_log_template`].
+    pub log_template: Option<String>,
+    /// Line length for the base64 body of exported keys' armor. `None`
+    /// keeps sequoia's 64.
+    pub armor_width: Option<usize>,
+    #[serde(skip)]
+    pub dump_config: bool,
+    #[serde(skip)]
+    pub debug_dump_first: bool,
+    #[serde(skip)]
+    pub profile: bool,
+    /// Hidden `--fast-insecure`: quicker, subkey-less test keys for
+    /// exercising pipelines end to end. Never for real use.
+    #[serde(skip)]
+    pub fast_insecure: bool,
+    /// Hidden `--debug-save-delay-ms`: how long the writer sleeps before
+    /// each save, to stand in for a slow disk when exercising the bounded
+    /// save queue.
+    #[serde(skip)]
+    pub debug_save_delay: Option<Duration>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            email: String::new(),
+            comment: None,
+            identities: Vec::new(),
+            export_dir: PathBuf::from("./gpg_export"),
+            total_keys: DEFAULT_TOTAL_KEYS,
+            odds_warning: true,
+            max_matches: None,
+            stop_when_exhausted: false,
+            collide_short_id: None,
+            match_anywhere: false,
+            group: None,
+            match_key: MatchKey::Primary,
+            best_practice: false,
+            monolithic: false,
+            rsa_bits: None,
+            encryption_usage: EncryptionUsage::Both,
+            id_form: IdForm::Fingerprint,
+            transform: TransformKind::None,
+            prefer_common: None,
+            min_pattern_len: None,
+            max_pattern_len: None,
+            patterns: None,
+            pattern_groups: Vec::new(),
+            mask: None,
+            fingerprint_set: None,
+            whole_fingerprint_predicate: None,
+            dont_care: Vec::new(),
+            dedup: true,
+            exclude_keyring: None,
+            min_score: None,
+            score_rules: None,
+            revocation: true,
+            x509_out: false,
+            qr: false,
+            gpg_import: false,
+            auth_subkey: false,
+            ssh_out: None,
+            archive: None,
+            webhook: None,
+            watts: None,
+            price_per_kwh: None,
+            serve: None,
+            coordinator: None,
+            max_error_rate: 0.5,
+            min_rate: None,
+            on_low_rate: LowRateAction::Warn,
+            max_attempts_per_match: None,
+            threads: None,
+            pin_threads: false,
+            run_between: None,
+            delay_start: None,
+            warmup: false,
+            compliance: false,
+            stdout: false,
+            stdout_private: false,
+            progress_format: ProgressFormat::Bar,
+            stats_file: None,
+            audit_log: None,
+            max_files_per_dir: None,
+            name_by: NameBy::Index,
+            hex_case: HexCase::Upper,
+            fallback_dir: None,
+            save_queue_depth: DEFAULT_SAVE_QUEUE_DEPTH,
+            dir_mode: DEFAULT_DIR_MODE,
+            file_mode: DEFAULT_FILE_MODE,
+            state_file: None,
+            symmetric_prefs: None,
+            compression_prefs: None,
+            signature_hash: None,
+            armor_comment: None,
+            log_template: None,
+            armor_width: None,
+            dump_config: false,
+            debug_dump_first: false,
+            profile: false,
+            fast_insecure: false,
+            debug_save_delay: None,
+        }
+    }
+}
+
+impl Config {
+    /// Whether `total_keys` means "until stopped" rather than a count.
+    pub fn is_unlimited(&self) -> bool {
+        self.total_keys >= UNLIMITED_TOTAL_KEYS
+    }
+
+    /// The identifier form patterns are matched against, which is always
+    /// the short key ID with `--collide-short-id`.
+    pub fn match_id_form(&self) -> IdForm {
+        if self.collide_short_id.is_some() {
+            IdForm::Short
+        } else {
+            self.id_form
+        }
+    }
+
+    /// The patterns searched for in place of the built-ins: `--pattern`, or
+    /// just the ID with `--collide-short-id`.
+    pub fn pattern_list(&self) -> Option<Vec<String>> {
+        match &self.collide_short_id {
+            Some(id) => Some(vec![id.clone()]),
+            None => self.patterns.clone(),
+        }
+    }
+
+    /// `--max-matches`, which a `--collide-short-id` run defaults to 1.
+    pub fn match_limit(&self) -> Option<usize> {
+        self.max_matches
+            .or(self.collide_short_id.as_ref().map(|_| 1))
+    }
+
+    /// Whether stdout carries a machine-read stream (keys with `--stdout`,
+    /// progress objects with `--progress-format json`), so notices meant
+    /// for people go to stderr instead.
+    pub fn stdout_reserved(&self) -> bool {
+        self.stdout || self.progress_format == ProgressFormat::Json
+    }
+
+    pub fn key_layout(&self) -> KeyLayout {
+        if self.fast_insecure {
+            KeyLayout::PrimaryOnly
+        } else if self.best_practice {
+            KeyLayout::BestPractice(self.encryption_usage)
+        } else if self.monolithic {
+            KeyLayout::Monolithic(self.encryption_usage)
+        } else {
+            KeyLayout::Standard(self.encryption_usage)
+        }
+    }
+
+    /// The algorithms every key is generated with: RSA of `rsa_bits`, or
+    /// by default Curve25519, except that `monolithic` keys need RSA and
+    /// default to 3072 bits. An unsupported `rsa_bits` falls back to 3072
+    /// here; [`Config::validate`] reports it.
+    pub fn cipher_suite(&self) -> CipherSuite {
+        match self.rsa_bits {
+            Some(bits) => rsa_suite(bits).unwrap_or(CipherSuite::RSA3k),
+            None if self.monolithic => CipherSuite::RSA3k,
+            None => CipherSuite::Cv25519,
+        }
+    }
+
+    /// The user IDs keys are mined for: each of `identities`, or the one
+    /// from `user_id`.
+    pub fn user_ids(&self) -> Vec<String> {
+        if self.identities.is_empty() {
+            vec![self.user_id()]
+        } else {
+            self.identities.clone()
+        }
+    }
+
+    /// The UserID every generated key carries, in the traditional
+    /// `Name (comment) <email>` form when a comment is set. Test keys from
+    /// `--fast-insecure` always say so in the comment.
+    pub fn user_id(&self) -> String {
+        let comment = if self.fast_insecure {
+            Some(FAST_INSECURE_COMMENT.to_string())
+        } else {
+            self.comment.clone()
+        };
+        match &comment {
+            Some(comment) => format!("{} ({}) <{}>", self.name, comment, self.email),
+            None => format!("{} <{}>", self.name, self.email),
+        }
+    }
+
+    /// Settings that are allowed but worth a caution, for the caller to
+    /// show and audit before mining starts.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.fast_insecure {
+            warnings.push(format!(
+                "--fast-insecure generates throwaway TEST keys with no encryption subkey, \
+                 labelled \"{}\". Do not use them for anything real.",
+                FAST_INSECURE_COMMENT
+            ));
+        }
+        if self.monolithic {
+            warnings.push(
+                "--monolithic uses one key for signing and encryption. A compromise of it \
+                 exposes both, and it can't be rotated without replacing the whole identity."
+                    .to_string(),
+            );
+        }
+        if self.cipher_suite() != CipherSuite::Cv25519 {
+            warnings.push(
+                "generating RSA keys is orders of magnitude slower than Curve25519, so expect \
+                 far fewer keys/s, and larger sizes slower still."
+                    .to_string(),
+            );
+        }
+        warnings
+    }
+
+    /// Checks the whole config for invalid values and conflicting options,
+    /// collecting every problem found rather than stopping at the first.
+    /// Checks that depend on an option being valid are skipped when it
+    /// isn't. Library callers building a `Config` by hand should call this
+    /// before mining.
+    pub fn validate(&self) -> Result<(), MinerError> {
+        let mut problems = Vec::new();
+        let mut check = |result: Result<(), String>| {
+            if let Err(problem) = result {
+                problems.push(problem);
+            }
+        };
+
+        if self.identities.is_empty() {
+            if self.name.is_empty() || self.email.is_empty() {
+                check(Err("Name and email must not be empty".to_string()));
+            }
+        } else {
+            check(self.validate_identities());
+        }
+        if self.total_keys == 0 {
+            check(Err(
+                "total_keys must be at least 1, or unlimited to run until stopped".to_string(),
+            ));
+        }
+        if self.threads == Some(0) {
+            check(Err("--threads must be at least 1".to_string()));
+        }
+        if self.max_matches == Some(0) {
+            check(Err("--max-matches must be at least 1".to_string()));
+        }
+        if self.min_rate == Some(0) {
+            check(Err("--min-rate must be at least 1".to_string()));
+        }
+        // A zero-capacity channel would make every save a rendezvous with
+        // the writer.
+        if self.save_queue_depth == 0 {
+            check(Err("--save-queue-depth must be at least 1".to_string()));
+        }
+        // Shards are numbered by `index / max_files_per_dir`.
+        if self.max_files_per_dir == Some(0) {
+            check(Err("--max-files-per-dir must be at least 1".to_string()));
+        }
+        if !self.stdout && self.archive.is_none() && self.export_dir.is_file() {
+            check(Err(format!(
+                "Export directory {} is a file",
+                self.export_dir.display()
+            )));
+        }
+
+        check(self.validate_patterns());
+
+        if self.fast_insecure && (self.best_practice || self.match_key != MatchKey::Primary) {
+            check(Err(
+                "--fast-insecure keys have no subkeys, so can't be combined with \
+                 --best-practice or --match-key"
+                    .to_string(),
+            ));
+        }
+        if self.monolithic
+            && (self.fast_insecure
+                || self.best_practice
+                || self.auth_subkey
+                || self.match_key != MatchKey::Primary)
+        {
+            check(Err(
+                "--monolithic keys have no subkeys, so can't be combined with --best-practice, \
+                 --auth-subkey, --match-key or --fast-insecure"
+                    .to_string(),
+            ));
+        }
+        if let Some(bits) = self.rsa_bits {
+            check(rsa_suite(bits).map(drop));
+        }
+        if self.x509_out && self.cipher_suite() != CipherSuite::Cv25519 {
+            check(Err(
+                "--x509-out exports Ed25519 keys, so can't be used with the RSA keys of \
+                 --monolithic or --rsa-bits"
+                    .to_string(),
+            ));
+        }
+        if self.match_key == MatchKey::Signing && !self.best_practice {
+            check(Err(
+                "--match-key signing needs --best-practice, which adds the signing subkey"
+                    .to_string(),
+            ));
+        }
+
+        if let Some(hash) = &self.signature_hash {
+            check(parse_hash(hash).map(drop));
+        }
+
+        if self.min_score.is_some() {
+            check(Scorer::from_config(self).map(drop));
+        } else if self.score_rules.is_some() {
+            check(Err("--score-rules requires --min-score".to_string()));
+        }
+
+        if let Some(schedule) = &self.run_between {
+            check(schedule.parse::<Schedule>().map(drop));
+        }
+
+        if self.profile && !cfg!(feature = "profile") {
+            check(Err(
+                "--profile requires building with --features profile".to_string()
+            ));
+        }
+
+        if self.qr && !cfg!(feature = "qr") {
+            check(Err("--qr requires building with --features qr".to_string()));
+        }
+
+        if self.x509_out {
+            if !cfg!(feature = "x509") {
+                check(Err(
+                    "--x509-out requires building with --features x509".to_string()
+                ));
+            }
+            if self.stdout {
+                check(Err(
+                    "--x509-out writes files and can't be used with --stdout".to_string(),
+                ));
+            }
+        }
+
+        if self.ssh_out.is_some() {
+            if !cfg!(feature = "ssh") {
+                check(Err(
+                    "--ssh-out requires building with --features ssh".to_string()
+                ));
+            }
+            if !self.auth_subkey {
+                check(Err(
+                    "--ssh-out exports the authentication subkey, so needs --auth-subkey"
+                        .to_string(),
+                ));
+            }
+            if self.stdout {
+                check(Err(
+                    "--ssh-out writes files and can't be used with --stdout".to_string(),
+                ));
+            }
+        }
+
+        if self
+            .prefer_common
+            .is_some_and(|factor| !(factor >= 1.0 && factor.is_finite()))
+        {
+            check(Err("--prefer-common must be at least 1".to_string()));
+        }
+
+        if self
+            .max_attempts_per_match
+            .is_some_and(|factor| !(factor >= 1.0 && factor.is_finite()))
+        {
+            check(Err(
+                "--max-attempts-per-match must be at least 1".to_string()
+            ));
+        }
+
+        if !(0.0..=1.0).contains(&self.max_error_rate) {
+            check(Err("--max-error-rate must be between 0 and 1".to_string()));
+        }
+
+        if self.on_low_rate == LowRateAction::Abort && self.min_rate.is_none() {
+            check(Err("--on-low-rate abort needs --min-rate".to_string()));
+        }
+
+        if let Some(archive) = &self.archive {
+            if !cfg!(feature = "archive") {
+                check(Err(
+                    "--archive requires building with --features archive".to_string()
+                ));
+            }
+            if !archive
+                .extension()
+                .is_some_and(|ext| ext == "tar" || ext == "zip")
+            {
+                check(Err(format!(
+                    "--archive {} must end in .tar or .zip",
+                    archive.display()
+                )));
+            }
+            if self.stdout
+                || self.x509_out
+                || self.ssh_out.is_some()
+                || self.max_files_per_dir.is_some()
+                || self.fallback_dir.is_some()
+            {
+                check(Err(
+                    "--archive can't be combined with --stdout, --x509-out, --ssh-out, \
+                     --max-files-per-dir or --fallback-dir"
+                        .to_string(),
+                ));
+            }
+        }
+
+        if let Some(url) = &self.webhook {
+            if !cfg!(feature = "webhook") {
+                check(Err(
+                    "--webhook requires building with --features webhook".to_string()
+                ));
+            }
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                check(Err(format!("--webhook {:?} is not an http(s) URL", url)));
+            }
+        }
+
+        if let Some(watts) = self.watts {
+            if !(watts.is_finite() && watts > 0.0) {
+                check(Err(format!("--watts must be positive, got {}", watts)));
+            }
+        }
+        if let Some(price) = self.price_per_kwh {
+            if !(price.is_finite() && price >= 0.0) {
+                check(Err(format!(
+                    "--price-per-kwh can't be negative, got {}",
+                    price
+                )));
+            }
+            if self.watts.is_none() {
+                check(Err("--price-per-kwh needs --watts".to_string()));
+            }
+        }
+
+        if self.serve.is_some()
+            && (self.stdout
+                || self.archive.is_some()
+                || self.state_file.is_some()
+                || self.coordinator.is_some()
+                || self.mask.is_some()
+                || self.fingerprint_set.is_some()
+                || self.whole_fingerprint_predicate.is_some()
+                || !self.pattern_groups.is_empty())
+        {
+            check(Err(
+                "--serve mines the patterns each request sends into the export directory, so \
+                 can't be combined with --stdout, --archive, --state, --coordinator, --mask, \
+                 --fingerprint-set, --whole-fingerprint-predicate or pattern_groups"
+                    .to_string(),
+            ));
+        }
+
+        if let Some(url) = &self.coordinator {
+            if !cfg!(feature = "coordinator") {
+                check(Err(
+                    "--coordinator requires building with --features coordinator".to_string(),
+                ));
+            }
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                check(Err(format!(
+                    "--coordinator {:?} is not an http(s) URL",
+                    url
+                )));
+            }
+        }
+
+        // Any of these would make the UserID ambiguous to parse back into
+        // name, comment and email.
+        if let Some(comment) = &self.comment {
+            if comment.contains(['(', ')', '<', '>']) || comment.chars().any(char::is_control) {
+                check(Err(
+                    "--comment must be a single line without parentheses or angle brackets"
+                        .to_string(),
+                ));
+            }
+        }
+
+        if let Some(comment) = &self.armor_comment {
+            // A line break would end the header early and corrupt the armor.
+            if comment.chars().any(char::is_control) {
+                check(Err("--armor-comment must be a single line".to_string()));
+            }
+        }
+
+        if let Some(id) = &self.collide_short_id {
+            if id.len() != IdForm::Short.hex_len() || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+                check(Err(format!(
+                    "--collide-short-id {:?} is not an 8-digit hex short key ID",
+                    id
+                )));
+            }
+            if self.patterns.is_some()
+                || self.id_form != IdForm::Fingerprint
+                || self.match_anywhere
+                || self.group.is_some()
+                || self.transform != TransformKind::None
+                || self.mask.is_some()
+                || !self.dont_care.is_empty()
+                || !self.pattern_groups.is_empty()
+                || self.fingerprint_set.is_some()
+                || self.whole_fingerprint_predicate.is_some()
+                || self.min_pattern_len.is_some()
+                || self.max_pattern_len.is_some()
+            {
+                check(Err(
+                    "--collide-short-id matches the whole short key ID exactly, so can't be \
+                     combined with --pattern, --id-form, --anywhere, --group, \
+                     --transform, --mask, --dont-care or pattern lengths"
+                        .to_string(),
+                ));
+            }
+        }
+
+        if self.stop_when_exhausted
+            && (self.mask.is_some()
+                || !self.dont_care.is_empty()
+                || self.whole_fingerprint_predicate.is_some())
+        {
+            check(Err(
+                "--stop-when-exhausted needs a finite set of patterns or fingerprints, not \
+                 --mask, --dont-care or --whole-fingerprint-predicate"
+                    .to_string(),
+            ));
+        }
+
+        if let Some(path) = &self.exclude_keyring {
+            if !self.dedup {
+                check(Err(
+                    "--exclude-keyring works through the dedup set, so can't be combined with \
+                     --no-dedup"
+                        .to_string(),
+                ));
+            }
+            if !path.is_file() {
+                check(Err(format!(
+                    "--exclude-keyring {} is not a file",
+                    path.display()
+                )));
+            }
+        }
+
+        if let Some(template) = &self.log_template {
+            check(check_log_template(template));
+        }
+
+        if let Some(width) = self.armor_width {
+            if !ARMOR_WIDTHS.contains(&width) {
+                check(Err(format!(
+                    "--armor-width must be {} to {}, got {}",
+                    ARMOR_WIDTHS.start(),
+                    ARMOR_WIDTHS.end(),
+                    width
+                )));
+            }
+        }
+
+        if self.stdout_private && !self.stdout {
+            check(Err("--stdout-private requires --stdout".to_string()));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(MinerError::InvalidConfig(problems))
+        }
+    }
+
+    fn validate_identities(&self) -> Result<(), String> {
+        let mut seen = HashSet::new();
+        for identity in &self.identities {
+            let (name, email) = identity
+                .strip_suffix('>')
+                .and_then(|rest| rest.split_once('<'))
+                .ok_or_else(|| {
+                    format!("Identity {:?} is not of the form Name <email>", identity)
+                })?;
+            if name.trim().is_empty() || email.is_empty() {
+                return Err(format!("Identity {:?} needs a name and an email", identity));
+            }
+            if !seen.insert(identity) {
+                return Err(format!("Identity {:?} is listed twice", identity));
+            }
+        }
+        // Both of these are per run, not per identity.
+        if self.comment.is_some() || self.fast_insecure {
+            return Err(
+                "--identities can't be combined with --comment or --fast-insecure; write any \
+                 comment into the identities instead"
+                    .to_string(),
+            );
+        }
+        if self.x509_out {
+            return Err(
+                "--x509-out certifies the single name and email, so can't be used with \
+                 --identities"
+                    .to_string(),
+            );
+        }
+        Ok(())
+    }
+
+    /// The checks on what is searched for. These build on each other, so
+    /// stop at the first problem.
+    fn validate_patterns(&self) -> Result<(), String> {
+        let mut width = PATTERN_LEN;
+        if let Some(mask) = &self.mask {
+            let transform = build_transform(self.transform);
+            width = MaskMatcher::new(
+                mask,
+                self.match_anywhere,
+                transform.output_len(self.id_form.hex_len()),
+                &*transform,
+            )?
+            .width();
+            if self.prefer_common.is_some() {
+                return Err("--prefer-common only applies to pattern sets, not --mask".to_string());
+            }
+            if self.min_pattern_len.is_some() || self.max_pattern_len.is_some() {
+                return Err(
+                    "--min-pattern-length and --max-pattern-length only apply to pattern sets, \
+                     not --mask"
+                        .to_string(),
+                );
+            }
+        }
+        if let (Some(min), Some(max)) = (self.min_pattern_len, self.max_pattern_len) {
+            if min > max {
+                return Err(format!(
+                    "--min-pattern-length {} is above --max-pattern-length {}",
+                    min, max
+                ));
+            }
+        }
+
+        if let Some(group) = self.group {
+            if self.match_anywhere {
+                return Err(
+                    "--group sets a fixed position, so can't be used with --anywhere".to_string(),
+                );
+            }
+            let id_len = build_transform(self.transform).output_len(self.id_form.hex_len());
+            let groups = id_len / GROUP_LEN;
+            let last = (id_len - width) / GROUP_LEN + 1;
+            if group == 0 || group > last {
+                return Err(format!(
+                    "--group {} is out of range: the identifier has {} groups and a {}-digit \
+                     window fits from group 1 to {}",
+                    group, groups, width, last
+                ));
+            }
+        }
+
+        if self.mask.is_some() && self.patterns.is_some() {
+            return Err("--pattern and --mask are mutually exclusive".to_string());
+        }
+        if !self.pattern_groups.is_empty() {
+            if self.mask.is_some() || self.patterns.is_some() {
+                return Err("pattern_groups can't be combined with --pattern or --mask".to_string());
+            }
+            let mut names = HashSet::new();
+            for group in &self.pattern_groups {
+                if !names.insert(group.name.as_str()) {
+                    return Err(format!("Pattern group {:?} is defined twice", group.name));
+                }
+                if group.patterns.is_empty() || group.max_matches == 0 {
+                    return Err(format!(
+                        "Pattern group {:?} needs at least one pattern and a max_matches of 1 \
+                         or more",
+                        group.name
+                    ));
+                }
+            }
+        }
+        if self.transform.changes_alphabet() {
+            // The built-in words are hex and mostly not valid in other
+            // alphabets, and --dont-care packs hex nibbles.
+            if self.mask.is_none() && self.patterns.is_none() && self.pattern_groups.is_empty() {
+                return Err("--transform needs --pattern or --mask in the new alphabet".to_string());
+            }
+            if !self.dont_care.is_empty() {
+                return Err("--dont-care only works on hex, not with --transform".to_string());
+            }
+        }
+        if let Some(predicate) = &self.whole_fingerprint_predicate {
+            if self.mask.is_some()
+                || self.patterns.is_some()
+                || self.fingerprint_set.is_some()
+                || !self.pattern_groups.is_empty()
+                || !self.dont_care.is_empty()
+                || self.prefer_common.is_some()
+                || self.min_pattern_len.is_some()
+                || self.max_pattern_len.is_some()
+            {
+                return Err(
+                    "--whole-fingerprint-predicate replaces patterns, so can't be combined with \
+                     --pattern, --mask, --fingerprint-set, pattern_groups, --dont-care, \
+                     --prefer-common or pattern lengths"
+                        .to_string(),
+                );
+            }
+            if self.match_anywhere
+                || self.group.is_some()
+                || self.id_form != IdForm::Fingerprint
+                || self.transform != TransformKind::None
+            {
+                return Err(
+                    "--whole-fingerprint-predicate looks at the whole hex fingerprint, so \
+                     --anywhere, --group, --id-form and --transform don't apply"
+                        .to_string(),
+                );
+            }
+            predicate.parse::<WholePredicate>()?;
+        } else if let Some(path) = &self.fingerprint_set {
+            if self.mask.is_some()
+                || self.patterns.is_some()
+                || !self.pattern_groups.is_empty()
+                || !self.dont_care.is_empty()
+                || self.prefer_common.is_some()
+                || self.min_pattern_len.is_some()
+                || self.max_pattern_len.is_some()
+            {
+                return Err(
+                    "--fingerprint-set replaces patterns, so can't be combined with --pattern, \
+                     --mask, pattern_groups, --dont-care, --prefer-common or pattern lengths"
+                        .to_string(),
+                );
+            }
+            if self.match_anywhere || self.group.is_some() {
+                return Err(
+                    "--fingerprint-set compares whole fingerprints, so --anywhere and --group \
+                     don't apply"
+                        .to_string(),
+                );
+            }
+            if self.id_form != IdForm::Fingerprint || self.transform != TransformKind::None {
+                return Err(
+                    "--fingerprint-set matches full hex fingerprints, so needs the default \
+                     --id-form and no --transform"
+                        .to_string(),
+                );
+            }
+            FingerprintSet::load(path)?;
+        } else if self.mask.is_none() {
+            PatternCache::from_config(self)?;
+        }
+
+        if !self.dont_care.is_empty() {
+            if self.mask.is_some() || self.match_anywhere {
+                return Err(
+                    "--dont-care applies to the fixed window only, not --mask or --anywhere"
+                        .to_string(),
+                );
+            }
+            if let Some(&position) = self.dont_care.iter().find(|&&p| p >= PATTERN_LEN) {
+                return Err(format!(
+                    "--dont-care position {} is outside the window (0-{})",
+                    position,
+                    PATTERN_LEN - 1
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn usage(program: &str) -> String {
+    let mut usage = format!(
+        "Usage: {0} \"Your Name\" \"your.email@example.com\" [total_keys] [options]\n       \
+         {0} [total_keys] [options]  (with {1} and {2} set)\n       \
+         {0} --identities <file> [total_keys] [options]\n       \
+         {0} init\n       \
+         {0} coordinate <address:port> <matches>\n       \
+         {0} verify [export_dir]\n       \
+         {0} check <key_file> <pattern> [matching options]\n       \
+         {0} info\n       \
+         {0} --version [--json]\n       \
+         {0} list-patterns [--config <file>] [--pattern <hex>]...\n\nOptions:",
+        program, NAME_ENV, EMAIL_ENV
+    );
+    for (flag, help) in OPTIONS {
+        usage.push_str(&format!("\n  {:<24} {}", flag, help));
+    }
+    usage
+}
+
+/// Parses an octal permission mode such as `700` or `0o700`.
+fn parse_mode(value: &str, flag: &str) -> Result<u32, String> {
+    let digits = value.strip_prefix("0o").unwrap_or(value);
+    u32::from_str_radix(digits, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| format!("{} expects an octal mode like 700, got {:?}", flag, value))
+}
+
+/// Takes the value following `flag`, failing if the arguments ran out.
+fn next_value<'a>(
+    args: &mut impl Iterator<Item = &'a String>,
+    flag: &str,
+) -> Result<&'a String, String> {
+    args.next()
+        .ok_or_else(|| format!("{} expects a value", flag))
+}
+
+/// Takes and parses the value following `flag`.
+fn parse_value<'a, T: FromStr>(
+    args: &mut impl Iterator<Item = &'a String>,
+    flag: &str,
+) -> Result<T, String> {
+    let value = next_value(args, flag)?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", flag, value))
+}
+
+/// Splits a comma-separated algorithm list, checking every name with `parse`.
+fn parse_list<T>(
+    value: &str,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> Result<Vec<String>, String> {
+    let names: Vec<String> = value
+        .split(',')
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+    if names.is_empty() {
+        return Err(format!("Empty algorithm list: {:?}", value));
+    }
+    for name in &names {
+        parse(name)?;
+    }
+    Ok(names)
+}
+
+/// Reads one `Name <email>` user ID per line, skipping blank lines and `#`
+/// comments.
+pub fn load_identities(path: &str) -> Result<Vec<String>, String> {
+    let text =
+        fs::read_to_string(path).map_err(|e| format!("Cannot read identities {}: {}", path, e))?;
+    let identities: Vec<String> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect();
+    if identities.is_empty() {
+        return Err(format!("Identities file {} lists no identities", path));
+    }
+    Ok(identities)
+}
+
+/// Loads a config file, leaving unset fields at their defaults.
+pub fn load_config(path: &str) -> Result<Config, String> {
+    let text =
+        fs::read_to_string(path).map_err(|e| format!("Cannot read config {}: {}", path, e))?;
+    toml::from_str(&text).map_err(|e| format!("Invalid config {}: {}", path, e))
+}
+
+/// Builds the effective config. Settings are layered lowest to highest:
+/// defaults, the `--config` file, the name/email environment variables,
+/// then the command line. Only the syntax is checked here; call
+/// [`Config::validate`] on the result before using it.
+pub fn parse_args(args: &[String]) -> Result<Config, String> {
+    let program = args
+        .first()
+        .map(String::as_str)
+        .unwrap_or("vanity-pgp-miner");
+    let mut config = match args.iter().position(|arg| arg == "--config") {
+        Some(index) => load_config(
+            args.get(index + 1)
+                .ok_or_else(|| "--config expects a value".to_string())?,
+        )?,
+        None => Config::default(),
+    };
+    let mut positional = Vec::new();
+
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // Already loaded above, before any flag could be overridden.
+            "--config" => {
+                next_value(&mut args, arg)?;
+            }
+            "--dump-config" => config.dump_config = true,
+            "--debug-dump-first" => config.debug_dump_first = true,
+            "--profile" => config.profile = true,
+            // Deliberately missing from OPTIONS: only for testing the miner
+            // itself, e.g. backpressure and end-to-end output checks.
+            "--fast-insecure" => config.fast_insecure = true,
+            "--debug-save-delay-ms" => {
+                config.debug_save_delay = Some(Duration::from_millis(parse_value(&mut args, arg)?))
+            }
+            "--anywhere" => config.match_anywhere = true,
+            "--group" => config.group = Some(parse_value(&mut args, arg)?),
+            "--best-practice" => config.best_practice = true,
+            "--monolithic" => config.monolithic = true,
+            "--rsa-bits" => config.rsa_bits = Some(parse_value(&mut args, arg)?),
+            "--encryption-usage" => {
+                config.encryption_usage = next_value(&mut args, arg)?.parse()?
+            }
+            "--match-key" => config.match_key = next_value(&mut args, arg)?.parse()?,
+            "--transform" => config.transform = next_value(&mut args, arg)?.parse()?,
+            "--reverse" => config.transform = TransformKind::Reverse,
+            "--id-form" => config.id_form = next_value(&mut args, arg)?.parse()?,
+            "--prefer-common" => config.prefer_common = Some(parse_value(&mut args, arg)?),
+            "--min-pattern-length" => config.min_pattern_len = Some(parse_value(&mut args, arg)?),
+            "--max-pattern-length" => config.max_pattern_len = Some(parse_value(&mut args, arg)?),
+            "--dont-care" => {
+                config.dont_care = next_value(&mut args, arg)?
+                    .split(',')
+                    .map(|position| {
+                        position
+                            .trim()
+                            .parse()
+                            .map_err(|_| format!("Invalid --dont-care position: {}", position))
+                    })
+                    .collect::<Result<_, _>>()?;
+            }
+            "--pattern" => config
+                .patterns
+                .get_or_insert_with(Vec::new)
+                .push(next_value(&mut args, arg)?.clone()),
+            "--mask" => config.mask = Some(next_value(&mut args, arg)?.to_ascii_uppercase()),
+            "--fingerprint-set" => {
+                config.fingerprint_set = Some(next_value(&mut args, arg)?.into())
+            }
+            "--whole-fingerprint-predicate" => {
+                config.whole_fingerprint_predicate =
+                    Some(next_value(&mut args, arg)?.to_ascii_lowercase())
+            }
+            "--stop-when-exhausted" => config.stop_when_exhausted = true,
+            "--collide-short-id" => {
+                config.collide_short_id = Some(next_value(&mut args, arg)?.to_ascii_uppercase())
+            }
+            "--max-matches" => config.max_matches = Some(parse_value(&mut args, arg)?),
+            "--once" => config.max_matches = Some(1),
+            "--min-score" => config.min_score = Some(parse_value(&mut args, arg)?),
+            "--score-rules" => config.score_rules = Some(next_value(&mut args, arg)?.into()),
+            "--no-odds-warning" => config.odds_warning = false,
+            "--no-dedup" => config.dedup = false,
+            "--exclude-keyring" => {
+                config.exclude_keyring = Some(next_value(&mut args, arg)?.into())
+            }
+            "--identities" => config.identities = load_identities(next_value(&mut args, arg)?)?,
+            "--comment" => config.comment = Some(next_value(&mut args, arg)?.clone()),
+            "--no-revocation" => config.revocation = false,
+            "--x509-out" => config.x509_out = true,
+            "--qr" => config.qr = true,
+            "--gpg-import" => config.gpg_import = true,
+            "--auth-subkey" => config.auth_subkey = true,
+            "--ssh-out" => config.ssh_out = Some(next_value(&mut args, arg)?.into()),
+            "--max-attempts-per-match" => {
+                config.max_attempts_per_match = Some(parse_value(&mut args, arg)?)
+            }
+            "--max-error-rate" => config.max_error_rate = parse_value(&mut args, arg)?,
+            "--min-rate" => config.min_rate = Some(parse_value(&mut args, arg)?),
+            "--on-low-rate" => config.on_low_rate = next_value(&mut args, arg)?.parse()?,
+            "--archive" => config.archive = Some(next_value(&mut args, arg)?.into()),
+            "--webhook" => config.webhook = Some(next_value(&mut args, arg)?.clone()),
+            "--watts" => config.watts = Some(parse_value(&mut args, arg)?),
+            "--price-per-kwh" => config.price_per_kwh = Some(parse_value(&mut args, arg)?),
+            "--serve" => config.serve = Some(next_value(&mut args, arg)?.clone()),
+            "--coordinator" => config.coordinator = Some(next_value(&mut args, arg)?.clone()),
+            "--threads" => config.threads = Some(parse_value(&mut args, arg)?),
+            "--pin-threads" => config.pin_threads = true,
+            "--run-between" => config.run_between = Some(next_value(&mut args, arg)?.clone()),
+            "--delay-start" => config.delay_start = Some(parse_value(&mut args, arg)?),
+            "--warmup" => config.warmup = true,
+            "--compliance" => config.compliance = true,
+            "--stdout" => config.stdout = true,
+            "--stdout-private" => config.stdout_private = true,
+            "--stats-file" => config.stats_file = Some(next_value(&mut args, arg)?.into()),
+            "--audit-log" => config.audit_log = Some(next_value(&mut args, arg)?.into()),
+            "--plain-progress" => config.progress_format = ProgressFormat::Plain,
+            "--progress-format" => {
+                config.progress_format = next_value(&mut args, arg)?.parse()?;
+            }
+            "--symmetric-prefs" => {
+                config.symmetric_prefs =
+                    Some(parse_list(next_value(&mut args, arg)?, parse_symmetric)?)
+            }
+            "--compression-prefs" => {
+                config.compression_prefs =
+                    Some(parse_list(next_value(&mut args, arg)?, parse_compression)?)
+            }
+            "--signature-hash" => {
+                config.signature_hash = Some(next_value(&mut args, arg)?.to_ascii_lowercase())
+            }
+            "--armor-comment" => config.armor_comment = Some(next_value(&mut args, arg)?.clone()),
+            "--log-template" => config.log_template = Some(next_value(&mut args, arg)?.clone()),
+            "--armor-width" => config.armor_width = Some(parse_value(&mut args, arg)?),
+            "--state" => config.state_file = Some(next_value(&mut args, arg)?.into()),
+            "--save-queue-depth" => config.save_queue_depth = parse_value(&mut args, arg)?,
+            "--dir-mode" => config.dir_mode = parse_mode(next_value(&mut args, arg)?, arg)?,
+            "--file-mode" => config.file_mode = parse_mode(next_value(&mut args, arg)?, arg)?,
+            "--name-by" => config.name_by = next_value(&mut args, arg)?.parse()?,
+            "--hex-case" => config.hex_case = next_value(&mut args, arg)?.parse()?,
+            "--fallback-dir" => config.fallback_dir = Some(next_value(&mut args, arg)?.into()),
+            "--max-files-per-dir" => config.max_files_per_dir = Some(parse_value(&mut args, arg)?),
+            flag if flag.starts_with("--") => {
+                return Err(format!("Unknown option: {}\n{}", flag, usage(program)))
+            }
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    // Name and email come from the positionals when both are given, and
+    // otherwise from the environment or config file, leaving an optional
+    // lone total_keys.
+    let total = match positional.len() {
+        2 | 3 => {
+            config.name = positional[0].clone();
+            config.email = positional[1].clone();
+            positional.get(2)
+        }
+        0 | 1 => {
+            if let Ok(name) = std::env::var(NAME_ENV) {
+                config.name = name;
+            }
+            if let Ok(email) = std::env::var(EMAIL_ENV) {
+                config.email = email;
+            }
+            positional.first()
+        }
+        _ => return Err(usage(program)),
+    };
+
+    if config.identities.is_empty() && (config.name.is_empty() || config.email.is_empty()) {
+        return Err(format!(
+            "Name and email must be given as arguments, via {} and {}, or in a config file\n{}",
+            NAME_ENV,
+            EMAIL_ENV,
+            usage(program)
+        ));
+    }
+
+    // About 2^32 keys are needed, so a collision test runs until it finds
+    // one unless told otherwise.
+    if config.collide_short_id.is_some() && total.is_none() {
+        config.total_keys = UNLIMITED_TOTAL_KEYS;
+    }
+    if let Some(total) = total {
+        config.total_keys = match total.as_str() {
+            "unlimited" => UNLIMITED_TOTAL_KEYS,
+            total => total.replace('_', "").parse().map_err(|_| {
+                format!(
+                    "Invalid total_keys {:?}, expected a number of keys or unlimited",
+                    total
+                )
+            })?,
+        };
+    }
+    config.total_keys = config.total_keys.min(UNLIMITED_TOTAL_KEYS);
+
+    Ok(config)
+}
+
+/// The command line mining for `Test <test@example.com>` with `flags`, for
+/// tests to hand to [`parse_args`].
+#[cfg(test)]
+pub(crate) fn test_args(flags: &[&str]) -> Vec<String> {
+    ["vanity-pgp-miner", "Test", "test@example.com"]
+        .iter()
+        .chain(flags)
+        .map(|arg| arg.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What `validate` finds wrong with a command line mining for
+    /// `Test <test@example.com>` with `flags`.
+    fn problems(flags: &[&str]) -> Vec<String> {
+        match parse_args(&test_args(flags)).unwrap().validate() {
+            Ok(()) => Vec::new(),
+            Err(MinerError::InvalidConfig(problems)) => problems,
+        }
+    }
+
+    #[test]
+    fn plain_command_line_is_valid() {
+        assert_eq!(problems(&[]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn name_and_email_are_required() {
+        let problems = match Config::default().validate() {
+            Ok(()) => Vec::new(),
+            Err(MinerError::InvalidConfig(problems)) => problems,
+        };
+        assert!(problems.contains(&"Name and email must not be empty".to_string()));
+    }
+
+    #[test]
+    fn zero_total_keys_is_refused() {
+        assert_eq!(
+            problems(&["0"]),
+            ["total_keys must be at least 1, or unlimited to run until stopped"]
+        );
+    }
+
+    #[test]
+    fn zero_counts_are_refused() {
+        for flag in [
+            "--threads",
+            "--max-matches",
+            "--min-rate",
+            "--save-queue-depth",
+            "--max-files-per-dir",
+        ] {
+            assert_eq!(
+                problems(&[flag, "0"]),
+                [format!("{} must be at least 1", flag)]
+            );
+        }
+    }
+
+    #[test]
+    fn prefer_common_must_be_a_finite_factor_of_at_least_one() {
+        for factor in ["0.5", "0", "-2", "inf", "NaN"] {
+            assert_eq!(
+                problems(&["--prefer-common", factor]),
+                ["--prefer-common must be at least 1"],
+                "--prefer-common {}",
+                factor
+            );
+        }
+        assert_eq!(problems(&["--prefer-common", "1"]), Vec::<String>::new());
+    }
+
+    #[test]
+    fn collide_short_id_refuses_pattern_in_either_order() {
+        for flags in [
+            ["--collide-short-id", "0123abcd", "--pattern", "ABCD"],
+            ["--pattern", "ABCD", "--collide-short-id", "0123abcd"],
+        ] {
+            let problems = problems(&flags);
+            assert_eq!(problems.len(), 1, "{:?}: {:?}", flags, problems);
+            assert!(problems[0].starts_with("--collide-short-id matches the whole short key ID"));
+        }
+        assert_eq!(
+            problems(&["--collide-short-id", "0123abcd"]),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn collide_short_id_must_be_a_short_key_id() {
+        assert_eq!(
+            problems(&["--collide-short-id", "0123abc"]),
+            ["--collide-short-id \"0123ABC\" is not an 8-digit hex short key ID"]
+        );
+    }
+
+    #[test]
+    fn warnings_are_returned_for_the_caller_to_show() {
+        let warnings = |flags: &[&str]| parse_args(&test_args(flags)).unwrap().warnings();
+        assert!(warnings(&[]).is_empty());
+        assert_eq!(warnings(&["--fast-insecure"]).len(), 1);
+        assert_eq!(warnings(&["--rsa-bits", "4096"]).len(), 1);
+        let monolithic = warnings(&["--monolithic"]);
+        assert_eq!(monolithic.len(), 2, "{:?}", monolithic);
+        assert!(monolithic[0].starts_with("--monolithic"));
+    }
+
+    #[test]
+    fn comment_must_not_break_the_user_id() {
+        for comment in ["a (b)", "a)", "<a@b>", "a > b", "two\nlines"] {
+            assert_eq!(
+                problems(&["--comment", comment]),
+                ["--comment must be a single line without parentheses or angle brackets"],
+                "--comment {:?}",
+                comment
+            );
+        }
+        assert_eq!(problems(&["--comment", "work"]), Vec::<String>::new());
+
+        // A config file or a library caller gets the same check.
+        let config = Config {
+            name: "Test".to_string(),
+            email: "test@example.com".to_string(),
+            comment: Some("a (b)".to_string()),
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        assert_eq!(
+            problems(&["--threads", "0", "--max-matches", "0"]),
+            [
+                "--threads must be at least 1",
+                "--max-matches must be at least 1"
+            ]
+        );
+    }
+}
@@ -0,0 +1,321 @@
+//! `--coordinator` and the `coordinate` subcommand: several miners on
+//! different machines report to one coordinator, which adds up their
+//! counts, prints their matches, and tells them all to stop once the
+//! combined number of matches reaches its target.
+//!
+//! The protocol is deliberately tiny, so the coordinator needs nothing but
+//! std. Workers `POST /register?worker=ID` once and then
+//! `POST /report?worker=ID&checked=N&found=M` every few seconds, with one
+//! `FINGERPRINT PATTERN` line in the body per match since the last report.
+//! Both get `continue` or `stop` as the response body. `GET /status`
+//! returns the totals as JSON.
+
+use crate::miner::{MinerResult, Stats};
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::Ordering,
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Request bodies are a line per match; anything bigger is not a miner.
+const MAX_BODY: usize = 1 << 20;
+
+/// A miner's connection to the coordinator. Matches are queued to a
+/// background thread that sends them with the next report, so a slow or
+/// unreachable coordinator never holds up mining.
+pub struct Coordinator {
+    sender: Sender<String>,
+    handle: JoinHandle<()>,
+}
+
+impl Coordinator {
+    /// Registers with the coordinator at `url` and starts reporting. A
+    /// coordinator that can't be reached at startup is an error; once
+    /// running, failed reports are retried with the next one.
+    pub fn spawn(url: String, stats: Arc<Stats>) -> Result<Self, String> {
+        let client = Client {
+            agent: ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build(),
+            url: url.trim_end_matches('/').to_string(),
+            worker: worker_id(),
+        };
+        if client.post("register", &[], "")? {
+            return Err(format!(
+                "Coordinator {} has already stopped the search",
+                url
+            ));
+        }
+        eprintln!("Registered with coordinator {} as {}", url, client.worker);
+
+        let (sender, receiver) = mpsc::channel();
+        let handle = thread::spawn(move || client.run(receiver, &stats));
+        Ok(Self { sender, handle })
+    }
+
+    pub fn record(&self, result: &MinerResult) {
+        let _ = self
+            .sender
+            .send(format!("{} {}", result.fingerprint, result.pattern));
+    }
+
+    /// Sends the final counts and waits for the reporting thread.
+    pub fn finish(self) {
+        drop(self.sender);
+        let _ = self.handle.join();
+    }
+}
+
+struct Client {
+    agent: ureq::Agent,
+    url: String,
+    worker: String,
+}
+
+impl Client {
+    /// POSTs to `path` and returns whether the coordinator said to stop.
+    fn post(&self, path: &str, query: &[(&str, String)], body: &str) -> Result<bool, String> {
+        let mut request = self
+            .agent
+            .post(&format!("{}/{}", self.url, path))
+            .query("worker", &self.worker);
+        for (name, value) in query {
+            request = request.query(name, value);
+        }
+        let response = request
+            .send_string(body)
+            .map_err(|e| format!("Coordinator {} failed: {}", self.url, e))?
+            .into_string()
+            .map_err(|e| format!("Coordinator {} failed: {}", self.url, e))?;
+        Ok(response.trim() == "stop")
+    }
+
+    /// Reports every `REPORT_INTERVAL`, or as soon as the channel closes at
+    /// the end of the run. Matches that fail to send are kept for the next
+    /// attempt.
+    fn run(&self, receiver: Receiver<String>, stats: &Stats) {
+        let mut pending = Vec::new();
+        let mut last_report = Instant::now();
+        loop {
+            let closed = match receiver.recv_timeout(REPORT_INTERVAL) {
+                Ok(line) => {
+                    pending.push(line);
+                    false
+                }
+                Err(RecvTimeoutError::Timeout) => false,
+                Err(RecvTimeoutError::Disconnected) => true,
+            };
+            if !closed && last_report.elapsed() < REPORT_INTERVAL {
+                continue;
+            }
+            last_report = Instant::now();
+
+            let query = [
+                (
+                    "checked",
+                    stats.keys_checked.load(Ordering::Relaxed).to_string(),
+                ),
+                (
+                    "found",
+                    stats.keys_found.load(Ordering::Relaxed).to_string(),
+                ),
+            ];
+            let mut body = pending.join("\n");
+            body.push('\n');
+            match self.post("report", &query, &body) {
+                Ok(stop) => {
+                    pending.clear();
+                    if stop && !stats.stopped() {
+                        eprintln!("\nCoordinator {} reached its target, stopping", self.url);
+                        stats.stop();
+                    }
+                }
+                Err(e) => eprintln!("\n{}", e),
+            }
+            if closed {
+                break;
+            }
+        }
+    }
+}
+
+/// This machine's name and the process ID, restricted to characters that
+/// need no escaping in a query string.
+fn worker_id() -> String {
+    let host = std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .unwrap_or_else(|_| "miner".to_string());
+    let host: String = host
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_'))
+        .collect();
+    format!("{}-{}", host, std::process::id())
+}
+
+#[derive(Default)]
+struct WorkerCounts {
+    checked: usize,
+    found: usize,
+}
+
+/// The `coordinate` subcommand's state.
+struct Search {
+    target: usize,
+    workers: HashMap<String, WorkerCounts>,
+    /// Workers that have been answered `stop`.
+    stopped: HashSet<String>,
+}
+
+impl Search {
+    fn found(&self) -> usize {
+        self.workers.values().map(|counts| counts.found).sum()
+    }
+
+    fn checked(&self) -> usize {
+        self.workers.values().map(|counts| counts.checked).sum()
+    }
+
+    fn done(&self) -> bool {
+        self.found() >= self.target
+    }
+
+    /// Handles one request, returning the status code and body.
+    fn handle(&mut self, method: &str, path: &str, query: &str, body: &str) -> (u16, String) {
+        let param = |name: &str| {
+            query
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        };
+        let count = |name: &str| param(name).and_then(|value| value.parse().ok());
+
+        match (method, path) {
+            ("GET", "/status") => (
+                200,
+                format!(
+                    "{{\"workers\":{},\"checked\":{},\"found\":{},\"target\":{}}}",
+                    self.workers.len(),
+                    self.checked(),
+                    self.found(),
+                    self.target
+                ),
+            ),
+            ("POST", "/register") | ("POST", "/report") => {
+                let Some(worker) = param("worker") else {
+                    return (400, "missing worker".to_string());
+                };
+                if !self.workers.contains_key(&worker) {
+                    println!("Worker {} joined", worker);
+                }
+                let counts = self.workers.entry(worker.clone()).or_default();
+                if path == "/report" {
+                    counts.checked = count("checked").unwrap_or(counts.checked);
+                    counts.found = count("found").unwrap_or(counts.found);
+                    for line in body.lines().filter(|line| !line.trim().is_empty()) {
+                        println!("MATCH from {}: {}", worker, line.trim());
+                    }
+                    println!(
+                        "{} workers: checked {}, found {}/{}",
+                        self.workers.len(),
+                        self.checked(),
+                        self.found(),
+                        self.target
+                    );
+                }
+                if self.done() {
+                    self.stopped.insert(worker);
+                    (200, "stop".to_string())
+                } else {
+                    (200, "continue".to_string())
+                }
+            }
+            _ => (404, "not found".to_string()),
+        }
+    }
+}
+
+/// Runs a coordinator on `addr` until `target` matches have been reported
+/// across all workers and every worker has been told to stop. A worker
+/// that dies without reporting again keeps it running until interrupted.
+/// Requests are small and infrequent, so they are served one at a time.
+pub fn serve(addr: &str, target: usize) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    println!(
+        "Coordinating on {}, stopping workers after {} matches",
+        listener.local_addr()?,
+        target
+    );
+    let mut search = Search {
+        target,
+        workers: HashMap::new(),
+        stopped: HashSet::new(),
+    };
+    for stream in listener.incoming() {
+        if let Err(e) = stream.and_then(|stream| serve_one(stream, &mut search)) {
+            eprintln!("Coordinator request failed: {}", e);
+        }
+        if search.done() && search.stopped.len() == search.workers.len() {
+            println!(
+                "Target reached: {} matches in {} keys across {} workers",
+                search.found(),
+                search.checked(),
+                search.workers.len()
+            );
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn serve_one(stream: TcpStream, search: &mut Search) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default().to_string();
+    let (path, query) = target.split_once('?').unwrap_or((target.as_str(), ""));
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    if content_length > MAX_BODY {
+        return respond(&stream, 413, "too large");
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let (status, response) = search.handle(&method, path, query, &String::from_utf8_lossy(&body));
+    respond(&stream, status, &response)
+}
+
+fn respond(mut stream: &TcpStream, status: u16, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        if status == 200 { "OK" } else { "Error" },
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
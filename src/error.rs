@@ -0,0 +1,20 @@
+use std::fmt;
+
+/// Errors the library reports that callers may want to tell apart, as
+/// opposed to the I/O and OpenPGP failures passed through as-is.
+#[derive(Debug)]
+pub enum MinerError {
+    /// Everything [`Config::validate`](crate::Config::validate) found wrong
+    /// with a config, one message per problem.
+    InvalidConfig(Vec<String>),
+}
+
+impl fmt::Display for MinerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidConfig(problems) => write!(f, "{}", problems.join("\n")),
+        }
+    }
+}
+
+impl std::error::Error for MinerError {}
@@ -0,0 +1,1021 @@
+#[cfg(feature = "archive")]
+use crate::archive::Archive;
+use crate::{
+    audit,
+    config::{Config, NameBy},
+    manifest::{Manifest, ManifestEntry},
+    matcher::{visual_entropy, LOW_VISUAL_ENTROPY},
+    miner::{MinerResult, Stats},
+};
+use chrono::{SecondsFormat, Utc};
+#[cfg(feature = "archive")]
+use sequoia_openpgp::crypto::mem::Protected;
+use sequoia_openpgp::{
+    armor,
+    cert::CertParser,
+    packet::{Packet, Signature},
+    parse::Parse,
+    serialize::Marshal,
+    Cert, Error, Result,
+};
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::Ordering,
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+const BUFFER_SIZE: usize = 32768;
+const LOG_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const DISK_FULL_RETRY: Duration = Duration::from_secs(30);
+const SHORT_ID_LEN: usize = 8;
+
+/// The primary fingerprints of every cert in the keyring at `path`, as
+/// uppercase hex like `MinerResult::fingerprint`. Certs are parsed one at
+/// a time, so only the set is held in memory however large the keyring
+/// is. A cert that fails to parse is skipped with a warning rather than
+/// failing the run.
+pub fn keyring_fingerprints(path: &Path) -> Result<HashSet<String>> {
+    let mut fingerprints = HashSet::new();
+    let mut unreadable = 0;
+    for cert in CertParser::from_file(path)? {
+        match cert {
+            Ok(cert) => {
+                fingerprints.insert(cert.fingerprint().to_hex());
+            }
+            Err(_) => unreadable += 1,
+        }
+    }
+    if unreadable > 0 {
+        eprintln!(
+            "Warning: skipped {} unreadable certs in {}",
+            unreadable,
+            path.display()
+        );
+    }
+    Ok(fingerprints)
+}
+
+/// Owns the export directory for the duration of a run. Matches are handed
+/// over a channel to a single writer thread, which keeps `found_keys.txt`
+/// open and flushes it on an interval rather than reopening it per match.
+/// With `--stdout` the same thread streams the keys to stdout instead, so
+/// concurrent matches never interleave.
+///
+/// The channel holds at most `--save-queue-depth` keys. When bursts of
+/// matches outpace the writer, workers block in [`KeyWriter::save`] until
+/// there is room, so mining slows down instead of piling keys up in memory.
+pub struct KeyWriter {
+    sender: SyncSender<MinerResult>,
+    handle: JoinHandle<Result<()>>,
+}
+
+impl KeyWriter {
+    pub fn spawn(config: Arc<Config>, stats: Arc<Stats>) -> Result<Self> {
+        let (log, manifest) = if config.stdout || config.archive.is_some() {
+            (None, None)
+        } else {
+            let log_path = config.export_dir.join("found_keys.txt");
+            let log = BufWriter::with_capacity(
+                BUFFER_SIZE,
+                fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(log_path)?,
+            );
+            (Some(log), Some(Manifest::load(&config.export_dir)?))
+        };
+
+        // A resumed run, or another run into the same directory, continues
+        // the numbering rather than overwriting.
+        let next_index = stats
+            .keys_found
+            .load(Ordering::Relaxed)
+            .max(manifest.as_ref().map_or(0, Manifest::next_index));
+        let (sender, receiver) = mpsc::sync_channel(config.save_queue_depth);
+        let mut state = WriterState {
+            #[cfg(feature = "archive")]
+            archive: config
+                .archive
+                .clone()
+                .map(|path| Archive::new(path, config.file_mode)),
+            config,
+            stats,
+            log,
+            manifest,
+            next_index,
+        };
+        let handle = thread::spawn(move || state.run(receiver));
+        Ok(Self { sender, handle })
+    }
+
+    pub fn save(&self, result: MinerResult) {
+        if let Err(e) = self.sender.send(result) {
+            eprintln!("Key writer has stopped, dropping key {}", e.0.fingerprint);
+        }
+    }
+
+    /// Closes the channel and waits for every queued key to be written and
+    /// the log flushed.
+    pub fn finish(self) -> Result<()> {
+        drop(self.sender);
+        self.handle
+            .join()
+            .map_err(|_| Error::InvalidOperation("key writer thread panicked".into()))?
+    }
+}
+
+/// Everything the writer thread owns.
+struct WriterState {
+    config: Arc<Config>,
+    stats: Arc<Stats>,
+    log: Option<BufWriter<File>>,
+    /// Kept alongside `log`, and rewritten after every saved key.
+    manifest: Option<Manifest>,
+    /// With `--archive`, where keys and the log go instead of `log`.
+    #[cfg(feature = "archive")]
+    archive: Option<Archive>,
+    /// Indices are handed out here, in the order keys reach the writer, so
+    /// they are dense and match the order of lines in found_keys.txt no
+    /// matter how the workers race.
+    next_index: usize,
+}
+
+impl WriterState {
+    fn run(&mut self, receiver: Receiver<MinerResult>) -> Result<()> {
+        let mut last_flush = Instant::now();
+        loop {
+            match receiver.recv_timeout(LOG_FLUSH_INTERVAL) {
+                Ok(result) => {
+                    if let Some(delay) = self.config.debug_save_delay {
+                        thread::sleep(delay);
+                    }
+                    let saved = self.save(&result);
+                    match saved {
+                        Ok(paths) => {
+                            for path in &paths {
+                                if self.config.stdout_reserved() {
+                                    eprintln!("Saved {}", path.display());
+                                } else {
+                                    println!("Saved {}", path.display());
+                                }
+                            }
+                            self.record_in_manifest(&result, paths);
+                            self.next_index += 1;
+                            if self.config.gpg_import {
+                                if let Err(e) = gpg_import(&result.cert) {
+                                    eprintln!(
+                                        "Error importing {} into gpg: {}",
+                                        result.fingerprint, e
+                                    );
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            let message = format!("Error saving key: {}", e);
+                            eprintln!("{}", message);
+                            audit::error(&message);
+                        }
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            if last_flush.elapsed() >= LOG_FLUSH_INTERVAL {
+                self.flush_log();
+                last_flush = Instant::now();
+            }
+        }
+
+        self.flush_log();
+        #[cfg(feature = "archive")]
+        if let Some(archive) = &self.archive {
+            archive.write()?;
+            if let Some(path) = &self.config.archive {
+                if self.config.stdout_reserved() {
+                    eprintln!("Wrote {}", path.display());
+                } else {
+                    println!("Wrote {}", path.display());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves `result` wherever this run keeps keys, returning the paths of
+    /// any files written.
+    fn save(&mut self, result: &MinerResult) -> Result<Vec<PathBuf>> {
+        #[cfg(feature = "archive")]
+        if let Some(archive) = self.archive.as_mut() {
+            archive_key(result, self.next_index, archive, &self.config)?;
+            return Ok(Vec::new());
+        }
+        if self.log.is_some() {
+            self.save_with_fallback(result)
+        } else {
+            write_stdout(result, &self.config).map(|()| Vec::new())
+        }
+    }
+
+    /// Adds a saved key to the manifest and rewrites it. Failing to is
+    /// only a warning, since the key itself is already safely on disk.
+    fn record_in_manifest(&mut self, result: &MinerResult, paths: Vec<PathBuf>) {
+        let Some(manifest) = self.manifest.as_mut() else {
+            return;
+        };
+        let export_dir = &self.config.export_dir;
+        let case = self.config.hex_case;
+        manifest.keys.push(ManifestEntry {
+            index: self.next_index,
+            fingerprint: case.apply(&result.fingerprint),
+            pattern: case.apply(&result.pattern),
+            identity: result.identity.clone(),
+            files: paths
+                .iter()
+                .map(|path| path.strip_prefix(export_dir).unwrap_or(path).to_path_buf())
+                .collect(),
+        });
+        if let Err(e) = manifest.write(export_dir) {
+            eprintln!("Error writing manifest.json: {}", e);
+        }
+    }
+
+    /// Flushes the log, warning rather than stopping the writer on failure
+    /// so a temporarily full disk doesn't lose the matches still to come.
+    fn flush_log(&mut self) {
+        if let Some(log) = self.log.as_mut() {
+            if let Err(e) = log.flush() {
+                eprintln!("Error flushing found_keys.txt: {}", e);
+            }
+        }
+    }
+
+    /// Saves into `export_dir`, moving to `--fallback-dir` if the disk
+    /// fills up. Without a fallback, or if that fills too, mining is paused
+    /// and the key held in memory until the user has made room. Returns the
+    /// paths of the files written.
+    fn save_with_fallback(&mut self, result: &MinerResult) -> Result<Vec<PathBuf>> {
+        let mut root = self.config.export_dir.clone();
+        loop {
+            let Some(log) = self.log.as_mut() else {
+                return Ok(Vec::new());
+            };
+            let error = match save_key(result, self.next_index, &root, log, &self.config) {
+                Ok(paths) => {
+                    self.stats.paused.store(false, Ordering::Relaxed);
+                    return Ok(paths);
+                }
+                Err(e) if is_disk_full(&e) => e,
+                Err(e) => return Err(e),
+            };
+
+            match self
+                .config
+                .fallback_dir
+                .as_ref()
+                .filter(|dir| **dir != root)
+            {
+                Some(fallback) => {
+                    eprintln!(
+                        "\n{} ({}), saving key {} to {}",
+                        root.display(),
+                        error,
+                        result.fingerprint,
+                        fallback.display()
+                    );
+                    create_private_dir(fallback, &self.config)?;
+                    root = fallback.clone();
+                }
+                None => self.wait_for_space(&root, result)?,
+            }
+        }
+    }
+
+    fn wait_for_space(&self, root: &Path, result: &MinerResult) -> Result<()> {
+        self.stats.paused.store(true, Ordering::Relaxed);
+        eprintln!(
+            "\nDisk full writing to {}. Mining is paused and key {} is held in memory.\n\
+             Free some space and press Enter to retry.",
+            root.display(),
+            result.fingerprint
+        );
+        audit::warning(&format!(
+            "Disk full writing to {}, mining paused",
+            root.display()
+        ));
+
+        // Without an interactive stdin, retry on a timer instead.
+        if io::stdin().read_line(&mut String::new())? == 0 {
+            thread::sleep(DISK_FULL_RETRY);
+        }
+        Ok(())
+    }
+}
+
+fn is_disk_full(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|e| e.kind() == io::ErrorKind::StorageFull)
+    })
+}
+
+/// Imports `cert`, secret keys included, into the user's GnuPG keyring with
+/// `gpg --batch --import`, passing on gpg's own messages.
+fn gpg_import(cert: &Cert) -> Result<()> {
+    let mut child = Command::new("gpg")
+        .args(["--batch", "--import"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| Error::InvalidOperation(format!("cannot run gpg: {}", e)))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        cert.as_tsk().armored().serialize(&mut stdin)?;
+    }
+    let output = child.wait_with_output()?;
+    for line in String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .chain(String::from_utf8_lossy(&output.stdout).lines())
+    {
+        eprintln!("{}", line);
+    }
+
+    if !output.status.success() {
+        return Err(Error::InvalidOperation(format!("gpg --import {}", output.status)).into());
+    }
+    Ok(())
+}
+
+/// Streams the armored key to stdout, ready to pipe into `gpg --import`.
+fn write_stdout(result: &MinerResult, config: &Config) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    write_armored(&result.cert, false, config, &mut stdout)?;
+    if config.stdout_private {
+        write_armored(&result.cert, true, config, &mut stdout)?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+/// sequoia's fixed armor line length.
+const SEQUOIA_ARMOR_WIDTH: usize = 64;
+
+/// Writes `cert`, with its secret keys if `secret`, in ASCII armor. The
+/// headers are sequoia's usual fingerprint and UserID comments, plus the
+/// `--armor-comment` if one was given. sequoia can't wrap at any other
+/// width, so for `--armor-width` its output is re-wrapped afterwards.
+fn write_armored(cert: &Cert, secret: bool, config: &Config, out: &mut dyn Write) -> Result<()> {
+    match config.armor_width {
+        Some(width) if width != SEQUOIA_ARMOR_WIDTH => {
+            let mut armored = Vec::new();
+            armor_cert(cert, secret, config, &mut armored)?;
+            out.write_all(rewrap_armor(&String::from_utf8_lossy(&armored), width).as_bytes())?;
+            Ok(())
+        }
+        _ => armor_cert(cert, secret, config, out),
+    }
+}
+
+/// Re-wraps the base64 body of sequoia's armor to `width` characters a
+/// line, keeping the BEGIN line, headers, checksum and END line as they are.
+fn rewrap_armor(armored: &str, width: usize) -> String {
+    let mut out = String::with_capacity(armored.len() * 2);
+    let mut lines = armored.lines();
+    // Up to and including the blank line that ends the headers.
+    for line in lines.by_ref() {
+        out.push_str(line);
+        out.push('\n');
+        if line.is_empty() {
+            break;
+        }
+    }
+
+    // The body ends at the `=XXXX` checksum or, without one, the END line.
+    // Base64 padding can leave a short last line of `=` or `==`, which is
+    // still body.
+    let mut body = String::new();
+    let mut trailer = Vec::new();
+    for line in lines {
+        let checksum = line.len() == 5 && line.starts_with('=');
+        if trailer.is_empty() && !checksum && !line.starts_with("-----") {
+            body.push_str(line);
+        } else {
+            trailer.push(line);
+        }
+    }
+    for chunk in body.as_bytes().chunks(width) {
+        out.push_str(&String::from_utf8_lossy(chunk));
+        out.push('\n');
+    }
+    for line in trailer {
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+fn armor_cert(cert: &Cert, secret: bool, config: &Config, out: &mut dyn Write) -> Result<()> {
+    let Some(comment) = &config.armor_comment else {
+        if secret {
+            cert.as_tsk().armored().serialize(out)?;
+        } else {
+            cert.armored().serialize(out)?;
+        }
+        return Ok(());
+    };
+
+    let mut headers = cert.armor_headers();
+    headers.push(comment.clone());
+    let kind = if secret {
+        armor::Kind::SecretKey
+    } else {
+        armor::Kind::PublicKey
+    };
+    let mut armored = armor::Writer::with_headers(
+        out,
+        kind,
+        headers.iter().map(|header| ("Comment", header.as_str())),
+    )?;
+    if secret {
+        cert.as_tsk().serialize(&mut armored)?;
+    } else {
+        cert.serialize(&mut armored)?;
+    }
+    armored.finalize()?;
+    Ok(())
+}
+
+/// Creates `dir` and any missing parents with `--dir-mode`. Directories
+/// that already exist keep their permissions.
+#[cfg_attr(not(unix), allow(unused_variables))]
+pub fn create_private_dir(dir: &Path, config: &Config) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, config.dir_mode);
+    builder.create(dir)
+}
+
+/// Opens `path` for a secret key, with `--file-mode`. An existing file is
+/// truncated and has its permissions reset too, so overwriting a key
+/// from an earlier run can't leave it readable.
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn create_private_file(path: &Path, config: &Config) -> io::Result<File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, config.file_mode);
+    let file = options.open(path)?;
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(
+        config.file_mode,
+    ))?;
+    Ok(file)
+}
+
+/// The name `path` is written under before being renamed into place: its
+/// whole file name plus `.tmp`, so `run.toml` and `run.json` side by side
+/// don't share a `run.tmp`.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/// Writes `path` through `write` under a temporary name opened with
+/// `options`, then renames it into place, so readers never see a partly
+/// written file and a crash mid-write leaves the previous version intact.
+/// The temporary file is removed if the write fails.
+pub fn replace_file<E: From<io::Error>>(
+    path: &Path,
+    options: &fs::OpenOptions,
+    write: impl FnOnce(File) -> std::result::Result<(), E>,
+) -> std::result::Result<(), E> {
+    let temp = temp_path(path);
+    let result = options
+        .open(&temp)
+        .map_err(E::from)
+        .and_then(write)
+        .and_then(|()| fs::rename(&temp, path).map_err(E::from));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// [`replace_file`] for contents written in one go.
+pub fn write_atomically(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    replace_file(
+        path,
+        fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true),
+        |mut file| file.write_all(contents.as_ref()),
+    )
+}
+
+/// Directory the files for key `index` go in: `root` itself, or with
+/// `--max-files-per-dir` a zero-padded numbered subdirectory of it.
+fn key_dir(root: &Path, index: usize, config: &Config) -> Result<PathBuf> {
+    match config.max_files_per_dir {
+        Some(per_dir) => {
+            let dir = root.join(format!("{:04}", index / per_dir));
+            create_private_dir(&dir, config)?;
+            Ok(dir)
+        }
+        None => Ok(root.to_path_buf()),
+    }
+}
+
+/// What a key's files are named by: its index, or with `--name-by
+/// fingerprint` its short key ID, suffixed `_2`, `_3`, ... in the unlikely
+/// case that `taken` says a key with the same short ID was already saved.
+fn file_tag(
+    result: &MinerResult,
+    index: usize,
+    config: &Config,
+    taken: impl Fn(&str) -> bool,
+) -> String {
+    match config.name_by {
+        NameBy::Index => index.to_string(),
+        NameBy::Fingerprint => {
+            let short_id = config
+                .hex_case
+                .apply(&result.fingerprint[result.fingerprint.len() - SHORT_ID_LEN..]);
+            let mut tag = short_id.clone();
+            let mut n = 1;
+            while taken(&format!("pub_{}.asc", tag)) {
+                n += 1;
+                tag = format!("{}_{}", short_id, n);
+            }
+            tag
+        }
+    }
+}
+
+fn save_key(
+    result: &MinerResult,
+    index: usize,
+    root: &Path,
+    log: &mut impl Write,
+    config: &Config,
+) -> Result<Vec<PathBuf>> {
+    let MinerResult {
+        cert, revocation, ..
+    } = result;
+    let dir = key_dir(root, index, config)?;
+    let tag = file_tag(result, index, config, |name| dir.join(name).exists());
+    let (public, private, revocation_name) = file_prefixes(config);
+
+    let public_path = dir.join(format!("{}_{}.asc", public, tag));
+    let mut writer = BufWriter::with_capacity(BUFFER_SIZE, File::create(&public_path)?);
+    write_armored(cert, false, config, &mut writer)?;
+    writer.flush()?;
+
+    let private_path = dir.join(format!("{}_{}.asc", private, tag));
+    let mut writer =
+        BufWriter::with_capacity(BUFFER_SIZE, create_private_file(&private_path, config)?);
+    write_armored(cert, true, config, &mut writer)?;
+    writer.flush()?;
+
+    let mut paths = vec![public_path, private_path];
+    if config.revocation {
+        let revocation_path = dir.join(format!("{}_{}.asc", revocation_name, tag));
+        let mut writer = BufWriter::with_capacity(BUFFER_SIZE, File::create(&revocation_path)?);
+        write_revocation(revocation, &mut writer)?;
+        writer.flush()?;
+        paths.push(revocation_path);
+    }
+
+    #[cfg(feature = "x509")]
+    if config.x509_out {
+        let x509 = crate::x509::export(cert, config)?;
+        let key_path = dir.join(format!("x509_key_{}.pem", tag));
+        create_private_file(&key_path, config)?.write_all(&x509.key_pem[..])?;
+        let cert_path = dir.join(format!("x509_cert_{}.pem", tag));
+        fs::write(&cert_path, &x509.cert_pem)?;
+        paths.extend([key_path, cert_path]);
+    }
+
+    #[cfg(feature = "qr")]
+    if config.qr {
+        let qr_path = dir.join(format!("qr_{}.png", tag));
+        crate::qr::write_png(&result.fingerprint, &qr_path)?;
+        paths.push(qr_path);
+    }
+
+    #[cfg(feature = "ssh")]
+    if let Some(ssh_dir) = &config.ssh_out {
+        paths.extend(crate::ssh::write_ssh_keys(cert, ssh_dir, &tag)?);
+    }
+
+    write_log_line(result, index, &paths, log, config)?;
+
+    Ok(paths)
+}
+
+/// Public, private and revocation file name prefixes for `--name-by`.
+fn file_prefixes(config: &Config) -> (&'static str, &'static str, &'static str) {
+    match config.name_by {
+        NameBy::Index => ("public_key", "private_key", "revocation"),
+        NameBy::Fingerprint => ("pub", "priv", "rev"),
+    }
+}
+
+fn write_revocation(revocation: &Signature, out: &mut dyn Write) -> Result<()> {
+    let mut armored = armor::Writer::new(out, armor::Kind::PublicKey)?;
+    Packet::from(revocation.clone()).serialize(&mut armored)?;
+    armored.finalize()?;
+    Ok(())
+}
+
+/// Placeholders a `--log-template` may use.
+const LOG_PLACEHOLDERS: [&str; 5] = ["index", "fpr", "pattern", "offset", "time"];
+
+/// Splits a `--log-template` into literal text and placeholder names, in
+/// order, rejecting unknown placeholders and unclosed braces.
+fn parse_log_template(template: &str) -> std::result::Result<Vec<(&str, bool)>, String> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        parts.push((&rest[..start], false));
+        let Some(end) = rest[start..].find('}') else {
+            return Err(format!("--log-template {:?} has an unclosed {{", template));
+        };
+        let name = &rest[start + 1..start + end];
+        if !LOG_PLACEHOLDERS.contains(&name) {
+            return Err(format!(
+                "--log-template has unknown placeholder {{{}}}, expected one of {}",
+                name,
+                LOG_PLACEHOLDERS
+                    .iter()
+                    .map(|name| format!("{{{}}}", name))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        parts.push((name, true));
+        rest = &rest[start + end + 1..];
+    }
+    parts.push((rest, false));
+    Ok(parts)
+}
+
+/// Checks a `--log-template` for `Config::validate`.
+pub fn check_log_template(template: &str) -> std::result::Result<(), String> {
+    if template.chars().any(char::is_control) {
+        return Err("--log-template must be a single line".to_string());
+    }
+    parse_log_template(template).map(|_| ())
+}
+
+/// Appends the found_keys.txt line for key `index`, saved as `paths`.
+fn write_log_line(
+    result: &MinerResult,
+    index: usize,
+    paths: &[PathBuf],
+    log: &mut impl Write,
+    config: &Config,
+) -> Result<()> {
+    let MinerResult {
+        fingerprint,
+        identifier,
+        pattern,
+        offset,
+        group,
+        identity,
+        label,
+        ..
+    } = result;
+    let case = config.hex_case;
+    if let Some(template) = &config.log_template {
+        // Validated with the rest of the config.
+        let parts = parse_log_template(template).map_err(Error::InvalidArgument)?;
+        for (text, placeholder) in parts {
+            if !placeholder {
+                log.write_all(text.as_bytes())?;
+                continue;
+            }
+            match text {
+                "index" => write!(log, "{}", index)?,
+                "fpr" => write!(log, "{}", case.apply(fingerprint))?,
+                "pattern" => write!(log, "{}", case.apply(pattern))?,
+                "offset" => write!(log, "{}", offset)?,
+                "time" => write!(
+                    log,
+                    "{}",
+                    Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+                )?,
+                _ => {}
+            }
+        }
+        writeln!(log)?;
+        return Ok(());
+    }
+    write!(
+        log,
+        "[{}] {} - Matched pattern: {} at offset {}",
+        index,
+        case.apply(identifier),
+        case.apply(pattern),
+        offset
+    )?;
+    if identifier != fingerprint {
+        write!(log, " - Primary fingerprint {}", case.apply(fingerprint))?;
+    }
+    if let Some(label) = label {
+        write!(log, " - {}", label)?;
+    }
+    if let Some(group) = group {
+        write!(log, " - Group {}", group)?;
+    }
+    if let Some(identity) = identity {
+        write!(log, " - Identity {}", identity)?;
+    }
+    if config.name_by == NameBy::Fingerprint {
+        let names: Vec<String> = paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        write!(log, " - Files: {}", names.join(", "))?;
+    }
+    let entropy = visual_entropy(pattern);
+    if entropy < LOW_VISUAL_ENTROPY {
+        write!(
+            log,
+            " - Low visual entropy ({:.2} bits/char), secret key unaffected",
+            entropy
+        )?;
+    }
+    writeln!(log)?;
+    Ok(())
+}
+
+/// `--archive`: keeps key `index` in memory under the names [`save_key`]
+/// would give its files, to be written out with the rest at the end.
+#[cfg(feature = "archive")]
+fn archive_key(
+    result: &MinerResult,
+    index: usize,
+    archive: &mut Archive,
+    config: &Config,
+) -> Result<()> {
+    let tag = file_tag(result, index, config, |name| archive.contains(name));
+    let (public, private, revocation_name) = file_prefixes(config);
+
+    let mut names = Vec::new();
+    let mut data = Vec::new();
+    write_armored(&result.cert, false, config, &mut data)?;
+    names.push(format!("{}_{}.asc", public, tag));
+    archive.add(names[0].clone(), data.into(), false);
+
+    let mut data = ProtectedWriter::new();
+    write_armored(&result.cert, true, config, &mut data)?;
+    names.push(format!("{}_{}.asc", private, tag));
+    archive.add(names[1].clone(), data.into_protected(), true);
+
+    if config.revocation {
+        let mut data = Vec::new();
+        write_revocation(&result.revocation, &mut data)?;
+        names.push(format!("{}_{}.asc", revocation_name, tag));
+        archive.add(names[2].clone(), data.into(), false);
+    }
+
+    let paths: Vec<PathBuf> = names.into_iter().map(PathBuf::from).collect();
+    write_log_line(result, index, &paths, &mut archive.log, config)
+}
+
+/// A `Write` sink for secrets that grows like a `Vec` but keeps its
+/// contents in `Protected` buffers, so the copy left behind each time it
+/// grows is wiped rather than freed as it is.
+#[cfg(feature = "archive")]
+struct ProtectedWriter {
+    buf: Protected,
+    len: usize,
+}
+
+#[cfg(feature = "archive")]
+impl ProtectedWriter {
+    fn new() -> Self {
+        Self {
+            buf: Protected::from(Vec::new()),
+            len: 0,
+        }
+    }
+
+    fn into_protected(self) -> Protected {
+        Protected::from(&self.buf[..self.len])
+    }
+}
+
+#[cfg(feature = "archive")]
+impl Write for ProtectedWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let end = self.len + data.len();
+        if end > self.buf.len() {
+            let mut grown = Protected::from(vec![0; end.max(2 * self.buf.len())]);
+            grown[..self.len].copy_from_slice(&self.buf[..self.len]);
+            self.buf = grown;
+        }
+        self.buf[self.len..end].copy_from_slice(data);
+        self.len = end;
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HexCase;
+    use sequoia_openpgp::packet::UserID;
+    use std::sync::Barrier;
+
+    /// An empty directory under the system temp dir, removed on drop.
+    struct ScratchDir(PathBuf);
+
+    impl ScratchDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "vanity-pgp-miner-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for ScratchDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn atomic_writes_to_names_sharing_a_stem_use_separate_temp_files() {
+        let dir = ScratchDir::new("atomic");
+        let state = dir.0.join("run.toml");
+        let stats = dir.0.join("run.json");
+        assert_ne!(temp_path(&state), temp_path(&stats));
+        assert_eq!(temp_path(&state), dir.0.join("run.toml.tmp"));
+
+        write_atomically(&state, "state").unwrap();
+        write_atomically(&stats, "stats").unwrap();
+        assert_eq!(fs::read_to_string(&state).unwrap(), "state");
+        assert_eq!(fs::read_to_string(&stats).unwrap(), "stats");
+        assert_eq!(fs::read_dir(&dir.0).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn failed_replace_keeps_the_old_file_and_drops_the_temp_file() {
+        let dir = ScratchDir::new("replace");
+        let path = dir.0.join("manifest.json");
+        write_atomically(&path, "old").unwrap();
+        let result = replace_file(
+            &path,
+            fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true),
+            |mut file| {
+                file.write_all(b"half")?;
+                Err(io::Error::other("interrupted"))
+            },
+        );
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old");
+        assert!(!temp_path(&path).exists());
+    }
+
+    #[test]
+    fn simultaneous_matches_get_dense_unique_ordered_indices() {
+        const THREADS: usize = 8;
+        const MATCHES_PER_THREAD: usize = 8;
+        let dir = ScratchDir::new("writer-stress");
+        let config = Arc::new(Config {
+            export_dir: dir.0.clone(),
+            ..Config::default()
+        });
+        let stats = Arc::new(Stats::new());
+        let writer = KeyWriter::spawn(Arc::clone(&config), Arc::clone(&stats)).unwrap();
+
+        // Keys are generated up front so the workers all save at once.
+        let uid = UserID::from("Test <test@example.com>");
+        let batches: Vec<Vec<MinerResult>> = (0..THREADS)
+            .map(|_| {
+                (0..MATCHES_PER_THREAD)
+                    .map(|_| MinerResult::generated(&uid))
+                    .collect()
+            })
+            .collect();
+        let barrier = Barrier::new(THREADS);
+        thread::scope(|scope| {
+            for batch in batches {
+                let (writer, barrier, stats) = (&writer, &barrier, &stats);
+                scope.spawn(move || {
+                    barrier.wait();
+                    for result in batch {
+                        stats.keys_found.fetch_add(1, Ordering::Relaxed);
+                        writer.save(result);
+                    }
+                });
+            }
+        });
+        writer.finish().unwrap();
+
+        let total = THREADS * MATCHES_PER_THREAD;
+        let manifest = Manifest::load(&dir.0).unwrap();
+        let indices: Vec<usize> = manifest.keys.iter().map(|entry| entry.index).collect();
+        assert_eq!(indices, (0..total).collect::<Vec<_>>());
+        let fingerprints: HashSet<&str> = manifest
+            .keys
+            .iter()
+            .map(|entry| entry.fingerprint.as_str())
+            .collect();
+        assert_eq!(fingerprints.len(), total);
+
+        // found_keys.txt lists the keys in index order, and every file the
+        // manifest names was written.
+        let log = fs::read_to_string(dir.0.join("found_keys.txt")).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), total);
+        for (line, entry) in lines.iter().zip(&manifest.keys) {
+            let prefix = format!(
+                "[{}] {} ",
+                entry.index,
+                config.hex_case.apply(&entry.fingerprint)
+            );
+            assert!(line.starts_with(&prefix), "{:?} for {:?}", line, prefix);
+            for file in &entry.files {
+                assert!(dir.0.join(file).is_file(), "{} is missing", file.display());
+            }
+        }
+        assert_eq!(stats.keys_found.load(Ordering::Relaxed), total);
+    }
+
+    #[test]
+    fn log_line_shows_the_matched_identifier() {
+        let mut result = MinerResult::generated(&UserID::from("Test <test@example.com>"));
+        // As for --match-key subkey --id-form long, though any identifier
+        // other than the fingerprint is logged the same way.
+        result.identifier = "0123456789ABCDEF".to_string();
+        result.pattern = "89ABCDEF".to_string();
+        result.offset = 8;
+
+        let mut log = Vec::new();
+        write_log_line(&result, 3, &[], &mut log, &Config::default()).unwrap();
+        let line = String::from_utf8(log).unwrap();
+        assert_eq!(
+            line,
+            format!(
+                "[3] 0123456789ABCDEF - Matched pattern: 89ABCDEF at offset 8 - \
+                 Primary fingerprint {}\n",
+                result.fingerprint
+            )
+        );
+    }
+
+    #[test]
+    fn manifest_follows_hex_case_and_numbering_continues_across_cases() {
+        let dir = ScratchDir::new("manifest-case");
+        let uid = UserID::from("Test <test@example.com>");
+        for hex_case in [HexCase::Lower, HexCase::Upper] {
+            let config = Arc::new(Config {
+                export_dir: dir.0.clone(),
+                hex_case,
+                ..Config::default()
+            });
+            let writer = KeyWriter::spawn(config, Arc::new(Stats::new())).unwrap();
+            writer.save(MinerResult::generated(&uid));
+            writer.finish().unwrap();
+        }
+
+        let manifest = Manifest::load(&dir.0).unwrap();
+        let [lower, upper] = &manifest.keys[..] else {
+            panic!("expected 2 manifest entries, got {}", manifest.keys.len());
+        };
+        assert_eq!((lower.index, upper.index), (0, 1));
+        assert_eq!(lower.fingerprint, lower.fingerprint.to_ascii_lowercase());
+        assert_eq!(lower.pattern, lower.pattern.to_ascii_lowercase());
+        assert_eq!(upper.fingerprint, upper.fingerprint.to_ascii_uppercase());
+        assert_eq!(upper.pattern, upper.pattern.to_ascii_uppercase());
+        assert_eq!(manifest.next_index(), 2);
+    }
+}
@@ -0,0 +1,860 @@
+use crate::config::{Config, EncryptionUsage, IdForm, MatchKey};
+use sequoia_openpgp::{
+    cert::{amalgamation::ValidAmalgamation, CertBuilder, CipherSuite},
+    crypto::{hash::Digest, mem::Protected, mpi},
+    packet::{
+        key::{
+            KeyRole, PublicParts, SecretKeyMaterial, SecretParts, SubordinateRole, UnspecifiedRole,
+        },
+        prelude::*,
+        signature::SignatureBuilder,
+    },
+    policy::StandardPolicy,
+    types::*,
+    Cert, Error, PacketPile, Result,
+};
+use std::{cell::RefCell, io::Write, time::SystemTime};
+
+/// Which keys a generated cert is made of.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum KeyLayout {
+    /// A primary key that certifies and signs, with one encryption subkey
+    /// flagged for the given usage.
+    Standard(EncryptionUsage),
+    /// `--best-practice`: the primary only certifies, and signing moves to
+    /// a subkey of its own, at the cost of generating a third key.
+    BestPractice(EncryptionUsage),
+    /// `--monolithic`: a lone primary key that certifies, signs and
+    /// encrypts for the given usage. Curve25519 keys can't both sign and
+    /// encrypt, so this needs an RSA cipher suite, which is far slower to
+    /// generate.
+    Monolithic(EncryptionUsage),
+    /// Hidden `--fast-insecure`: a lone primary key with no encryption
+    /// subkey. Cv25519 is already sequoia's cheapest suite, so skipping the
+    /// subkey is what makes these quicker; they are for testing pipelines,
+    /// not for use.
+    PrimaryOnly,
+}
+
+thread_local! {
+    /// This thread's set-up `CertBuilder`, cloned for each candidate rather
+    /// than rebuilt from scratch. It only describes the cert (UserID, key
+    /// flags, cipher suite): key material is created inside `generate`, so
+    /// no secret passes through the template. Certs themselves are not
+    /// pooled, as each owns its secret keys, which sequoia wipes when the
+    /// cert is dropped.
+    static TEMPLATE: RefCell<Option<(UserID, KeyLayout, CipherSuite, CertBuilder<'static>)>> =
+        const { RefCell::new(None) };
+}
+
+/// Generates a candidate key of `suite`'s algorithms, returning the cert,
+/// its revocation certificate and the hex fingerprint used for matching.
+#[inline(always)]
+pub fn generate_key(
+    uid: &UserID,
+    layout: KeyLayout,
+    suite: CipherSuite,
+) -> Result<(Cert, Signature, String)> {
+    let (cert, revocation) = builder_template(uid, layout, suite).generate()?;
+
+    let key_id = cert.fingerprint().to_hex();
+    Ok((cert, revocation, key_id))
+}
+
+/// A copy of the thread's builder template, set up again only when `uid`,
+/// `layout` or `suite` differ from the last call.
+fn builder_template(uid: &UserID, layout: KeyLayout, suite: CipherSuite) -> CertBuilder<'static> {
+    TEMPLATE.with(|template| {
+        let mut template = template.borrow_mut();
+        match &*template {
+            Some((cached_uid, cached_layout, cached_suite, builder))
+                if cached_uid == uid && *cached_layout == layout && *cached_suite == suite =>
+            {
+                builder.clone()
+            }
+            _ => {
+                let builder = new_builder(uid, layout, suite);
+                *template = Some((uid.clone(), layout, suite, builder.clone()));
+                builder
+            }
+        }
+    })
+}
+
+fn new_builder(uid: &UserID, layout: KeyLayout, suite: CipherSuite) -> CertBuilder<'static> {
+    let builder = CertBuilder::new()
+        .add_userid(uid.clone())
+        .set_cipher_suite(suite);
+    let builder = match layout {
+        KeyLayout::BestPractice(_) => builder
+            .set_primary_key_flags(KeyFlags::empty().set_certification())
+            .add_signing_subkey(),
+        KeyLayout::Standard(_) | KeyLayout::PrimaryOnly => {
+            builder.set_primary_key_flags(KeyFlags::empty().set_certification().set_signing())
+        }
+        KeyLayout::Monolithic(usage) => {
+            builder.set_primary_key_flags(encryption_flags(usage).set_certification().set_signing())
+        }
+    };
+    match layout {
+        KeyLayout::PrimaryOnly | KeyLayout::Monolithic(_) => builder,
+        KeyLayout::Standard(usage) | KeyLayout::BestPractice(usage) => {
+            builder.add_subkey(encryption_flags(usage), None, suite)
+        }
+    }
+}
+
+fn encryption_flags(usage: EncryptionUsage) -> KeyFlags {
+    match usage {
+        EncryptionUsage::Both => KeyFlags::empty()
+            .set_transport_encryption()
+            .set_storage_encryption(),
+        EncryptionUsage::Transport => KeyFlags::empty().set_transport_encryption(),
+        EncryptionUsage::Storage => KeyFlags::empty().set_storage_encryption(),
+    }
+}
+
+/// Checks that the linked crypto backend supports every algorithm that
+/// generating and signing a key for `config` needs: its cipher suite, its
+/// signature digest and any preferences it advertises. Returns the
+/// backend's description. Sequoia can be built on backends with different
+/// coverage, and this turns an unsupported one into an immediate error
+/// instead of a failure on the first key.
+pub fn check_backend(config: &Config) -> Result<String> {
+    let mut missing = Vec::new();
+    let suite = config.cipher_suite();
+    if suite.is_supported().is_err() {
+        missing.push(format!("the {:?} cipher suite", suite));
+    }
+    let hash = config
+        .signature_hash
+        .as_deref()
+        .map(parse_hash)
+        .transpose()
+        .map_err(Error::InvalidArgument)?
+        .unwrap_or_default();
+    if !hash.is_supported() {
+        missing.push(hash.to_string());
+    }
+    for name in config.symmetric_prefs.iter().flatten() {
+        let cipher = parse_symmetric(name).map_err(Error::InvalidArgument)?;
+        if !cipher.is_supported() {
+            missing.push(cipher.to_string());
+        }
+    }
+    for name in config.compression_prefs.iter().flatten() {
+        let compression = parse_compression(name).map_err(Error::InvalidArgument)?;
+        if !compression.is_supported() {
+            missing.push(compression.to_string());
+        }
+    }
+
+    let backend = sequoia_openpgp::crypto::backend();
+    if !missing.is_empty() {
+        return Err(Error::InvalidOperation(format!(
+            "the {} crypto backend lacks {}, which key generation needs",
+            backend,
+            missing.join(", ")
+        ))
+        .into());
+    }
+    Ok(backend)
+}
+
+/// The identifier of `cert` that patterns are matched against, in the
+/// chosen form: the primary key's or the encryption subkey's fingerprint,
+/// long key ID, short key ID or keygrip, as uppercase hex.
+pub fn key_identifier(cert: &Cert, match_key: MatchKey, id_form: IdForm) -> Option<String> {
+    let key = match match_key {
+        MatchKey::Primary => cert.primary_key().key().role_as_unspecified(),
+        MatchKey::Subkey => subkey_with_flags(cert, |flags| {
+            flags.for_transport_encryption() || flags.for_storage_encryption()
+        })?,
+        MatchKey::Signing => subkey_with_flags(cert, |flags| flags.for_signing())?,
+    };
+    if id_form == IdForm::Keygrip {
+        return keygrip(key);
+    }
+    let hex = key.fingerprint().to_hex();
+    Some(hex[hex.len().checked_sub(id_form.hex_len())?..].to_string())
+}
+
+/// The first subkey whose binding grants flags that `want` accepts. Read
+/// straight from the self-signatures, without a policy, since the miner's
+/// own freshly generated certs are the input.
+fn subkey_with_flags(
+    cert: &Cert,
+    want: impl Fn(&KeyFlags) -> bool,
+) -> Option<&Key<PublicParts, UnspecifiedRole>> {
+    cert.keys()
+        .subkeys()
+        .find(|subkey| {
+            subkey
+                .self_signatures()
+                .next()
+                .and_then(|binding| binding.key_flags())
+                .is_some_and(|flags| want(&flags))
+        })
+        .map(|subkey| subkey.key().role_as_unspecified())
+}
+
+/// Domain parameters libgcrypt hashes ahead of the public point in an
+/// elliptic curve keygrip, as hex: p, a, b, the uncompressed base point g
+/// and the order n (the cofactor is left out). Ed25519's negative `a` and
+/// `d` go in as their magnitudes.
+const ED25519_KEYGRIP_PARAMS: [(char, &str); 5] = [
+    (
+        'p',
+        "7FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFED",
+    ),
+    ('a', "01"),
+    (
+        'b',
+        "2DFC9311D490018C7338BF8688861767FF8FF5B2BEBE27548A14B235ECA6874A",
+    ),
+    (
+        'g',
+        "04216936D3CD6E53FEC0A4E231FDD6DC5C692CC7609525A7B2C9562D608F25D51A\
+         6666666666666666666666666666666666666666666666666666666666666658",
+    ),
+    (
+        'n',
+        "1000000000000000000000000000000014DEF9DEA2F79CD65812631A5CF5D3ED",
+    ),
+];
+const CV25519_KEYGRIP_PARAMS: [(char, &str); 5] = [
+    (
+        'p',
+        "7FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFED",
+    ),
+    ('a', "01DB41"),
+    ('b', "01"),
+    (
+        'g',
+        "040000000000000000000000000000000000000000000000000000000000000009\
+         20AE19A1B8A086B4E01EDD2C7748D14C923D4D7E6D7C61B229E9C5A27ECED3D9",
+    ),
+    (
+        'n',
+        "1000000000000000000000000000000014DEF9DEA2F79CD65812631A5CF5D3ED",
+    ),
+];
+
+/// Appends one `(1:<name><len>:<value>)` element of a keygrip
+/// S-expression.
+fn push_keygrip_param(sexp: &mut Vec<u8>, name: char, value: &[u8]) {
+    sexp.extend_from_slice(format!("(1:{}{}:", name, value.len()).as_bytes());
+    sexp.extend_from_slice(value);
+    sexp.push(b')');
+}
+
+/// The GnuPG keygrip of `key`, as gpg-agent names private keys and
+/// `gpg --with-keygrip` shows them: a SHA-1 over the public parameters as
+/// libgcrypt lays them out, independent of the OpenPGP packet and so of
+/// the creation time. Covers the RSA, Ed25519 and Cv25519 keys the miner
+/// generates.
+pub fn keygrip<R: KeyRole>(key: &Key<PublicParts, R>) -> Option<String> {
+    let mut sexp = Vec::new();
+    let (params, q) = match key.mpis() {
+        mpi::PublicKey::RSA { n, .. } => {
+            // The modulus alone, as a signed big-endian integer.
+            if n.value().first().is_some_and(|byte| byte & 0x80 != 0) {
+                sexp.push(0);
+            }
+            sexp.extend_from_slice(n.value());
+            (&[][..], None)
+        }
+        mpi::PublicKey::EdDSA {
+            curve: Curve::Ed25519,
+            q,
+        } => (&ED25519_KEYGRIP_PARAMS[..], Some(q)),
+        mpi::PublicKey::ECDH {
+            curve: Curve::Cv25519,
+            q,
+            ..
+        } => (&CV25519_KEYGRIP_PARAMS[..], Some(q)),
+        _ => return None,
+    };
+    for (name, hex) in params {
+        let value: Vec<u8> = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<std::result::Result<_, _>>()
+            .ok()?;
+        push_keygrip_param(&mut sexp, *name, &value);
+    }
+    if let Some(q) = q {
+        // The compressed point, without OpenPGP's 0x40 prefix.
+        let point = q.value().strip_prefix(&[0x40]).unwrap_or(q.value());
+        push_keygrip_param(&mut sexp, 'q', point);
+    }
+
+    let mut hash = HashAlgorithm::SHA1.context().ok()?;
+    hash.update(&sexp);
+    let mut digest = vec![0; hash.digest_size()];
+    hash.digest(&mut digest).ok()?;
+    Some(digest.iter().map(|byte| format!("{:02X}", byte)).collect())
+}
+
+/// Checks that the cert and every one of its keys is valid under sequoia's
+/// `StandardPolicy` right now, so unusual builder settings can't produce a
+/// key that OpenPGP implementations will refuse.
+pub fn check_compliance(cert: &Cert) -> Result<()> {
+    let policy = StandardPolicy::new();
+    let valid_cert = cert.with_policy(&policy, None)?;
+
+    let valid_keys = valid_cert.keys().count();
+    let total_keys = cert.keys().count();
+    if valid_keys != total_keys {
+        return Err(Error::InvalidOperation(format!(
+            "only {} of {} keys are valid under the standard policy",
+            valid_keys, total_keys
+        ))
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Writes the packet structure of `cert` to `out`, one line per packet,
+/// so the cipher suite, key flags and subkeys can be checked by eye.
+pub fn dump_cert(cert: &Cert, out: &mut dyn Write) -> Result<()> {
+    let pile = PacketPile::from(cert.clone());
+    for packet in pile.descendants() {
+        let key = match packet {
+            Packet::PublicKey(key) => Some((key.pk_algo(), key.fingerprint(), None)),
+            Packet::PublicSubkey(key) => Some((key.pk_algo(), key.fingerprint(), None)),
+            Packet::SecretKey(key) => Some((
+                key.pk_algo(),
+                key.fingerprint(),
+                Some(secret_storage(key.secret())),
+            )),
+            Packet::SecretSubkey(key) => Some((
+                key.pk_algo(),
+                key.fingerprint(),
+                Some(secret_storage(key.secret())),
+            )),
+            _ => None,
+        };
+        if let Some((algo, fingerprint, storage)) = key {
+            write!(out, "{:?}: {} {}", packet.tag(), algo, fingerprint)?;
+            if let Some(storage) = storage {
+                write!(out, " secret={}", storage)?;
+            }
+            writeln!(out)?;
+            continue;
+        }
+
+        match packet {
+            Packet::UserID(userid) => writeln!(out, "{:?}: {}", packet.tag(), userid)?,
+            Packet::Signature(sig) => {
+                write!(
+                    out,
+                    "  {:?}: {} {}/{}",
+                    packet.tag(),
+                    sig.typ(),
+                    sig.pk_algo(),
+                    sig.hash_algo()
+                )?;
+                if let Some(flags) = sig.key_flags() {
+                    write!(out, " flags={:?}", flags)?;
+                }
+                if let Some(symmetric) = sig.preferred_symmetric_algorithms() {
+                    write!(out, " symmetric={:?}", symmetric)?;
+                }
+                writeln!(out)?;
+            }
+            other => writeln!(out, "{:?}", other.tag())?,
+        }
+    }
+    Ok(())
+}
+
+/// How sequoia is holding a secret key. Unencrypted secrets live in its
+/// `mem::Encrypted`, sealed under a per-process key and zeroed when
+/// dropped, so the millions of candidates thrown away during a run leave
+/// no plaintext secrets behind in freed memory.
+fn secret_storage(secret: &SecretKeyMaterial) -> &'static str {
+    match secret {
+        SecretKeyMaterial::Unencrypted(_) => "sealed-in-memory,zeroed-on-drop",
+        SecretKeyMaterial::Encrypted(_) => "passphrase-encrypted",
+    }
+}
+
+pub const ED25519_SEED_LEN: usize = 32;
+
+/// The 32-byte secret seed of an unencrypted Ed25519 key, as other formats
+/// (PKCS#8, OpenSSH) store it.
+pub fn ed25519_seed<R: KeyRole>(key: &Key<SecretParts, R>) -> Result<Protected> {
+    match key.secret() {
+        SecretKeyMaterial::Unencrypted(secret) => secret.map(|mpis| match mpis {
+            mpi::SecretKeyMaterial::EdDSA { scalar } => Some(scalar.value_padded(ED25519_SEED_LEN)),
+            _ => None,
+        }),
+        SecretKeyMaterial::Encrypted(_) => None,
+    }
+    .ok_or_else(|| {
+        Error::InvalidOperation("only unencrypted Ed25519 keys can be exported".into()).into()
+    })
+}
+
+/// Adds an Ed25519 authentication subkey, e.g. for SSH. Subkeys don't
+/// change the primary fingerprint, so this can wait until a key matched.
+fn add_auth_subkey(cert: Cert) -> Result<Cert> {
+    let mut signer = cert
+        .primary_key()
+        .key()
+        .clone()
+        .parts_into_secret()?
+        .into_keypair()?;
+    let subkey: Key<SecretParts, SubordinateRole> =
+        Key4::generate_ecc(true, Curve::Ed25519)?.into();
+    let binding = subkey.bind(
+        &mut signer,
+        &cert,
+        SignatureBuilder::new(SignatureType::SubkeyBinding)
+            .set_key_flags(KeyFlags::empty().set_authentication())?,
+    )?;
+    cert.insert_packets(vec![Packet::from(subkey), binding.into()])
+}
+
+/// Applies the settings that don't affect the fingerprint to a matched
+/// cert. Doing this only for matches keeps it off the hot path.
+pub fn finalize_cert(mut cert: Cert, config: &Config) -> Result<Cert> {
+    if config.auth_subkey {
+        cert = add_auth_subkey(cert)?;
+    }
+
+    if config.symmetric_prefs.is_none()
+        && config.compression_prefs.is_none()
+        && config.signature_hash.is_none()
+    {
+        return Ok(cert);
+    }
+
+    let symmetric = config
+        .symmetric_prefs
+        .as_deref()
+        .map(|names| {
+            names
+                .iter()
+                .map(|name| parse_symmetric(name))
+                .collect::<std::result::Result<Vec<_>, _>>()
+        })
+        .transpose()
+        .map_err(Error::InvalidArgument)?;
+    let compression = config
+        .compression_prefs
+        .as_deref()
+        .map(|names| {
+            names
+                .iter()
+                .map(|name| parse_compression(name))
+                .collect::<std::result::Result<Vec<_>, _>>()
+        })
+        .transpose()
+        .map_err(Error::InvalidArgument)?;
+    let hash = config
+        .signature_hash
+        .as_deref()
+        .map(parse_hash)
+        .transpose()
+        .map_err(Error::InvalidArgument)?;
+
+    // Re-issue each UserID binding with the new preferences and digest.
+    // CertBuilder backdates its signatures, so the fresh ones take
+    // precedence.
+    let mut signer = cert
+        .primary_key()
+        .key()
+        .clone()
+        .parts_into_secret()?
+        .into_keypair()?;
+    let policy = StandardPolicy::new();
+    let mut packets: Vec<Packet> = Vec::new();
+    for userid in cert.with_policy(&policy, None)?.userids() {
+        let mut builder = SignatureBuilder::from(userid.binding_signature().clone())
+            .set_signature_creation_time(SystemTime::now())?;
+        if let Some(symmetric) = &symmetric {
+            builder = builder.set_preferred_symmetric_algorithms(symmetric.clone())?;
+        }
+        if let Some(compression) = &compression {
+            builder = builder.set_preferred_compression_algorithms(compression.clone())?;
+        }
+        if let Some(hash) = hash {
+            builder = builder.set_hash_algo(hash);
+        }
+        let binding = builder.sign_userid_binding(&mut signer, None, userid.userid())?;
+        packets.push(userid.userid().clone().into());
+        packets.push(binding.into());
+    }
+
+    // A new digest also applies to the direct key and subkey binding
+    // signatures. Signing subkeys keep their primary key binding, which
+    // doesn't depend on the outer signature.
+    if let Some(hash) = hash {
+        let valid = cert.with_policy(&policy, None)?;
+        let direct = SignatureBuilder::from(valid.direct_key_signature()?.clone())
+            .set_signature_creation_time(SystemTime::now())?
+            .set_hash_algo(hash)
+            .sign_direct_key(&mut signer, None)?;
+        packets.push(cert.primary_key().key().clone().parts_into_secret()?.into());
+        packets.push(direct.into());
+
+        for subkey in valid.keys().subkeys() {
+            let binding = SignatureBuilder::from(subkey.binding_signature().clone())
+                .set_signature_creation_time(SystemTime::now())?
+                .set_hash_algo(hash)
+                .sign_subkey_binding(&mut signer, None, subkey.key())?;
+            packets.push(subkey.key().clone().parts_into_secret()?.into());
+            packets.push(binding.into());
+        }
+    }
+
+    cert.insert_packets(packets)
+}
+
+pub fn parse_symmetric(name: &str) -> std::result::Result<SymmetricAlgorithm, String> {
+    Ok(match name.to_ascii_lowercase().as_str() {
+        "aes256" => SymmetricAlgorithm::AES256,
+        "aes192" => SymmetricAlgorithm::AES192,
+        "aes128" => SymmetricAlgorithm::AES128,
+        "twofish" => SymmetricAlgorithm::Twofish,
+        "camellia256" => SymmetricAlgorithm::Camellia256,
+        "camellia192" => SymmetricAlgorithm::Camellia192,
+        "camellia128" => SymmetricAlgorithm::Camellia128,
+        "3des" | "tripledes" => SymmetricAlgorithm::TripleDES,
+        "cast5" => SymmetricAlgorithm::CAST5,
+        "blowfish" => SymmetricAlgorithm::Blowfish,
+        "idea" => SymmetricAlgorithm::IDEA,
+        _ => return Err(format!("Unknown symmetric algorithm: {}", name)),
+    })
+}
+
+/// The cipher suite for `--rsa-bits`. sequoia's `CertBuilder` offers RSA
+/// in these three sizes only, and the backend has to support the one
+/// chosen.
+pub fn rsa_suite(bits: u32) -> std::result::Result<CipherSuite, String> {
+    let suite = match bits {
+        2048 => CipherSuite::RSA2k,
+        3072 => CipherSuite::RSA3k,
+        4096 => CipherSuite::RSA4k,
+        _ => {
+            return Err(format!(
+                "--rsa-bits {} is not supported, expected 2048, 3072 or 4096",
+                bits
+            ))
+        }
+    };
+    suite.is_supported().map_err(|e| {
+        format!(
+            "RSA-{} is not supported by the {} crypto backend: {}",
+            bits,
+            sequoia_openpgp::crypto::backend(),
+            e
+        )
+    })?;
+    Ok(suite)
+}
+
+/// Parses a `--signature-hash` name, refusing digests the crypto backend
+/// can't compute.
+pub fn parse_hash(name: &str) -> std::result::Result<HashAlgorithm, String> {
+    let hash = match name.to_ascii_lowercase().as_str() {
+        "sha224" => HashAlgorithm::SHA224,
+        "sha256" => HashAlgorithm::SHA256,
+        "sha384" => HashAlgorithm::SHA384,
+        "sha512" => HashAlgorithm::SHA512,
+        _ => return Err(format!("Unknown hash algorithm: {}", name)),
+    };
+    if !hash.is_supported() {
+        return Err(format!(
+            "Hash algorithm {} is not supported by the {} crypto backend",
+            hash,
+            sequoia_openpgp::crypto::backend()
+        ));
+    }
+    Ok(hash)
+}
+
+pub fn parse_compression(name: &str) -> std::result::Result<CompressionAlgorithm, String> {
+    Ok(match name.to_ascii_lowercase().as_str() {
+        "uncompressed" | "none" => CompressionAlgorithm::Uncompressed,
+        "zip" => CompressionAlgorithm::Zip,
+        "zlib" => CompressionAlgorithm::Zlib,
+        "bzip2" => CompressionAlgorithm::BZip2,
+        _ => return Err(format!("Unknown compression algorithm: {}", name)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{parse_args, test_args};
+    use sequoia_openpgp::fmt::hex;
+
+    /// The config for a command line mining for `Test <test@example.com>`
+    /// with `flags`.
+    fn config_from(flags: &[&str]) -> Config {
+        let config = parse_args(&test_args(flags)).unwrap();
+        config.validate().unwrap();
+        config
+    }
+
+    /// A key generated the way the miner would for `config`.
+    fn generate(config: &Config) -> Cert {
+        let uid = UserID::from(config.user_id());
+        let (cert, _, _) = generate_key(&uid, config.key_layout(), config.cipher_suite()).unwrap();
+        cert
+    }
+
+    /// The key flags of the primary key, from its direct key signature,
+    /// and of each subkey, from its binding.
+    fn key_flags(cert: &Cert) -> (KeyFlags, Vec<KeyFlags>) {
+        let flags = |signature: Option<&Signature>| {
+            signature
+                .and_then(|signature| signature.key_flags())
+                .unwrap()
+        };
+        let primary = flags(cert.primary_key().self_signatures().next());
+        let subkeys = cert
+            .keys()
+            .subkeys()
+            .map(|subkey| flags(subkey.self_signatures().next()))
+            .collect();
+        (primary, subkeys)
+    }
+
+    #[test]
+    fn encryption_usage_sets_the_subkey_flags() {
+        for (flags, usage, transport, storage) in [
+            (&[][..], EncryptionUsage::Both, true, true),
+            (
+                &["--encryption-usage", "both"][..],
+                EncryptionUsage::Both,
+                true,
+                true,
+            ),
+            (
+                &["--encryption-usage", "transport"][..],
+                EncryptionUsage::Transport,
+                true,
+                false,
+            ),
+            (
+                &["--encryption-usage", "storage"][..],
+                EncryptionUsage::Storage,
+                false,
+                true,
+            ),
+        ] {
+            let config = config_from(flags);
+            assert!(config.encryption_usage == usage, "{:?}", flags);
+
+            let (primary, subkeys) = key_flags(&generate(&config));
+            assert!(primary.for_certification() && primary.for_signing());
+            assert!(!primary.for_transport_encryption() && !primary.for_storage_encryption());
+            assert_eq!(subkeys.len(), 1);
+            let subkey = &subkeys[0];
+            assert_eq!(subkey.for_transport_encryption(), transport, "{:?}", flags);
+            assert_eq!(subkey.for_storage_encryption(), storage, "{:?}", flags);
+            assert!(!subkey.for_signing() && !subkey.for_certification());
+        }
+    }
+
+    #[test]
+    fn encryption_usage_applies_with_best_practice() {
+        let config = config_from(&["--best-practice", "--encryption-usage", "storage"]);
+        let (primary, subkeys) = key_flags(&generate(&config));
+        assert!(primary.for_certification() && !primary.for_signing());
+        let encryption: Vec<&KeyFlags> = subkeys
+            .iter()
+            .filter(|flags| flags.for_transport_encryption() || flags.for_storage_encryption())
+            .collect();
+        assert_eq!(encryption.len(), 1);
+        assert!(encryption[0].for_storage_encryption());
+        assert!(!encryption[0].for_transport_encryption());
+    }
+
+    #[test]
+    fn unknown_encryption_usage_is_refused() {
+        let error = parse_args(&test_args(&["--encryption-usage", "signing"]))
+            .err()
+            .unwrap();
+        assert!(
+            error.starts_with("Unknown encryption usage \"signing\""),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn monolithic_primary_does_everything_with_no_subkeys() {
+        // RSA-2048 rather than the default 3072 bits, to keep the test quick.
+        let config = config_from(&["--monolithic", "--rsa-bits", "2048"]);
+        assert!(config.key_layout() == KeyLayout::Monolithic(EncryptionUsage::Both));
+        let cert = generate(&config);
+
+        assert_eq!(cert.keys().subkeys().count(), 0);
+        let (primary, _) = key_flags(&cert);
+        assert!(primary.for_certification() && primary.for_signing());
+        assert!(primary.for_transport_encryption() && primary.for_storage_encryption());
+        assert_eq!(
+            key_identifier(&cert, MatchKey::Primary, IdForm::Fingerprint),
+            Some(cert.fingerprint().to_hex())
+        );
+    }
+
+    #[test]
+    fn monolithic_primary_takes_the_encryption_usage() {
+        let config = config_from(&[
+            "--monolithic",
+            "--rsa-bits",
+            "2048",
+            "--encryption-usage",
+            "transport",
+        ]);
+        let cert = generate(&config);
+        assert_eq!(cert.keys().subkeys().count(), 0);
+        let (primary, _) = key_flags(&cert);
+        assert!(primary.for_certification() && primary.for_signing());
+        assert!(primary.for_transport_encryption());
+        assert!(!primary.for_storage_encryption());
+    }
+
+    #[test]
+    fn monolithic_needs_rsa_and_no_subkeys() {
+        assert!(config_from(&["--monolithic"]).cipher_suite() == CipherSuite::RSA3k);
+        for flags in [
+            &["--best-practice"][..],
+            &["--auth-subkey"][..],
+            &["--match-key", "subkey"][..],
+        ] {
+            let args = test_args(&[&["--monolithic"][..], flags].concat());
+            assert!(
+                parse_args(&args).unwrap().validate().is_err(),
+                "--monolithic {:?} was accepted",
+                flags
+            );
+        }
+    }
+
+    #[test]
+    fn subkey_long_key_id() {
+        let config = config_from(&["--match-key", "subkey", "--id-form", "long"]);
+        let cert = generate(&config);
+        let subkey = cert.keys().subkeys().next().unwrap();
+
+        let identifier = key_identifier(&cert, config.match_key, config.match_id_form()).unwrap();
+        assert_eq!(identifier.len(), 16);
+        assert_eq!(identifier, subkey.key().keyid().to_hex());
+        assert!(subkey.key().fingerprint().to_hex().ends_with(&identifier));
+        assert_ne!(identifier, cert.keyid().to_hex());
+    }
+
+    #[test]
+    fn id_form_applies_to_the_chosen_key() {
+        let cert = generate(&config_from(&[]));
+        let primary = cert.fingerprint().to_hex();
+        let subkey = cert
+            .keys()
+            .subkeys()
+            .next()
+            .unwrap()
+            .key()
+            .fingerprint()
+            .to_hex();
+
+        for (match_key, fingerprint) in [(MatchKey::Primary, &primary), (MatchKey::Subkey, &subkey)]
+        {
+            assert_eq!(
+                key_identifier(&cert, match_key, IdForm::Fingerprint).as_ref(),
+                Some(fingerprint)
+            );
+            assert_eq!(
+                key_identifier(&cert, match_key, IdForm::Long).as_deref(),
+                Some(&fingerprint[24..])
+            );
+            assert_eq!(
+                key_identifier(&cert, match_key, IdForm::Short).as_deref(),
+                Some(&fingerprint[32..])
+            );
+        }
+    }
+
+    #[test]
+    fn backend_check_covers_the_configured_algorithms() {
+        assert!(check_backend(&config_from(&[])).is_ok());
+        assert!(check_backend(&config_from(&[
+            "--rsa-bits",
+            "4096",
+            "--signature-hash",
+            "sha256",
+            "--symmetric-prefs",
+            "aes256,aes128",
+            "--compression-prefs",
+            "zlib,uncompressed",
+        ]))
+        .is_ok());
+
+        let config = Config {
+            symmetric_prefs: Some(vec!["rot13".to_string()]),
+            ..Config::default()
+        };
+        let error = check_backend(&config).unwrap_err().to_string();
+        assert!(
+            error.contains("Unknown symmetric algorithm: rot13"),
+            "{}",
+            error
+        );
+    }
+
+    /// Public points from libgcrypt's keygrip tests, with the keygrips it
+    /// expects for them, which are what `gpg --with-keygrip` prints.
+    #[test]
+    fn keygrip_known_answers() {
+        let ed25519: Key<PublicParts, UnspecifiedRole> = Key4::import_public_ed25519(
+            &hex::decode("773E72848C1FD5F9652B29E2E7AF79571A04990E96F2016BF4E0EC1890C2B7DB")
+                .unwrap(),
+            SystemTime::UNIX_EPOCH,
+        )
+        .unwrap()
+        .into();
+        assert_eq!(
+            keygrip(&ed25519).as_deref(),
+            Some("9DB6C64A38830F4960701789475520BE8C821F47")
+        );
+
+        let cv25519: Key<PublicParts, UnspecifiedRole> = Key4::import_public_cv25519(
+            &hex::decode("918C1733127F6BF2646FAE3D081A18AE77111C903B906310B077505EFFF12740")
+                .unwrap(),
+            HashAlgorithm::SHA256,
+            SymmetricAlgorithm::AES128,
+            SystemTime::now(),
+        )
+        .unwrap()
+        .into();
+        assert_eq!(
+            keygrip(&cv25519).as_deref(),
+            Some("0F89A565D3EA187CE839332398F5D480677DF49C")
+        );
+    }
+
+    #[test]
+    fn signing_subkey_needs_one() {
+        let cert = generate(&config_from(&[]));
+        assert_eq!(key_identifier(&cert, MatchKey::Signing, IdForm::Long), None);
+
+        let cert = generate(&config_from(&["--best-practice"]));
+        let identifier = key_identifier(&cert, MatchKey::Signing, IdForm::Long).unwrap();
+        let signing = cert
+            .keys()
+            .subkeys()
+            .find(|subkey| subkey.key().keyid().to_hex() == identifier);
+        assert!(signing.is_some());
+        assert_ne!(
+            identifier,
+            key_identifier(&cert, MatchKey::Subkey, IdForm::Long).unwrap()
+        );
+    }
+}
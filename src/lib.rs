@@ -0,0 +1,41 @@
+//! Core of the vanity PGP key miner, shared by the `vanity-pgp-miner`
+//! binary and the `bench` throughput tool so both exercise the same
+//! generation and matching path.
+
+#[cfg(feature = "archive")]
+pub mod archive;
+pub mod audit;
+pub mod config;
+#[cfg(feature = "coordinator")]
+pub mod coordinator;
+pub mod error;
+pub mod export;
+pub mod keygen;
+pub mod manifest;
+pub mod matcher;
+pub mod miner;
+#[cfg(feature = "profile")]
+pub mod profile;
+pub mod progress;
+#[cfg(feature = "qr")]
+pub mod qr;
+pub mod schedule;
+pub mod score;
+pub mod server;
+#[cfg(feature = "ssh")]
+pub mod ssh;
+pub mod state;
+pub mod transform;
+pub mod verify;
+#[cfg(feature = "webhook")]
+pub mod webhook;
+pub mod wizard;
+#[cfg(feature = "x509")]
+pub mod x509;
+
+pub use config::Config;
+pub use error::MinerError;
+pub use keygen::generate_key;
+pub use matcher::{generate_patterns, MaskMatcher, Matcher, PatternCache, WindowMaskMatcher};
+pub use miner::{mine_keys, mine_with, MinerResult, Stats};
+pub use score::Scorer;
@@ -6,272 +6,621 @@ static GLOBAL: jemallocator::Jemalloc = jemallocator::Jemalloc;
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
-use dashmap::DashSet;
-use indicatif::{ProgressBar, ProgressStyle};
-use lazy_static::lazy_static;
-use parking_lot::Mutex;
-use rayon::prelude::*;
-use rustc_hash::FxHashSet;
-use sequoia_openpgp::{
-    cert::{CertBuilder, CipherSuite},
-    packet::prelude::*,
-    serialize::Marshal,
-    types::*,
-    Cert, Result,
-};
-use std::iter;
+use sequoia_openpgp::{parse::Parse, Cert, Error, Result};
 use std::{
-    fs::{self, File},
-    io::{BufWriter, Write},
-    path::PathBuf,
-    sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
+    io::Write,
+    path::Path,
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+use vanity_pgp_miner::{
+    audit,
+    config::{load_config, parse_args, Config, ProgressFormat},
+    export::create_private_dir,
+    generate_patterns,
+    keygen::{check_backend, key_identifier},
+    matcher::{build_matcher, chance_of_any_match, pattern_categories},
+    mine_keys,
+    miner::sample_rate,
+    progress::{
+        display_progress, json_output, print_handshake, print_summary, status_output,
+        total_keys_json,
     },
-    time::{Duration, Instant},
+    server, state,
+    transform::build_transform,
+    verify::verify_export_dir,
+    wizard, Matcher, PatternCache, Stats,
 };
 
-const BUFFER_SIZE: usize = 32768;
-const PROGRESS_UPDATE_MS: u64 = 100;
-const DEFAULT_TOTAL_KEYS: usize = 2_000_000;
-const THREAD_STACK_SIZE: usize = 4 * 1024 * 1024;
+/// Below this chance of finding anything at all, a run is almost certainly
+/// misconfigured and gets a warning at startup.
+const LOW_ODDS: f64 = 0.01;
+/// How long `--watts` samples the key rate for its estimate.
+const COST_SAMPLE_WINDOW: Duration = Duration::from_secs(3);
+/// Optional cargo features, and whether this build has each.
+const FEATURES: [(&str, bool); 7] = [
+    ("x509", cfg!(feature = "x509")),
+    ("ssh", cfg!(feature = "ssh")),
+    ("profile", cfg!(feature = "profile")),
+    ("webhook", cfg!(feature = "webhook")),
+    ("coordinator", cfg!(feature = "coordinator")),
+    ("archive", cfg!(feature = "archive")),
+    ("qr", cfg!(feature = "qr")),
+];
+#[cfg(feature = "profile")]
+const PROFILE_OUTPUT: &str = "flamegraph.svg";
 
-struct Config {
-    name: String,
-    email: String,
-    export_dir: PathBuf,
-    total_keys: usize,
-}
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("--version") {
+        version(args.iter().any(|arg| arg == "--json"));
+        return Ok(());
+    }
+    if args.get(1).map(String::as_str) == Some("info") {
+        return info();
+    }
+    if args.get(1).map(String::as_str) == Some("check") {
+        return check(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("list-patterns") {
+        return list_patterns(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("coordinate") {
+        return coordinate(&args[2..]);
+    }
+    if args.get(1).map(String::as_str) == Some("verify") {
+        let dir = match args.get(2) {
+            Some(dir) => dir.into(),
+            None => Config::default().export_dir,
+        };
+        return verify(&dir);
+    }
 
-struct Stats {
-    keys_checked: AtomicUsize,
-    keys_found: AtomicUsize,
-    start_time: Instant,
-}
+    let config = if args.get(1).map(String::as_str) == Some("init") {
+        wizard::run()
+    } else {
+        parse_args(&args).map(Some)
+    };
+    let config = match config {
+        Ok(Some(config)) => config,
+        // The wizard saved its answers instead of mining.
+        Ok(None) => return Ok(()),
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = config.validate() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    let config = Arc::new(config);
 
-struct PatternCache {
-    patterns: FxHashSet<String>,
-}
+    if config.dump_config {
+        print!("{}", toml::to_string(&*config)?);
+        return Ok(());
+    }
 
-impl PatternCache {
-    fn new(patterns: Vec<String>) -> Self {
-        Self {
-            patterns: patterns.into_iter().collect(),
-        }
+    if let Some(addr) = &config.serve {
+        print_warnings(&config);
+        create_private_dir(&config.export_dir, &config)?;
+        return server::run((*config).clone(), addr);
     }
 
-    #[inline(always)]
-    fn contains<'a>(&self, key_id: &'a str) -> Option<&'a str> {
-        if key_id.len() >= 40 {
-            let target_section = &key_id[24..32];
-            if self.patterns.contains(target_section) {
-                return Some(target_section);
+    let mut out = status_output(&config);
+
+    if let Some(path) = &config.audit_log {
+        audit::open(path)?;
+        audit::record(
+            "start",
+            &format!(
+                "\"version\":\"{}\",\"config_hash\":\"{}\",\"total_keys\":{}",
+                env!("CARGO_PKG_VERSION"),
+                state::fnv_hash(&toml::to_string(&*config)?),
+                total_keys_json(&config)
+            ),
+        );
+    }
+    print_warnings(&config);
+
+    let backend = check_backend(&config)?;
+
+    let matcher = build_matcher(&config).map_err(Error::InvalidArgument)?;
+    if config.progress_format == ProgressFormat::Json {
+        let threads = config.threads.unwrap_or_else(rayon::current_num_threads);
+        print_handshake(&config, &*matcher, threads, &mut json_output(&config))?;
+    } else {
+        writeln!(out, "Crypto backend: {}", backend)?;
+        writeln!(
+            out,
+            "Searching for {}, about 1 in {:.0} keys should match",
+            matcher.describe(),
+            1.0 / matcher.match_probability()
+        )?;
+    }
+    if config.progress_format != ProgressFormat::Json
+        && (config.min_pattern_len.is_some() || config.max_pattern_len.is_some())
+    {
+        writeln!(
+            out,
+            "{} patterns are within the length limits",
+            matcher.pattern_count()
+        )?;
+    }
+    if let Some(id) = &config.collide_short_id {
+        if config.progress_format != ProgressFormat::Json {
+            writeln!(
+                out,
+                "Short key ID collision test against {}: expect about 2^32 ({:.0}) attempts. \
+                 For research and demonstration; never trust a short key ID to identify a key.",
+                id,
+                1.0 / matcher.match_probability()
+            )?;
+        }
+    }
+    if config.is_unlimited() && config.progress_format != ProgressFormat::Json {
+        writeln!(
+            out,
+            "No limit on keys checked: running until {}interrupted",
+            if config.match_limit().is_some() {
+                "--max-matches is reached or "
+            } else {
+                ""
             }
+        )?;
+    }
+    let odds = chance_of_any_match(matcher.match_probability(), config.total_keys);
+    if config.odds_warning && !config.is_unlimited() && odds < LOW_ODDS {
+        eprintln!(
+            "Warning: only a {:.2}% chance of any match in {} keys; about {:.0} keys are \
+             needed per match. Raise total_keys, or pass --no-odds-warning to silence this.",
+            odds * 100.0,
+            config.total_keys,
+            1.0 / matcher.match_probability()
+        );
+        audit::warning(&format!(
+            "Only a {:.2}% chance of any match in {} keys",
+            odds * 100.0,
+            config.total_keys
+        ));
+    }
+
+    if config.prefer_common.is_some() && config.progress_format != ProgressFormat::Json {
+        writeln!(out, "Most likely patterns:")?;
+        let patterns = PatternCache::from_config(&config).map_err(Error::InvalidArgument)?;
+        for (pattern, probability) in patterns.patterns_by_probability().iter().take(5) {
+            writeln!(out, "  {} (1 in {:.0})", pattern, 1.0 / probability)?;
         }
-        None
     }
-}
 
-lazy_static! {
-    static ref LOG_MUTEX: Mutex<()> = Mutex::new(());
-    static ref PATTERN_CACHE: PatternCache = PatternCache::new(generate_patterns());
-    static ref FOUND_KEYS: DashSet<String> = DashSet::new();
-}
+    if let Some(watts) = config.watts {
+        if config.progress_format != ProgressFormat::Json {
+            print_cost_estimate(&config, watts, matcher.match_probability(), &mut out)?;
+        }
+    }
 
-#[inline(always)]
-fn generate_key(uid: &UserID) -> Result<(Cert, String)> {
-    let (cert, _) = CertBuilder::new()
-        .add_userid(uid.clone())
-        .set_primary_key_flags(KeyFlags::empty().set_certification().set_signing())
-        .set_cipher_suite(CipherSuite::Cv25519)
-        .add_subkey(
-            KeyFlags::empty()
-                .set_transport_encryption()
-                .set_storage_encryption(),
-            None,
-            CipherSuite::Cv25519,
-        )
-        .generate()?;
+    if let Some(seconds) = config.delay_start {
+        let shown = config.progress_format != ProgressFormat::Json;
+        countdown(seconds, shown, &mut out)?;
+    }
 
-    let key_id = cert.fingerprint().to_hex();
-    Ok((cert, key_id))
-}
+    let stats = Arc::new(Stats::new());
+
+    let checkpoint = match &config.state_file {
+        Some(path) => {
+            let search_hash = state::search_hash(&config).map_err(Error::InvalidArgument)?;
+            state::resume(path, &search_hash, &stats).map_err(Error::InvalidArgument)?;
+            let checked = stats.keys_checked.load(Ordering::Relaxed);
+            if checked > 0 {
+                writeln!(
+                    out,
+                    "Resuming from {} after {} keys",
+                    path.display(),
+                    checked
+                )?;
+            }
 
-fn generate_patterns() -> Vec<String> {
-    let mut patterns = Vec::new();
-    const HEX_WORDS: &[&str] = &[
-        "DEAD", "BEEF", "CAFE", "BABE", "FACE", "FEED", "F00D", "FADE", "ACE0", "BAD0", "DAD0",
-        "DEAF", "DEED", "B00T", "C0DE", "1337", "D00M", "B105", "CA11", "0000", "1111", "2222",
-        "3333", "4444", "5555", "6666", "7777", "8888", "9999", "AAAA", "BBBB", "CCCC", "DDDD",
-        "EEEE", "FFFF", "A0A0", "B1B1", "C2C2", "D3D3", "E4E4", "F5F5", "0F0F", "1E1E", "2D2D",
-        "3C3C", "4B4B", "5A5A",
-    ];
-
-    for w1 in HEX_WORDS {
-        for w2 in HEX_WORDS {
-            patterns.push(format!("{}{}", w1, w2));
+            let path = path.clone();
+            let stats = Arc::clone(&stats);
+            Some(std::thread::spawn(move || {
+                state::checkpoint_loop(&path, &search_hash, &stats)
+            }))
         }
+        None => None,
+    };
+
+    if !config.stdout {
+        create_private_dir(&config.export_dir, &config)?;
     }
 
-    patterns.extend(
-        ["DEADBEEF", "CAFEBABE", "FEEDFACE"]
-            .iter()
-            .map(|&s| s.to_string()),
-    );
+    let stats_clone = Arc::clone(&stats);
+    let config_clone = Arc::clone(&config);
+    let match_probability = matcher.match_probability();
+    let progress = std::thread::spawn(move || {
+        display_progress(config_clone, stats_clone, match_probability);
+    });
 
-    for digit in "0123456789ABCDEF".chars() {
-        patterns.push(iter::repeat(digit).take(8).collect::<String>());
+    #[cfg(feature = "profile")]
+    let profiler = if config.profile {
+        Some(vanity_pgp_miner::profile::start()?)
+    } else {
+        None
+    };
+
+    let show_offsets = config.match_anywhere;
+    let result = mine_keys(Arc::clone(&config), Arc::clone(&stats));
+    stats.done.store(true, Ordering::Relaxed);
+    let _ = progress.join();
+    if let Some(checkpoint) = checkpoint {
+        let _ = checkpoint.join();
+    }
+    if let Err(e) = &result {
+        audit::error(&e.to_string());
     }
+    result?;
 
-    for d1 in "0123456789ABCDEF".chars() {
-        for d2 in "0123456789ABCDEF".chars() {
-            if d1 != d2 {
-                let pair = format!("{}{}", d1, d2);
-                patterns.push(pair.repeat(4));
-            }
-        }
+    #[cfg(feature = "profile")]
+    if let Some(profiler) = profiler {
+        vanity_pgp_miner::profile::write_flamegraph(profiler, PROFILE_OUTPUT.as_ref())?;
+        writeln!(out, "Wrote profile to {}", PROFILE_OUTPUT)?;
     }
 
-    patterns.push("0123456789ABCDEF".chars().cycle().take(8).collect());
-    patterns.push("FEDCBA9876543210".chars().cycle().take(8).collect());
+    print_summary(&stats, show_offsets, &mut out)?;
+    if let Some(id) = &config.collide_short_id {
+        print_collision_report(id, &stats, match_probability, &mut out)?;
+    }
+    audit::record(
+        "finish",
+        &format!(
+            "\"checked\":{},\"found\":{},\"errors\":{},\"elapsed\":{:.1}",
+            stats.keys_checked.load(Ordering::Relaxed),
+            stats.keys_found.load(Ordering::Relaxed),
+            stats.generation_errors.load(Ordering::Relaxed),
+            stats.elapsed().as_secs_f64()
+        ),
+    );
+
+    // With --once or --max-matches, running out of keys before finding
+    // anything is a failure a calling script should see.
+    if config.match_limit().is_some() && stats.keys_found.load(Ordering::Relaxed) == 0 {
+        eprintln!(
+            "No matching key found in {} keys",
+            stats.keys_checked.load(Ordering::Relaxed)
+        );
+        std::process::exit(1);
+    }
 
-    patterns.sort_unstable();
-    patterns.dedup();
-    patterns
+    Ok(())
 }
 
-fn save_key(cert: &Cert, key_id: &str, pattern: &str, index: usize, config: &Config) -> Result<()> {
-    let _lock = LOG_MUTEX.lock();
-
-    let public_path = config.export_dir.join(format!("public_key_{}.asc", index));
-    let mut writer = BufWriter::with_capacity(BUFFER_SIZE, File::create(public_path)?);
-    cert.armored().serialize(&mut writer)?;
-    writer.flush()?;
-
-    let private_path = config.export_dir.join(format!("private_key_{}.asc", index));
-    let mut writer = BufWriter::with_capacity(BUFFER_SIZE, File::create(private_path)?);
-    cert.as_tsk().armored().serialize(&mut writer)?;
-    writer.flush()?;
-
-    let log_path = config.export_dir.join("found_keys.txt");
-    let mut writer = BufWriter::with_capacity(
-        BUFFER_SIZE,
-        fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(log_path)?,
-    );
+/// Shows the config's [`Config::warnings`] on stderr, where they stay clear
+/// of `--stdout` keys and JSON progress, and records them in the audit log.
+fn print_warnings(config: &Config) {
+    for warning in config.warnings() {
+        eprintln!("Warning: {}", warning);
+        audit::warning(&warning);
+    }
+}
+
+/// `--watts`: samples the key rate briefly and prints the expected time,
+/// energy and, with `--price-per-kwh`, electricity cost of one match. Only
+/// a back-of-the-envelope figure: the rate drifts over a run and the
+/// wattage is the user's guess.
+fn print_cost_estimate(
+    config: &Config,
+    watts: f64,
+    match_probability: f64,
+    out: &mut dyn Write,
+) -> Result<()> {
     writeln!(
-        writer,
-        "[{}] {} - Matched pattern: {}",
-        index, key_id, pattern
+        out,
+        "Sampling the key rate for {}s for a cost estimate...",
+        COST_SAMPLE_WINDOW.as_secs()
     )?;
-    writer.flush()?;
-
+    let rate = sample_rate(config, COST_SAMPLE_WINDOW)?;
+    if rate <= 0.0 {
+        writeln!(out, "No keys generated while sampling, so no estimate")?;
+        return Ok(());
+    }
+    let seconds = 1.0 / match_probability / rate;
+    let kwh = watts * seconds / 3_600_000.0;
+    let hours = seconds / 3600.0;
+    let time = if hours < 48.0 {
+        format!("{:.1} hours", hours)
+    } else {
+        format!("{:.0} days", hours / 24.0)
+    };
+    write!(
+        out,
+        "Rough estimate per match at {:.0} keys/s and {} W: {}, {:.3} kWh",
+        rate, watts, time, kwh
+    )?;
+    if let Some(price) = config.price_per_kwh {
+        write!(out, ", about {:.2} in electricity", kwh * price)?;
+    }
+    writeln!(out)?;
     Ok(())
 }
 
-fn mine_keys(config: Arc<Config>, stats: Arc<Stats>) -> Result<()> {
-    let uid = UserID::from(format!("{} <{}>", config.name, config.email));
-    let pool = rayon::ThreadPoolBuilder::new()
-        .num_threads(rayon::current_num_threads())
-        .stack_size(THREAD_STACK_SIZE)
-        .build()
-        .unwrap();
-
-    pool.install(|| {
-        (0..config.total_keys)
-            .par_bridge()
-            .try_for_each(|_| -> Result<()> {
-                let current = stats.keys_checked.load(Ordering::Relaxed);
-                if current >= config.total_keys {
-                    return Ok(());
-                }
-
-                if let Ok((cert, key_id)) = generate_key(&uid) {
-                    if let Some(pattern) = PATTERN_CACHE.contains(&key_id) {
-                        if FOUND_KEYS.insert(key_id.clone()) {
-                            let found = stats.keys_found.fetch_add(1, Ordering::Relaxed);
-                            println!("\nMATCH FOUND! Key: {} Pattern: {}", key_id, pattern);
-                            if let Err(e) = save_key(&cert, &key_id, pattern, found, &config) {
-                                eprintln!("Error saving key: {}", e);
-                            }
-                        }
-                    }
-                    stats.keys_checked.fetch_add(1, Ordering::Relaxed);
-                }
-                Ok(())
-            })
-    })?;
-
+/// `--collide-short-id`: how long the collision took against what chance
+/// predicts. Attempts needed follow a geometric distribution, so one run
+/// can easily take several times the expectation or a small fraction of
+/// it.
+fn print_collision_report(
+    id: &str,
+    stats: &Stats,
+    match_probability: f64,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let checked = stats.keys_checked.load(Ordering::Relaxed);
+    let expected = 1.0 / match_probability;
+    if stats.keys_found.load(Ordering::Relaxed) == 0 {
+        writeln!(
+            out,
+            "No collision with short key ID {} after {} attempts ({:.2}x the expected {:.0})",
+            id,
+            checked,
+            checked as f64 / expected,
+            expected
+        )?;
+        return Ok(());
+    }
+    let elapsed = stats.elapsed().as_secs_f64();
+    writeln!(
+        out,
+        "Collided with short key ID {} after {} attempts in {:.1}s: {:.2}x the expected {:.0}, \
+         a run this short or shorter happens {:.1}% of the time",
+        id,
+        checked,
+        elapsed,
+        checked as f64 / expected,
+        expected,
+        chance_of_any_match(match_probability, checked) * 100.0
+    )?;
     Ok(())
 }
 
-fn display_progress(total: usize, stats: Arc<Stats>) {
-    let pb = ProgressBar::new(total as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}")
-            .unwrap()
-            .progress_chars("#>-"),
-    );
-
-    while stats.keys_checked.load(Ordering::Relaxed) < total {
-        let current = stats.keys_checked.load(Ordering::Relaxed);
-        let found = stats.keys_found.load(Ordering::Relaxed);
-        pb.set_position(current as u64);
-
-        let elapsed = stats.start_time.elapsed();
-        let speed = if elapsed.as_secs() > 0 {
-            current as u64 / elapsed.as_secs()
-        } else {
-            0
-        };
-
-        pb.set_message(format!("({}/s) | Found: {}", speed, found));
-        std::thread::sleep(Duration::from_millis(PROGRESS_UPDATE_MS));
+/// `--delay-start`: waits `seconds`, counting down on one line if `shown`.
+/// It runs before the clock starts and before any keys or state are
+/// written, so Ctrl-C here ends the process with no keys to clean up. The
+/// `--audit-log` start record is already written by then and is left
+/// without a finish record.
+fn countdown(seconds: u64, shown: bool, out: &mut dyn Write) -> Result<()> {
+    for remaining in (1..=seconds).rev() {
+        if shown {
+            write!(out, "\rStarting in {}s ", remaining)?;
+            out.flush()?;
+        }
+        std::thread::sleep(Duration::from_secs(1));
+    }
+    if shown && seconds > 0 {
+        writeln!(out, "\rStarting now    ")?;
     }
+    Ok(())
+}
 
-    pb.finish_with_message("Done!");
+/// The `coordinate` subcommand: serves `--coordinator` workers on an
+/// address until they have found the given number of matches between them.
+fn coordinate(args: &[String]) -> Result<()> {
+    let (Some(addr), Some(target)) = (
+        args.first(),
+        args.get(1).and_then(|target| target.parse::<usize>().ok()),
+    ) else {
+        eprintln!("Usage: vanity-pgp-miner coordinate <address:port> <matches>");
+        std::process::exit(1);
+    };
+    #[cfg(feature = "coordinator")]
+    {
+        vanity_pgp_miner::coordinator::serve(addr, target.max(1))?;
+        Ok(())
+    }
+    #[cfg(not(feature = "coordinator"))]
+    {
+        let _ = (addr, target);
+        Err(Error::InvalidOperation(
+            "coordinate requires building with --features coordinator".into(),
+        )
+        .into())
+    }
 }
 
-fn main() -> Result<()> {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 3 || args.len() > 4 {
-        eprintln!(
-            "Usage: {} \"Your Name\" \"your.email@example.com\" [total_keys]",
-            args[0]
+/// `--version`: the crate version with the crypto backend, allocator and
+/// enabled features, which all affect behaviour or speed, as one line or
+/// as JSON for bug reports and scripts.
+fn version(json: bool) {
+    let backend = sequoia_openpgp::crypto::backend();
+    let allocator = if cfg!(target_env = "msvc") {
+        "mimalloc"
+    } else {
+        "jemalloc"
+    };
+    let features: Vec<&str> = FEATURES
+        .iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| *name)
+        .collect();
+    if json {
+        println!(
+            "{{\"version\":\"{}\",\"sequoia\":\"{}\",\"backend\":{},\"allocator\":\"{}\",\
+             \"features\":[{}]}}",
+            env!("CARGO_PKG_VERSION"),
+            sequoia_openpgp::VERSION,
+            audit::json_string(&backend),
+            allocator,
+            features
+                .iter()
+                .map(|feature| format!("\"{}\"", feature))
+                .collect::<Vec<_>>()
+                .join(",")
+        );
+    } else {
+        println!(
+            "vanity-pgp-miner {} (sequoia-openpgp {}, {}, {}, features: {})",
+            env!("CARGO_PKG_VERSION"),
+            sequoia_openpgp::VERSION,
+            backend,
+            allocator,
+            if features.is_empty() {
+                "none".to_string()
+            } else {
+                features.join(", ")
+            }
         );
-        std::process::exit(1);
     }
+}
 
-    let total_keys = args
-        .get(3)
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(DEFAULT_TOTAL_KEYS);
+/// The `verify` subcommand: re-checks a finished export directory and exits
+/// with 1 if anything in it is wrong.
+fn verify(dir: &Path) -> Result<()> {
+    let report = verify_export_dir(dir)?;
+    for problem in &report.problems {
+        println!("{}", problem);
+    }
+    println!(
+        "Checked {} matches and {} key files in {}: {} problems",
+        report.entries,
+        report.files,
+        dir.display(),
+        report.problems.len()
+    );
+    if !report.problems.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
 
-    let config = Arc::new(Config {
-        name: args[1].clone(),
-        email: args[2].clone(),
-        export_dir: PathBuf::from("./gpg_export"),
-        total_keys,
-    });
+fn check_usage() -> ! {
+    eprintln!(
+        "Usage: vanity-pgp-miner check <key_file> <pattern> [--anywhere] [--group <n>] \
+         [--id-form <form>] [--match-key <key>] [--transform <t>]"
+    );
+    std::process::exit(1);
+}
 
-    let stats = Arc::new(Stats {
-        keys_checked: AtomicUsize::new(0),
-        keys_found: AtomicUsize::new(0),
-        start_time: Instant::now(),
-    });
+/// The `check` subcommand: tests whether an existing key, e.g. one made by
+/// another tool, matches a pattern the way a run with the same matching
+/// options would see it, and exits with 1 if it doesn't.
+fn check(args: &[String]) -> Result<()> {
+    let (Some(path), Some(pattern)) = (args.first(), args.get(1)) else {
+        check_usage();
+    };
+    let mut config = Config {
+        patterns: Some(vec![pattern.clone()]),
+        ..Config::default()
+    };
+    let mut options = args[2..].iter();
+    while let Some(option) = options.next() {
+        let parsed = match option.as_str() {
+            "--anywhere" => {
+                config.match_anywhere = true;
+                Ok(())
+            }
+            flag => {
+                let Some(value) = options.next() else {
+                    check_usage()
+                };
+                match flag {
+                    "--group" => value
+                        .parse()
+                        .map(|group| config.group = Some(group))
+                        .map_err(|_| format!("Invalid value for --group: {}", value)),
+                    "--id-form" => value.parse().map(|form| config.id_form = form),
+                    "--match-key" => value.parse().map(|key| config.match_key = key),
+                    "--transform" => value.parse().map(|kind| config.transform = kind),
+                    _ => check_usage(),
+                }
+            }
+        };
+        parsed.map_err(Error::InvalidArgument)?;
+    }
 
-    fs::create_dir_all(&config.export_dir)?;
+    let matcher = build_matcher(&config).map_err(Error::InvalidArgument)?;
+    let cert = Cert::from_file(path)?;
+    let Some(identifier) = key_identifier(&cert, config.match_key, config.id_form) else {
+        return Err(Error::InvalidArgument(format!(
+            "{} has no key to match with --match-key",
+            path
+        ))
+        .into());
+    };
+    let identifier = build_transform(config.transform).apply(&identifier);
+    match matcher.find(&identifier) {
+        Some((offset, pattern)) => {
+            println!(
+                "{}: {} matches {} at offset {}",
+                path, identifier, pattern, offset
+            );
+            Ok(())
+        }
+        None => {
+            println!(
+                "{}: {} does not match {}",
+                path,
+                identifier,
+                matcher.describe()
+            );
+            std::process::exit(1);
+        }
+    }
+}
 
-    let stats_clone = Arc::clone(&stats);
-    let total = config.total_keys;
-    std::thread::spawn(move || {
-        display_progress(total, stats_clone);
-    });
+/// The `list-patterns` subcommand: prints the patterns a run would search
+/// for, one per line in sorted order, so the output can be diffed across
+/// versions or config changes.
+fn list_patterns(args: &[String]) -> Result<()> {
+    let mut config = Config::default();
+    let mut patterns = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args.next();
+        match (arg.as_str(), value) {
+            ("--config", Some(path)) => {
+                config = load_config(path).map_err(Error::InvalidArgument)?;
+            }
+            ("--pattern", Some(pattern)) => patterns.push(pattern.clone()),
+            _ => {
+                eprintln!(
+                    "Usage: vanity-pgp-miner list-patterns [--config <file>] [--pattern <hex>]..."
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+    if !patterns.is_empty() {
+        config.patterns = Some(patterns);
+    }
 
-    mine_keys(config, stats)?;
+    let cache = PatternCache::from_config(&config).map_err(Error::InvalidArgument)?;
+    let mut out = std::io::stdout().lock();
+    for pattern in cache.sorted_patterns() {
+        writeln!(out, "{}", pattern)?;
+    }
+    Ok(())
+}
 
+/// The `info` subcommand: what the built-in pattern set contains and how
+/// likely it is to match, to help decide whether to bring your own.
+fn info() -> Result<()> {
+    let categories = pattern_categories();
+    let patterns = generate_patterns();
+    println!("Built-in patterns: {}", patterns.len());
+    for (category, members) in &categories {
+        println!("  {:<14} {}", category, members.len());
+    }
+    println!("(categories overlap; duplicates are counted once in the total)");
+
+    for anywhere in [false, true] {
+        let cache =
+            PatternCache::new(patterns.clone(), anywhere).map_err(Error::InvalidArgument)?;
+        println!(
+            "Chance per key {}: 1 in {:.0}",
+            if anywhere {
+                "with --anywhere"
+            } else {
+                "at the fixed position"
+            },
+            1.0 / cache.match_probability()
+        );
+    }
     Ok(())
 }
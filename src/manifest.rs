@@ -0,0 +1,83 @@
+//! `manifest.json`: a machine-readable index of every key saved into an
+//! export directory, kept across runs so that repeated runs into one
+//! directory add to it instead of overwriting earlier keys.
+
+use crate::export::write_atomically;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+const MANIFEST_NAME: &str = "manifest.json";
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub keys: Vec<ManifestEntry>,
+}
+
+/// A saved key. `fingerprint` and `pattern` are in the `--hex-case` of the
+/// run that saved it, so a directory filled by runs with different cases
+/// has both; compare them case-insensitively. Numbering only goes by
+/// `index`.
+#[derive(Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub index: usize,
+    pub fingerprint: String,
+    pub pattern: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<String>,
+    /// The files written for the key, relative to the export directory
+    /// unless they went to `--fallback-dir`.
+    pub files: Vec<PathBuf>,
+}
+
+impl Manifest {
+    /// Loads the manifest in `dir`, or an empty one if there is none. A
+    /// manifest that can't be parsed is renamed out of the way with a
+    /// warning, so one bad write can't stop every later run.
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        let path = dir.join(MANIFEST_NAME);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+        match serde_json::from_str(&text) {
+            Ok(manifest) => Ok(manifest),
+            Err(e) => {
+                let backup = dir.join(format!(
+                    "{}.corrupt-{}",
+                    MANIFEST_NAME,
+                    Local::now().format("%Y%m%d%H%M%S")
+                ));
+                fs::rename(&path, &backup)?;
+                eprintln!(
+                    "Warning: {} is corrupt ({}); moved it to {} and starting a new one",
+                    path.display(),
+                    e,
+                    backup.display()
+                );
+                Ok(Self::default())
+            }
+        }
+    }
+
+    /// The index after the highest one recorded, where a new run's keys
+    /// should start so earlier files aren't overwritten.
+    pub fn next_index(&self) -> usize {
+        self.keys
+            .iter()
+            .map(|entry| entry.index + 1)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Writes the manifest into `dir` under a temporary name and renames it
+    /// into place, so a crash mid-write leaves the previous version intact.
+    pub fn write(&self, dir: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        write_atomically(&dir.join(MANIFEST_NAME), json + "\n")
+    }
+}
@@ -0,0 +1,1208 @@
+use crate::{
+    config::Config,
+    transform::{build_transform, Hex, Transform},
+};
+use rustc_hash::FxHashSet;
+use std::{fs, iter, path::Path, str::FromStr};
+
+pub const FINGERPRINT_HEX_LEN: usize = 40;
+pub const PATTERN_LEN: usize = 8;
+/// Bounds on `--pattern` lengths. At the fixed position a pattern starts
+/// where the usual window does, so the longest still fits a fingerprint.
+pub const MIN_PATTERN_LEN: usize = 4;
+pub const MAX_PATTERN_LEN: usize = 16;
+pub const FIXED_OFFSET: usize = 24;
+/// Digits per group in GnuPG's spaced fingerprint display.
+pub const GROUP_LEN: usize = 4;
+const HEX_RADIX: u32 = 16;
+
+/// Windows scoring below this many bits per character look "weak" (long
+/// runs, two-symbol alternations) even though only the public fingerprint
+/// is patterned.
+pub const LOW_VISUAL_ENTROPY: f64 = 1.0;
+
+/// Where the fixed window starts in an identifier `len` hex digits long:
+/// digits 24-31 of a fingerprint, which are also digits 0-7 of the long
+/// key ID, and the whole of a short key ID.
+#[inline(always)]
+pub fn fixed_offset(len: usize) -> usize {
+    len.saturating_sub(FINGERPRINT_HEX_LEN - FIXED_OFFSET)
+}
+
+/// Where the fixed window starts: at the start of `--group`, or at the
+/// default [`fixed_offset`]. `None` if a `width`-digit window there would
+/// run past the end of the identifier.
+#[inline(always)]
+fn window_start(group: Option<usize>, len: usize, width: usize) -> Option<usize> {
+    let offset = match group {
+        Some(group) => (group - 1) * GROUP_LEN,
+        None => fixed_offset(len),
+    };
+    (offset + width <= len).then_some(offset)
+}
+
+/// Decides whether a fingerprint is a vanity match.
+pub trait Matcher: Send + Sync {
+    /// Returns the offset and text of the matching part of `fingerprint`.
+    fn find<'a>(&self, fingerprint: &'a str) -> Option<(usize, &'a str)>;
+
+    /// Chance that a uniformly random fingerprint matches.
+    fn match_probability(&self) -> f64;
+
+    /// How many distinct patterns are being searched for.
+    fn pattern_count(&self) -> usize;
+
+    /// Short description of what is being searched for, for the banner.
+    fn describe(&self) -> String;
+
+    /// What made `matched` a match, for matchers where the matched text
+    /// alone doesn't show it.
+    fn label(&self, _matched: &str) -> Option<String> {
+        None
+    }
+}
+
+/// Builds the matcher a run described by `config` searches with.
+pub fn build_matcher(config: &Config) -> Result<Box<dyn Matcher>, String> {
+    if let Some(predicate) = &config.whole_fingerprint_predicate {
+        return Ok(Box::new(predicate.parse::<WholePredicate>()?));
+    }
+    if let Some(path) = &config.fingerprint_set {
+        return Ok(Box::new(FingerprintSet::load(path)?));
+    }
+    Ok(match &config.mask {
+        Some(mask) => {
+            let transform = build_transform(config.transform);
+            Box::new(
+                MaskMatcher::new(
+                    mask,
+                    config.match_anywhere,
+                    transform.output_len(config.match_id_form().hex_len()),
+                    &*transform,
+                )?
+                .at_group(config.group),
+            )
+        }
+        None if !config.dont_care.is_empty() => {
+            let patterns = PatternCache::from_config(config)?;
+            Box::new(
+                WindowMaskMatcher::new(
+                    patterns.patterns.iter().map(String::as_str),
+                    &config.dont_care,
+                )?
+                .at_group(config.group),
+            )
+        }
+        None => Box::new(PatternCache::from_config(config)?),
+    })
+}
+
+pub struct PatternCache {
+    patterns: FxHashSet<String>,
+    /// The distinct pattern lengths, shortest first, so `find` only slices
+    /// windows some pattern could fill.
+    lengths: Vec<usize>,
+    anywhere: bool,
+    /// Length of the identifiers being matched, which sets how many
+    /// windows anywhere mode compares.
+    id_len: usize,
+    /// The 1-based `--group` the fixed window starts at, if set.
+    group: Option<usize>,
+    /// Symbols per position of the identifiers, 16 unless `--transform`
+    /// changes the alphabet.
+    radix: u32,
+}
+
+impl PatternCache {
+    /// Builds the set from `patterns`, which must each be
+    /// `MIN_PATTERN_LEN` to `MAX_PATTERN_LEN` hex digits. Lowercase digits
+    /// are accepted and uppercased to match the fingerprint. Anything else
+    /// could never match, so it is an error naming every offending pattern
+    /// rather than a silently dead entry.
+    pub fn new(patterns: Vec<String>, anywhere: bool) -> Result<Self, String> {
+        Self::new_in(patterns, anywhere, &Hex)
+    }
+
+    /// Like [`PatternCache::new`], for identifiers rewritten by `transform`:
+    /// patterns must be in its alphabet instead of hex.
+    pub fn new_in(
+        patterns: Vec<String>,
+        anywhere: bool,
+        transform: &dyn Transform,
+    ) -> Result<Self, String> {
+        let invalid: Vec<String> = patterns
+            .iter()
+            .filter_map(|pattern| {
+                if !(MIN_PATTERN_LEN..=MAX_PATTERN_LEN).contains(&pattern.len()) {
+                    Some(format!(
+                        "{:?} ({} characters, expected {}-{})",
+                        pattern,
+                        pattern.len(),
+                        MIN_PATTERN_LEN,
+                        MAX_PATTERN_LEN
+                    ))
+                } else if !pattern.chars().all(|c| transform.is_symbol(c)) {
+                    Some(format!("{:?} (not {})", pattern, transform.name()))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        if !invalid.is_empty() {
+            return Err(format!("Invalid patterns: {}", invalid.join(", ")));
+        }
+
+        let mut cache = Self {
+            patterns: patterns
+                .into_iter()
+                .map(|pattern| pattern.to_ascii_uppercase())
+                .collect(),
+            anywhere,
+            id_len: transform.output_len(FINGERPRINT_HEX_LEN),
+            group: None,
+            radix: transform.radix(),
+            lengths: Vec::new(),
+        };
+        cache.update_lengths();
+        Ok(cache)
+    }
+
+    fn update_lengths(&mut self) {
+        let mut lengths: Vec<usize> = self.patterns.iter().map(String::len).collect();
+        lengths.sort_unstable();
+        lengths.dedup();
+        self.lengths = lengths;
+    }
+
+    /// Keeps only the patterns from `min` to `max` characters long,
+    /// returning how many were removed.
+    pub fn retain_lengths(&mut self, min: usize, max: usize) -> usize {
+        let before = self.patterns.len();
+        self.patterns
+            .retain(|pattern| (min..=max).contains(&pattern.len()));
+        self.update_lengths();
+        before - self.patterns.len()
+    }
+
+    /// Builds the pattern set a run described by `config` searches for:
+    /// every `pattern_groups` pattern, or the `--pattern` list if one was
+    /// given, otherwise the built-ins.
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let patterns = if config.pattern_groups.is_empty() {
+            config.pattern_list().unwrap_or_else(generate_patterns)
+        } else {
+            let mut patterns: Vec<String> = config
+                .pattern_groups
+                .iter()
+                .flat_map(|group| group.patterns.iter().map(|p| p.to_ascii_uppercase()))
+                .collect();
+            patterns.sort();
+            patterns.dedup();
+            patterns
+        };
+        let transform = build_transform(config.transform);
+        let mut cache = Self::new_in(patterns, config.match_anywhere, &*transform)?;
+        cache.id_len = transform.output_len(config.match_id_form().hex_len());
+        cache.group = config.group;
+        if config.min_pattern_len.is_some() || config.max_pattern_len.is_some() {
+            let min = config.min_pattern_len.unwrap_or(MIN_PATTERN_LEN);
+            let max = config.max_pattern_len.unwrap_or(MAX_PATTERN_LEN);
+            cache.retain_lengths(min, max);
+            if cache.is_empty() {
+                return Err(format!(
+                    "No patterns are between {} and {} characters long",
+                    min, max
+                ));
+            }
+        }
+        if let Some(factor) = config.prefer_common {
+            cache.retain_likeliest(factor);
+        }
+        Ok(cache)
+    }
+
+    pub fn len(&self) -> usize {
+        self.patterns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Approximate heap footprint of the set: each hash table slot holds a
+    /// `String` plus a control byte, and each pattern's text is allocated
+    /// separately.
+    pub fn memory_bytes(&self) -> usize {
+        let slots = self.patterns.capacity() * (std::mem::size_of::<String>() + 1);
+        let text: usize = self.patterns.iter().map(String::capacity).sum();
+        slots + text
+    }
+
+    /// Number of windows of each fingerprint a `len`-digit pattern is
+    /// compared with.
+    pub fn windows(&self, len: usize) -> usize {
+        if self.anywhere {
+            (self.id_len + 1).saturating_sub(len)
+        } else {
+            1
+        }
+    }
+
+    /// Chance that `pattern` shows up in a uniformly random fingerprint.
+    pub fn pattern_probability(&self, pattern: &str) -> f64 {
+        probability(self.radix, pattern.len(), 1, self.windows(pattern.len()))
+    }
+
+    /// Every pattern in the set, sorted, so listings are stable across runs
+    /// and versions.
+    pub fn sorted_patterns(&self) -> Vec<&str> {
+        let mut patterns: Vec<&str> = self.patterns.iter().map(String::as_str).collect();
+        patterns.sort_unstable();
+        patterns
+    }
+
+    /// Patterns ordered from most to least likely, alphabetically on ties.
+    pub fn patterns_by_probability(&self) -> Vec<(&str, f64)> {
+        let mut ranked: Vec<(&str, f64)> = self
+            .patterns
+            .iter()
+            .map(|pattern| (pattern.as_str(), self.pattern_probability(pattern)))
+            .collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        ranked
+    }
+
+    /// Drops every pattern more than `factor` times less likely than the
+    /// most likely one, returning how many were removed. A `factor` of 16
+    /// keeps patterns up to one hex digit longer than the shortest.
+    pub fn retain_likeliest(&mut self, factor: f64) -> usize {
+        let best = self
+            .patterns
+            .iter()
+            .map(|pattern| self.pattern_probability(pattern))
+            .fold(0.0, f64::max);
+        let before = self.patterns.len();
+        let likeliest: FxHashSet<String> = self
+            .patterns
+            .iter()
+            .filter(|pattern| self.pattern_probability(pattern) >= best / factor * (1.0 - 1e-9))
+            .cloned()
+            .collect();
+        self.patterns = likeliest;
+        self.update_lengths();
+        before - self.patterns.len()
+    }
+}
+
+impl Matcher for PatternCache {
+    /// Returns the offset and text of the first window that matches a
+    /// pattern, either at the fixed position or, in anywhere mode, at any
+    /// offset in the fingerprint. Where patterns of several lengths could
+    /// match at the same offset, the shortest wins.
+    #[inline(always)]
+    fn find<'a>(&self, key_id: &'a str) -> Option<(usize, &'a str)> {
+        if self.anywhere {
+            (0..key_id.len()).find_map(|offset| {
+                self.lengths.iter().find_map(|&len| {
+                    let window = key_id.get(offset..offset + len)?;
+                    self.patterns.contains(window).then_some((offset, window))
+                })
+            })
+        } else {
+            self.lengths.iter().find_map(|&len| {
+                let offset = window_start(self.group, key_id.len(), len)?;
+                let target_section = &key_id[offset..offset + len];
+                self.patterns
+                    .contains(target_section)
+                    .then_some((offset, target_section))
+            })
+        }
+    }
+
+    /// Combines the lengths as independent events, like the windows.
+    fn match_probability(&self) -> f64 {
+        let miss: f64 = self
+            .lengths
+            .iter()
+            .map(|&len| {
+                let count = self.patterns.iter().filter(|p| p.len() == len).count();
+                1.0 - probability(self.radix, len, count, self.windows(len))
+            })
+            .product();
+        1.0 - miss
+    }
+
+    fn pattern_count(&self) -> usize {
+        self.patterns.len()
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "{} patterns (~{:.1} KiB)",
+            self.patterns.len(),
+            self.memory_bytes() as f64 / 1024.0
+        )
+    }
+}
+
+/// Tells which of the configured `pattern_groups` a matched pattern belongs
+/// to, so each group's matches can be counted against its own target.
+pub struct PatternGroups {
+    groups: Vec<(String, FxHashSet<String>, usize)>,
+}
+
+impl PatternGroups {
+    pub fn from_config(config: &Config) -> Self {
+        let groups = config
+            .pattern_groups
+            .iter()
+            .map(|group| {
+                let patterns = group
+                    .patterns
+                    .iter()
+                    .map(|pattern| pattern.to_ascii_uppercase())
+                    .collect();
+                (group.name.clone(), patterns, group.max_matches)
+            })
+            .collect();
+        Self { groups }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// The name and target of every group `pattern` is in, in config
+    /// order. A pattern listed in several groups counts towards the first
+    /// one that still needs matches.
+    pub fn containing<'a>(&'a self, pattern: &'a str) -> impl Iterator<Item = (&'a str, usize)> {
+        self.groups
+            .iter()
+            .filter(move |(_, patterns, _)| patterns.contains(pattern))
+            .map(|(name, _, max_matches)| (name.as_str(), *max_matches))
+    }
+}
+
+/// Matches a hex mask such as `DE?DBE?F`, where `?` stands for any digit,
+/// or a compact spec such as `DEAD[0-7]{4}`; see [`parse_mask`]. Comparing
+/// fixed positions directly is much cheaper than a regex, and far smaller
+/// than the tens of thousands of patterns a broad spec stands for.
+pub struct MaskMatcher {
+    /// The symbols allowed at each position, as a bitmap over ASCII.
+    mask: Vec<u128>,
+    /// The spec as given, for the banner.
+    spec: String,
+    anywhere: bool,
+    id_len: usize,
+    group: Option<usize>,
+    radix: u32,
+}
+
+impl MaskMatcher {
+    /// Parses `mask`, which must be `?` or symbols of `transform`'s
+    /// alphabet (hex by default). At the fixed position it has to fit the
+    /// usual window; anywhere it can be as long as the `id_len`-symbol
+    /// identifiers being matched.
+    pub fn new(
+        mask: &str,
+        anywhere: bool,
+        id_len: usize,
+        transform: &dyn Transform,
+    ) -> Result<Self, String> {
+        let spec = mask;
+        let mask = parse_mask(spec, transform)?;
+        let max_len = if anywhere { id_len } else { PATTERN_LEN };
+        if mask.is_empty() || mask.len() > max_len {
+            return Err(format!(
+                "Mask {:?} must be 1 to {} characters long",
+                spec, max_len
+            ));
+        }
+
+        Ok(Self {
+            mask,
+            spec: spec.to_string(),
+            anywhere,
+            id_len,
+            group: None,
+            radix: transform.radix(),
+        })
+    }
+
+    /// Moves the fixed window to start at the 1-based four-digit `group`.
+    pub fn at_group(self, group: Option<usize>) -> Self {
+        Self { group, ..self }
+    }
+
+    /// How many characters of the identifier the mask covers.
+    pub fn width(&self) -> usize {
+        self.mask.len()
+    }
+
+    fn windows(&self) -> usize {
+        if self.anywhere {
+            self.id_len - self.mask.len() + 1
+        } else {
+            1
+        }
+    }
+
+    #[inline(always)]
+    fn matches_at(&self, fingerprint: &[u8], offset: usize) -> bool {
+        self.mask
+            .iter()
+            .zip(&fingerprint[offset..])
+            .all(|(allowed, &have)| {
+                allowed
+                    .checked_shr(have as u32)
+                    .is_some_and(|bit| bit & 1 == 1)
+            })
+    }
+}
+
+impl Matcher for MaskMatcher {
+    #[inline(always)]
+    fn find<'a>(&self, fingerprint: &'a str) -> Option<(usize, &'a str)> {
+        let bytes = fingerprint.as_bytes();
+        let len = self.mask.len();
+        if bytes.len() < len.max(PATTERN_LEN) {
+            return None;
+        }
+
+        let mut offsets = if self.anywhere {
+            0..bytes.len() - len + 1
+        } else {
+            let offset = window_start(self.group, bytes.len(), len)?;
+            offset..offset + 1
+        };
+        offsets
+            .find(|&offset| self.matches_at(bytes, offset))
+            .map(|offset| (offset, &fingerprint[offset..offset + len]))
+    }
+
+    fn match_probability(&self) -> f64 {
+        let per_window: f64 = self
+            .mask
+            .iter()
+            .map(|allowed| allowed.count_ones() as f64 / self.radix as f64)
+            .product();
+        1.0 - (1.0 - per_window).powi(self.windows() as i32)
+    }
+
+    fn pattern_count(&self) -> usize {
+        1
+    }
+
+    fn describe(&self) -> String {
+        format!("mask {}", self.spec)
+    }
+}
+
+/// Parses a mask spec into the set of symbols allowed at each position.
+/// Each position is a symbol of `transform`'s alphabet, `?` for any
+/// symbol, or a class in brackets of symbols and ranges such as `[0-7]` or
+/// `[ACE]`, negated with `^` as in `[^0]`. Any of these can be followed by
+/// `{n}` to repeat it, so `DEAD[0-9A-F]{4}` is `DEAD????`. Ranges run in
+/// ASCII order and keep only the alphabet's symbols, so `[0-F]` is every
+/// hex digit.
+pub fn parse_mask(spec: &str, transform: &dyn Transform) -> Result<Vec<u128>, String> {
+    let any = (0..128u8)
+        .filter(|&byte| !byte.is_ascii_lowercase() && transform.is_symbol(byte as char))
+        .fold(0u128, |set, byte| set | (1 << byte));
+    let symbol = |c: char| -> Result<u8, String> {
+        if c.is_ascii() && transform.is_symbol(c) {
+            Ok(c.to_ascii_uppercase() as u8)
+        } else {
+            Err(format!(
+                "Invalid character {:?} in mask {:?}, expected {}, '?', [...] or {{n}}",
+                c,
+                spec,
+                transform.name()
+            ))
+        }
+    };
+
+    let mut mask = Vec::new();
+    let mut chars = spec.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '?' => mask.push(any),
+            '[' => {
+                let negated = chars.next_if_eq(&'^').is_some();
+                let mut class = 0u128;
+                loop {
+                    let first = match chars.next() {
+                        Some(']') => break,
+                        Some(c) => symbol(c)?,
+                        None => return Err(format!("Unclosed [ in mask {:?}", spec)),
+                    };
+                    let last = match chars.next_if_eq(&'-') {
+                        Some(_) => match chars.next() {
+                            Some(c) if c != ']' => symbol(c)?,
+                            _ => return Err(format!("Unfinished range in mask {:?}", spec)),
+                        },
+                        None => first,
+                    };
+                    if last < first {
+                        return Err(format!(
+                            "Range {}-{} in mask {:?} runs backwards",
+                            first as char, last as char, spec
+                        ));
+                    }
+                    class |= (first..=last).fold(0u128, |set, byte| set | (1 << byte));
+                }
+                let class = if negated { any & !class } else { any & class };
+                if class == 0 {
+                    return Err(format!("Empty class in mask {:?}", spec));
+                }
+                mask.push(class);
+            }
+            '{' => {
+                let mut count = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => count.push(c),
+                        None => return Err(format!("Unclosed {{ in mask {:?}", spec)),
+                    }
+                }
+                let count: usize = count
+                    .parse()
+                    .ok()
+                    .filter(|&count| count >= 1)
+                    .ok_or_else(|| format!("Invalid repeat {{{}}} in mask {:?}", count, spec))?;
+                let last = *mask
+                    .last()
+                    .ok_or_else(|| format!("Mask {:?} starts with a repeat", spec))?;
+                // Capped so a typo can't allocate much before the length
+                // check rejects it.
+                mask.extend(iter::repeat(last).take((count - 1).min(FINGERPRINT_HEX_LEN)));
+            }
+            c => mask.push(1 << symbol(c)?),
+        }
+    }
+    Ok(mask)
+}
+
+/// Matches the pattern set at the fixed window while ignoring chosen
+/// "don't care" positions inside it. The window is packed into a `u32`, one
+/// nibble per hex digit, so a match is a single AND plus a set lookup.
+pub struct WindowMaskMatcher {
+    values: FxHashSet<u32>,
+    mask: u32,
+    ignored: usize,
+    group: Option<usize>,
+}
+
+impl WindowMaskMatcher {
+    /// Builds the matcher from `patterns`, all of which must be
+    /// `PATTERN_LEN` hex digits, ignoring the 0-based window `positions`.
+    pub fn new<'a>(
+        patterns: impl Iterator<Item = &'a str>,
+        positions: &[usize],
+    ) -> Result<Self, String> {
+        let mut mask = u32::MAX;
+        for &position in positions {
+            if position >= PATTERN_LEN {
+                return Err(format!(
+                    "Don't-care position {} is outside the {}-digit window",
+                    position, PATTERN_LEN
+                ));
+            }
+            mask &= !(0xF << (4 * (PATTERN_LEN - 1 - position)));
+        }
+
+        let values = patterns
+            .map(|pattern| {
+                pack_window(pattern.as_bytes())
+                    .map(|value| value & mask)
+                    .ok_or_else(|| {
+                        format!("Pattern {:?} is not {} hex digits", pattern, PATTERN_LEN)
+                    })
+            })
+            .collect::<Result<FxHashSet<u32>, String>>()?;
+
+        Ok(Self {
+            values,
+            mask,
+            ignored: mask.count_zeros() as usize / 4,
+            group: None,
+        })
+    }
+
+    /// Moves the window to start at the 1-based four-digit `group`.
+    pub fn at_group(self, group: Option<usize>) -> Self {
+        Self { group, ..self }
+    }
+}
+
+/// Packs eight hex digits into a `u32`, first digit in the top nibble.
+#[inline(always)]
+fn pack_window(window: &[u8]) -> Option<u32> {
+    if window.len() != PATTERN_LEN {
+        return None;
+    }
+    window.iter().try_fold(0u32, |acc, &digit| {
+        let nibble = (digit as char).to_digit(16)?;
+        Some(acc << 4 | nibble)
+    })
+}
+
+impl Matcher for WindowMaskMatcher {
+    #[inline(always)]
+    fn find<'a>(&self, fingerprint: &'a str) -> Option<(usize, &'a str)> {
+        if fingerprint.len() < PATTERN_LEN {
+            return None;
+        }
+
+        let offset = window_start(self.group, fingerprint.len(), PATTERN_LEN)?;
+        let window = &fingerprint[offset..offset + PATTERN_LEN];
+        let value = pack_window(window.as_bytes())?;
+        self.values
+            .contains(&(value & self.mask))
+            .then_some((offset, window))
+    }
+
+    fn match_probability(&self) -> f64 {
+        probability(HEX_RADIX, PATTERN_LEN - self.ignored, self.values.len(), 1)
+    }
+
+    fn pattern_count(&self) -> usize {
+        self.values.len()
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "{} patterns ignoring {} window positions",
+            self.values.len(),
+            self.ignored
+        )
+    }
+}
+
+/// `--whole-fingerprint-predicate`: a built-in condition on all 40 digits
+/// of the fingerprint rather than a window of it, for aesthetics no
+/// pattern can express.
+#[derive(Clone, Copy)]
+pub enum WholePredicate {
+    /// `no-<digit>`: the digit appears nowhere.
+    Without(u8),
+    /// `repeats:<n>`: at least `n` digits are the same as the one before.
+    Repeats(usize),
+    /// `digits-only`: no `A`-`F` anywhere.
+    DigitsOnly,
+}
+
+impl WholePredicate {
+    /// How many digits equal the one before them.
+    fn repeats(fingerprint: &[u8]) -> usize {
+        fingerprint
+            .windows(2)
+            .filter(|pair| pair[0] == pair[1])
+            .count()
+    }
+}
+
+impl FromStr for WholePredicate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid whole-fingerprint predicate {:?}, expected no-<digit>, repeats:<n> \
+                 or digits-only",
+                s
+            )
+        };
+        if s == "digits-only" {
+            return Ok(Self::DigitsOnly);
+        }
+        if let Some(digit) = s.strip_prefix("no-") {
+            let mut chars = digit.chars();
+            return match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii_hexdigit() => {
+                    Ok(Self::Without(c.to_ascii_uppercase() as u8))
+                }
+                _ => Err(invalid()),
+            };
+        }
+        if let Some(count) = s.strip_prefix("repeats:") {
+            let count: usize = count.parse().map_err(|_| invalid())?;
+            if count == 0 || count >= FINGERPRINT_HEX_LEN {
+                return Err(format!(
+                    "repeats:{} is out of range: a fingerprint has 1 to {} repeats",
+                    count,
+                    FINGERPRINT_HEX_LEN - 1
+                ));
+            }
+            return Ok(Self::Repeats(count));
+        }
+        Err(invalid())
+    }
+}
+
+impl Matcher for WholePredicate {
+    #[inline(always)]
+    fn find<'a>(&self, fingerprint: &'a str) -> Option<(usize, &'a str)> {
+        let bytes = fingerprint.as_bytes();
+        let holds = match *self {
+            Self::Without(digit) => !bytes.contains(&digit),
+            Self::Repeats(count) => Self::repeats(bytes) >= count,
+            Self::DigitsOnly => bytes.iter().all(u8::is_ascii_digit),
+        };
+        holds.then_some((0, fingerprint))
+    }
+
+    fn match_probability(&self) -> f64 {
+        let digits = FINGERPRINT_HEX_LEN as i32;
+        match *self {
+            Self::Without(_) => (15.0f64 / 16.0).powi(digits),
+            Self::DigitsOnly => (10.0f64 / 16.0).powi(digits),
+            // Each digit independently repeats the one before with chance
+            // 1/16, so the count is binomial over the 39 pairs.
+            Self::Repeats(count) => {
+                let pairs = FINGERPRINT_HEX_LEN - 1;
+                let p = 1.0 / HEX_RADIX as f64;
+                let mut term = (1.0 - p).powi(pairs as i32);
+                let mut below = 0.0;
+                for k in 0..count {
+                    below += term;
+                    term *= (pairs - k) as f64 / (k + 1) as f64 * p / (1.0 - p);
+                }
+                (1.0 - below).max(0.0)
+            }
+        }
+    }
+
+    fn pattern_count(&self) -> usize {
+        1
+    }
+
+    fn describe(&self) -> String {
+        match *self {
+            Self::Without(digit) => format!("fingerprints without the digit {}", digit as char),
+            Self::Repeats(count) => format!("fingerprints with at least {} repeats", count),
+            Self::DigitsOnly => "fingerprints of digits only".to_string(),
+        }
+    }
+
+    fn label(&self, matched: &str) -> Option<String> {
+        Some(match *self {
+            Self::Without(digit) => format!("no digit {}", digit as char),
+            Self::Repeats(_) => format!("{} repeats", Self::repeats(matched.as_bytes())),
+            Self::DigitsOnly => "digits only".to_string(),
+        })
+    }
+}
+
+/// `--fingerprint-set`: matches only fingerprints listed exactly in a file.
+/// With 2^160 possible fingerprints this is effectively never going to hit,
+/// but some searches are for specific wanted keys rather than a pattern.
+pub struct FingerprintSet {
+    fingerprints: FxHashSet<String>,
+}
+
+impl FingerprintSet {
+    /// Loads one 40-digit hex fingerprint per line from `path`, ignoring
+    /// blank lines, `#` comments and spaces within a fingerprint.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read fingerprint set {}: {}", path.display(), e))?;
+        let mut fingerprints = FxHashSet::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let fingerprint: String = line
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .collect::<String>()
+                .to_ascii_uppercase();
+            if fingerprint.is_empty() {
+                continue;
+            }
+            if fingerprint.len() != FINGERPRINT_HEX_LEN
+                || !fingerprint.chars().all(|c| c.is_ascii_hexdigit())
+            {
+                return Err(format!(
+                    "Fingerprint set {} line {}: expected {} hex digits",
+                    path.display(),
+                    number + 1,
+                    FINGERPRINT_HEX_LEN
+                ));
+            }
+            fingerprints.insert(fingerprint);
+        }
+        if fingerprints.is_empty() {
+            return Err(format!(
+                "Fingerprint set {} has no fingerprints",
+                path.display()
+            ));
+        }
+        Ok(Self { fingerprints })
+    }
+
+    /// The fingerprints, sorted, for hashing the search.
+    pub fn sorted(&self) -> Vec<&str> {
+        let mut fingerprints: Vec<&str> = self.fingerprints.iter().map(String::as_str).collect();
+        fingerprints.sort_unstable();
+        fingerprints
+    }
+}
+
+impl Matcher for FingerprintSet {
+    #[inline(always)]
+    fn find<'a>(&self, fingerprint: &'a str) -> Option<(usize, &'a str)> {
+        self.fingerprints
+            .contains(fingerprint)
+            .then_some((0, fingerprint))
+    }
+
+    fn match_probability(&self) -> f64 {
+        probability(HEX_RADIX, FINGERPRINT_HEX_LEN, self.fingerprints.len(), 1)
+    }
+
+    fn pattern_count(&self) -> usize {
+        self.fingerprints.len()
+    }
+
+    fn describe(&self) -> String {
+        format!("{} exact fingerprints", self.fingerprints.len())
+    }
+}
+
+/// Chance that at least one of `count` distinct patterns of `len` symbols
+/// from a `radix`-symbol alphabet shows up across `windows` windows of a
+/// uniformly random fingerprint, treating the windows as independent.
+pub fn probability(radix: u32, len: usize, count: usize, windows: usize) -> f64 {
+    let per_window = (count as f64 * (radix as f64).powi(-(len as i32))).min(1.0);
+    1.0 - (1.0 - per_window).powi(windows as i32)
+}
+
+/// Chance of at least one match in `attempts` keys that each match with
+/// probability `p`.
+pub fn chance_of_any_match(p: f64, attempts: usize) -> f64 {
+    -(attempts as f64 * (-p).ln_1p()).exp_m1()
+}
+
+/// Shannon entropy of the characters in `window`, in bits per character.
+/// `00000000` scores 0, `DEADBEEF` about 1.9 and eight distinct digits 3.
+pub fn visual_entropy(window: &str) -> f64 {
+    let mut counts = [0usize; 256];
+    for byte in window.bytes() {
+        counts[byte as usize] += 1;
+    }
+
+    let len = window.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// The built-in patterns by category, in the order they were added. The
+/// categories overlap: `AAAAAAAA` is both a word pair and a repeat.
+pub fn pattern_categories() -> Vec<(&'static str, Vec<String>)> {
+    const HEX_WORDS: &[&str] = &[
+        "DEAD", "BEEF", "CAFE", "BABE", "FACE", "FEED", "F00D", "FADE", "ACE0", "BAD0", "DAD0",
+        "DEAF", "DEED", "C0DE", "1337", "B105", "CA11", "0000", "1111", "2222", "3333", "4444",
+        "5555", "6666", "7777", "8888", "9999", "AAAA", "BBBB", "CCCC", "DDDD", "EEEE", "FFFF",
+        "A0A0", "B1B1", "C2C2", "D3D3", "E4E4", "F5F5", "0F0F", "1E1E", "2D2D", "3C3C", "4B4B",
+        "5A5A",
+    ];
+
+    let mut word_pairs = Vec::new();
+    for w1 in HEX_WORDS {
+        for w2 in HEX_WORDS {
+            word_pairs.push(format!("{}{}", w1, w2));
+        }
+    }
+
+    let hexspeak = ["DEADBEEF", "CAFEBABE", "FEEDFACE"]
+        .iter()
+        .map(|&s| s.to_string())
+        .collect();
+
+    let repeats = "0123456789ABCDEF"
+        .chars()
+        .map(|digit| iter::repeat(digit).take(8).collect::<String>())
+        .collect();
+
+    let mut alternations = Vec::new();
+    for d1 in "0123456789ABCDEF".chars() {
+        for d2 in "0123456789ABCDEF".chars() {
+            if d1 != d2 {
+                let pair = format!("{}{}", d1, d2);
+                alternations.push(pair.repeat(4));
+            }
+        }
+    }
+
+    let sequences = vec![
+        "0123456789ABCDEF".chars().cycle().take(8).collect(),
+        "FEDCBA9876543210".chars().cycle().take(8).collect(),
+    ];
+
+    vec![
+        ("word pairs", word_pairs),
+        ("hexspeak", hexspeak),
+        ("repeats", repeats),
+        ("alternations", alternations),
+        ("sequences", sequences),
+    ]
+}
+
+pub fn generate_patterns() -> Vec<String> {
+    let mut patterns: Vec<String> = pattern_categories()
+        .into_iter()
+        .flat_map(|(_, patterns)| patterns)
+        .collect();
+    patterns.sort_unstable();
+    patterns.dedup();
+    patterns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transform::Base32;
+
+    fn strings(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|pattern| pattern.to_string()).collect()
+    }
+
+    /// The error `PatternCache::new` gives for `patterns`, which it must
+    /// refuse.
+    fn rejection(patterns: &[&str]) -> String {
+        PatternCache::new(strings(patterns), false)
+            .err()
+            .expect("invalid patterns were accepted")
+    }
+
+    #[test]
+    fn rejects_patterns_of_the_wrong_length() {
+        assert_eq!(
+            rejection(&["DEA"]),
+            "Invalid patterns: \"DEA\" (3 characters, expected 4-16)"
+        );
+        assert_eq!(
+            rejection(&["DEADBEEFDEADBEEF0"]),
+            "Invalid patterns: \"DEADBEEFDEADBEEF0\" (17 characters, expected 4-16)"
+        );
+        assert_eq!(
+            rejection(&[""]),
+            "Invalid patterns: \"\" (0 characters, expected 4-16)"
+        );
+    }
+
+    #[test]
+    fn rejects_non_hex_patterns() {
+        assert_eq!(
+            rejection(&["DEADBEEG"]),
+            "Invalid patterns: \"DEADBEEG\" (not hex)"
+        );
+        assert_eq!(
+            rejection(&["DEAD BEE"]),
+            "Invalid patterns: \"DEAD BEE\" (not hex)"
+        );
+    }
+
+    #[test]
+    fn names_every_invalid_pattern() {
+        assert_eq!(
+            rejection(&["DEA", "CAFEBABE", "XYZW1234"]),
+            "Invalid patterns: \"DEA\" (3 characters, expected 4-16), \"XYZW1234\" (not hex)"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_masks() {
+        for (spec, message) in [
+            ("DEAD[0-9", "Unclosed [ in mask \"DEAD[0-9\""),
+            ("DEAD[0-9]{4", "Unclosed { in mask \"DEAD[0-9]{4\""),
+            ("DEAD{0}", "Invalid repeat {0} in mask \"DEAD{0}\""),
+            ("{4}DEAD", "Mask \"{4}DEAD\" starts with a repeat"),
+            (
+                "DEAD[9-0]",
+                "Range 9-0 in mask \"DEAD[9-0]\" runs backwards",
+            ),
+        ] {
+            assert_eq!(parse_mask(spec, &Hex).unwrap_err(), message, "{}", spec);
+        }
+        assert_eq!(parse_mask("DEAD[0-9]{4}", &Hex).unwrap().len(), 8);
+    }
+
+    #[test]
+    fn checks_patterns_against_the_transform_alphabet() {
+        // 0, 1, 8 and 9 aren't base32 digits.
+        let error = PatternCache::new_in(strings(&["DEAD0000"]), false, &Base32)
+            .err()
+            .expect("non-base32 pattern was accepted");
+        assert_eq!(error, "Invalid patterns: \"DEAD0000\" (not base32)");
+        assert!(PatternCache::new_in(strings(&["MINER"]), false, &Base32).is_ok());
+    }
+
+    #[test]
+    fn accepts_lowercase_and_uppercases_it() {
+        let cache = PatternCache::new(strings(&["deadbeef", "CafeBabe"]), false).unwrap();
+        assert_eq!(cache.sorted_patterns(), ["CAFEBABE", "DEADBEEF"]);
+    }
+
+    #[test]
+    fn built_in_patterns_are_all_valid() {
+        assert!(PatternCache::new(generate_patterns(), false).is_ok());
+    }
+
+    /// `list-patterns` output for the built-ins. When the set changes on
+    /// purpose, regenerate it with
+    /// `vanity-pgp-miner list-patterns > testdata/builtin_patterns.txt`.
+    const BUILT_IN_SNAPSHOT: &str = include_str!("../testdata/builtin_patterns.txt");
+
+    #[test]
+    fn built_in_patterns_match_the_snapshot() {
+        let patterns = generate_patterns();
+        let snapshot: Vec<&str> = BUILT_IN_SNAPSHOT.lines().collect();
+        let added: Vec<&str> = patterns
+            .iter()
+            .map(String::as_str)
+            .filter(|pattern| !snapshot.contains(pattern))
+            .collect();
+        let removed: Vec<&str> = snapshot
+            .iter()
+            .copied()
+            .filter(|pattern| !patterns.iter().any(|p| p == pattern))
+            .collect();
+        assert!(
+            added.is_empty() && removed.is_empty(),
+            "built-in patterns changed: added {:?}, removed {:?}",
+            added,
+            removed
+        );
+        assert_eq!(patterns, snapshot);
+
+        // list-patterns prints them in the same order.
+        let cache = PatternCache::new(patterns.clone(), false).unwrap();
+        assert_eq!(cache.sorted_patterns(), snapshot);
+    }
+
+    /// A `len`-digit identifier of zeros with `pattern` at `offset`.
+    fn identifier_with(pattern: &str, offset: usize, len: usize) -> String {
+        let mut identifier = "0".repeat(len);
+        identifier.replace_range(offset..offset + pattern.len(), pattern);
+        identifier
+    }
+
+    fn fingerprint_with(pattern: &str, offset: usize) -> String {
+        identifier_with(pattern, offset, FINGERPRINT_HEX_LEN)
+    }
+
+    #[test]
+    fn fixed_window_matches_only_at_digit_24() {
+        let cache = PatternCache::new(strings(&["DEADBEEF"]), false).unwrap();
+        assert_eq!(
+            cache.find(&fingerprint_with("DEADBEEF", 24)),
+            Some((24, "DEADBEEF"))
+        );
+        assert_eq!(cache.find(&fingerprint_with("DEADBEEF", 23)), None);
+        assert_eq!(cache.find(&fingerprint_with("DEADBEEF", 0)), None);
+    }
+
+    #[test]
+    fn fixed_window_follows_the_identifier_length() {
+        let cache = PatternCache::new(strings(&["DEADBEEF"]), false).unwrap();
+        // A long key ID is the last 16 digits, so the window starts it.
+        assert_eq!(fixed_offset(16), 0);
+        assert_eq!(fixed_offset(8), 0);
+        assert_eq!(
+            cache.find(&identifier_with("DEADBEEF", 0, 16)),
+            Some((0, "DEADBEEF"))
+        );
+        assert_eq!(cache.find(&identifier_with("DEADBEEF", 8, 16)), None);
+        assert_eq!(cache.find("DEADBEEF"), Some((0, "DEADBEEF")));
+    }
+
+    #[test]
+    fn group_moves_the_fixed_window() {
+        let mut cache = PatternCache::new(strings(&["DEADBEEF"]), false).unwrap();
+        cache.group = Some(2);
+        assert_eq!(
+            cache.find(&fingerprint_with("DEADBEEF", 4)),
+            Some((4, "DEADBEEF"))
+        );
+        assert_eq!(cache.find(&fingerprint_with("DEADBEEF", 24)), None);
+        cache.group = Some(9);
+        assert_eq!(
+            cache.find(&fingerprint_with("DEADBEEF", 32)),
+            Some((32, "DEADBEEF"))
+        );
+        // Group 10 starts at digit 36, too late for eight digits.
+        cache.group = Some(10);
+        assert_eq!(cache.find(&fingerprint_with("DEADBEEF", 32)), None);
+    }
+
+    #[test]
+    fn anywhere_reports_the_first_offset() {
+        let cache = PatternCache::new(strings(&["DEADBEEF"]), true).unwrap();
+        for offset in [0, 5, 24, 32] {
+            assert_eq!(
+                cache.find(&fingerprint_with("DEADBEEF", offset)),
+                Some((offset, "DEADBEEF"))
+            );
+        }
+        let mut twice = fingerprint_with("DEADBEEF", 30);
+        twice.replace_range(7..15, "DEADBEEF");
+        assert_eq!(cache.find(&twice), Some((7, "DEADBEEF")));
+        assert_eq!(cache.find(&"0".repeat(FINGERPRINT_HEX_LEN)), None);
+    }
+
+    #[test]
+    fn shortest_pattern_wins_at_an_offset() {
+        let cache = PatternCache::new(strings(&["DEADBEEF", "DEAD"]), false).unwrap();
+        assert_eq!(
+            cache.find(&fingerprint_with("DEADBEEF", 24)),
+            Some((24, "DEAD"))
+        );
+        let cache = PatternCache::new(strings(&["BEEF", "DEADBEEF"]), true).unwrap();
+        assert_eq!(
+            cache.find(&fingerprint_with("DEADBEEF", 10)),
+            Some((10, "DEADBEEF"))
+        );
+    }
+
+    #[test]
+    fn mask_offsets() {
+        let fixed = MaskMatcher::new("DE?DBE?F", false, FINGERPRINT_HEX_LEN, &Hex).unwrap();
+        assert_eq!(
+            fixed.find(&fingerprint_with("DEADBEEF", 24)),
+            Some((24, "DEADBEEF"))
+        );
+        assert_eq!(fixed.find(&fingerprint_with("DEADBEEF", 10)), None);
+
+        let anywhere = MaskMatcher::new("DE?DBE?F", true, FINGERPRINT_HEX_LEN, &Hex).unwrap();
+        assert_eq!(
+            anywhere.find(&fingerprint_with("DE0DBE1F", 10)),
+            Some((10, "DE0DBE1F"))
+        );
+        assert_eq!(
+            anywhere.find(&fingerprint_with("DEADBEEF", 32)),
+            Some((32, "DEADBEEF"))
+        );
+    }
+
+    #[test]
+    fn dont_care_offsets() {
+        let matcher = WindowMaskMatcher::new(["DEADBEEF"].into_iter(), &[3, 7]).unwrap();
+        assert_eq!(
+            matcher.find(&fingerprint_with("DEA0BEE1", 24)),
+            Some((24, "DEA0BEE1"))
+        );
+        assert_eq!(
+            matcher
+                .at_group(Some(1))
+                .find(&fingerprint_with("DEADBEEF", 0)),
+            Some((0, "DEADBEEF"))
+        );
+    }
+}
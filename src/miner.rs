@@ -0,0 +1,1170 @@
+use crate::{
+    audit,
+    config::{Config, IdForm, MatchKey, PatternGroup, TransformKind},
+    export::{keyring_fingerprints, KeyWriter},
+    keygen::{
+        check_backend, check_compliance, dump_cert, finalize_cert, generate_key, key_identifier,
+    },
+    matcher::{
+        build_matcher, visual_entropy, PatternGroups, FINGERPRINT_HEX_LEN, LOW_VISUAL_ENTROPY,
+    },
+    schedule::Schedule,
+    score::Scorer,
+    transform::build_transform,
+};
+use dashmap::{DashMap, DashSet};
+use owo_colors::{OwoColorize, Stream, Style};
+use parking_lot::Mutex;
+use rayon::prelude::*;
+use sequoia_openpgp::{
+    packet::{Signature, UserID},
+    Cert, Error, Result,
+};
+use std::{
+    cell::Cell,
+    iter,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::ThreadId,
+    time::{Duration, Instant},
+};
+
+const THREAD_STACK_SIZE: usize = 4 * 1024 * 1024;
+const WARMUP_KEYS_PER_THREAD: usize = 8;
+const PAUSE_POLL: Duration = Duration::from_millis(100);
+const SCHEDULE_POLL: Duration = Duration::from_secs(30);
+const THREAD_COUNT_FLUSH: usize = 64;
+/// Failed generations needed before `--max-error-rate` can stop a run, so
+/// a single early failure can't.
+const MIN_ERRORS_TO_ABORT: usize = 100;
+/// Failed generations in a row after which a worker gives up even under
+/// `--max-error-rate 1`, since nothing is going to succeed.
+const MAX_CONSECUTIVE_FAILURES: usize = 1000;
+
+/// Wall-clock time each batch of keys should take: long enough that
+/// handing out batches costs nothing next to generating keys, short enough
+/// that a pause, a schedule change or the end of the run is noticed soon.
+const TARGET_BATCH_TIME: Duration = Duration::from_millis(50);
+const MAX_BATCH_SIZE: usize = 4096;
+/// Keys generated and thrown away to estimate the first batch size.
+const CALIBRATION_KEYS: usize = 4;
+
+thread_local! {
+    /// Attempts made on this worker not yet added to `Stats::thread_checked`.
+    static THREAD_CHECKED: Cell<usize> = const { Cell::new(0) };
+}
+
+pub struct Stats {
+    pub keys_checked: AtomicUsize,
+    pub keys_found: AtomicUsize,
+    /// Keys that failed to generate. They don't count as checked.
+    pub generation_errors: AtomicUsize,
+    pub match_offsets: Vec<AtomicUsize>,
+    pub done: AtomicBool,
+    /// Set while the writer can't save (e.g. the disk is full), holding
+    /// the workers until it clears.
+    pub paused: AtomicBool,
+    /// Set while the workers wait for `--run-between` to come round.
+    pub sleeping: AtomicBool,
+    /// Set by [`Stats::abort`] to stop the workers early.
+    aborting: AtomicBool,
+    abort_reason: Mutex<Option<String>>,
+    /// Set by [`Stats::stop`].
+    stopping: AtomicBool,
+    /// Keys checked by each worker thread, for spotting uneven scaling.
+    /// Workers count locally and add to this in batches.
+    pub thread_checked: DashMap<ThreadId, usize>,
+    /// Matches counted towards each of the `pattern_groups`, by name.
+    pub group_found: DashMap<String, usize>,
+    /// Matches for each `--identities` user ID.
+    pub identity_found: DashMap<String, usize>,
+    /// Matches for each pattern, as the matcher reported it.
+    pub pattern_found: DashMap<String, usize>,
+    /// The counts a `--state` resume started from. They were checked in
+    /// an earlier run, so rates only count what this run added on top.
+    pub resumed_checked: AtomicUsize,
+    pub resumed_found: AtomicUsize,
+    start_time: Mutex<Instant>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            keys_checked: AtomicUsize::new(0),
+            keys_found: AtomicUsize::new(0),
+            generation_errors: AtomicUsize::new(0),
+            match_offsets: (0..FINGERPRINT_HEX_LEN)
+                .map(|_| AtomicUsize::new(0))
+                .collect(),
+            done: AtomicBool::new(false),
+            paused: AtomicBool::new(false),
+            sleeping: AtomicBool::new(false),
+            aborting: AtomicBool::new(false),
+            abort_reason: Mutex::new(None),
+            stopping: AtomicBool::new(false),
+            thread_checked: DashMap::new(),
+            group_found: DashMap::new(),
+            identity_found: DashMap::new(),
+            pattern_found: DashMap::new(),
+            resumed_checked: AtomicUsize::new(0),
+            resumed_found: AtomicUsize::new(0),
+            start_time: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Time since mining started (or since the warmup finished).
+    pub fn elapsed(&self) -> Duration {
+        self.start_time.lock().elapsed()
+    }
+
+    /// Keys checked by this run, not counting a resumed total.
+    pub fn checked_this_run(&self) -> usize {
+        self.keys_checked
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.resumed_checked.load(Ordering::Relaxed))
+    }
+
+    /// Matches found by this run, not counting a resumed total.
+    pub fn found_this_run(&self) -> usize {
+        self.keys_found
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.resumed_found.load(Ordering::Relaxed))
+    }
+
+    /// Whether `limit` matches have already been found.
+    pub fn limit_reached(&self, limit: Option<usize>) -> bool {
+        limit.is_some_and(|limit| self.keys_found.load(Ordering::Relaxed) >= limit)
+    }
+
+    /// Counts a match unless `limit` has been reached, so workers that hit
+    /// at the same moment can't overshoot `--max-matches`.
+    fn claim_match(&self, limit: Option<usize>) -> bool {
+        self.keys_found
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |found| match limit {
+                Some(limit) if found >= limit => None,
+                _ => Some(found + 1),
+            })
+            .is_ok()
+    }
+
+    /// Takes back a `claim_group_match` for a match that was turned away
+    /// after all.
+    fn unclaim_group_match(&self, group: &str) {
+        if let Some(mut found) = self.group_found.get_mut(group) {
+            *found = found.saturating_sub(1);
+        }
+    }
+
+    /// Whether `group` already has its `limit` matches.
+    fn group_full(&self, group: &str, limit: usize) -> bool {
+        self.group_found
+            .get(group)
+            .is_some_and(|found| *found >= limit)
+    }
+
+    /// Counts a match towards `group` unless it already has `limit`. The
+    /// entry stays locked in between, so concurrent claims can't overshoot.
+    fn claim_group_match(&self, group: &str, limit: usize) -> bool {
+        let mut found = self.group_found.entry(group.to_string()).or_insert(0);
+        if *found >= limit {
+            return false;
+        }
+        *found += 1;
+        true
+    }
+
+    fn identity_full(&self, identity: &str, limit: Option<usize>) -> bool {
+        limit.is_some_and(|limit| {
+            self.identity_found
+                .get(identity)
+                .is_some_and(|found| *found >= limit)
+        })
+    }
+
+    /// Counts a match for `identity` unless it already has `limit`, like
+    /// `claim_group_match`.
+    fn claim_identity_match(&self, identity: &str, limit: Option<usize>) -> bool {
+        let mut found = self.identity_found.entry(identity.to_string()).or_insert(0);
+        if limit.is_some_and(|limit| *found >= limit) {
+            return false;
+        }
+        *found += 1;
+        true
+    }
+
+    /// Whether there are `groups` and every one has its target.
+    pub fn groups_satisfied(&self, groups: &[PatternGroup]) -> bool {
+        !groups.is_empty()
+            && groups
+                .iter()
+                .all(|group| self.group_full(&group.name, group.max_matches))
+    }
+
+    /// Counts a failed key generation. Once enough have failed to judge,
+    /// and they make up more than `max_rate` of all attempts, returns
+    /// `error` to stop the run rather than grinding on without progress.
+    fn generation_failed(&self, error: anyhow::Error, max_rate: f64) -> Result<()> {
+        let errors = self.generation_errors.fetch_add(1, Ordering::Relaxed) + 1;
+        let attempts = errors + self.keys_checked.load(Ordering::Relaxed);
+        if errors >= MIN_ERRORS_TO_ABORT && errors as f64 > attempts as f64 * max_rate {
+            return Err(error.context(format!("{} of {} key generations failed", errors, attempts)));
+        }
+        Ok(())
+    }
+
+    /// Counts one attempt for the calling worker thread.
+    fn count_thread_attempt(&self) {
+        let pending = THREAD_CHECKED.with(|count| {
+            count.set(count.get() + 1);
+            count.get()
+        });
+        if pending >= THREAD_COUNT_FLUSH {
+            self.flush_thread_count();
+        }
+    }
+
+    /// Adds the calling thread's pending attempts to `thread_checked`.
+    fn flush_thread_count(&self) {
+        let pending = THREAD_CHECKED.with(|count| count.replace(0));
+        if pending > 0 {
+            *self
+                .thread_checked
+                .entry(std::thread::current().id())
+                .or_insert(0) += pending;
+        }
+    }
+
+    /// Stops the run from another thread, such as the progress thread's
+    /// `--min-rate` check. Mining ends with an error giving `reason`.
+    pub fn abort(&self, reason: String) {
+        self.abort_reason.lock().get_or_insert(reason);
+        self.aborting.store(true, Ordering::Relaxed);
+    }
+
+    pub fn aborted(&self) -> bool {
+        self.aborting.load(Ordering::Relaxed)
+    }
+
+    /// Ends the run early from another thread without it counting as a
+    /// failure, as when the `--coordinator` has enough matches.
+    pub fn stop(&self) {
+        self.stopping.store(true, Ordering::Relaxed);
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stopping.load(Ordering::Relaxed)
+    }
+
+    /// Restarts the clock, so rates exclude time spent before this point.
+    pub fn restart_clock(&self) {
+        *self.start_time.lock() = Instant::now();
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builds the worker pool used for mining, with `threads` workers (or one
+/// per core) and the larger stack that key generation needs. With
+/// `pin_threads`, each worker is pinned to its own core so the scheduler
+/// can't migrate it between cores (or between P and E cores on hybrid CPUs)
+/// mid-run.
+///
+/// If the tuned pool can't be built, e.g. because the OS refuses that many
+/// threads or that stack size, this warns and falls back to a pool with
+/// rayon's defaults rather than giving up on the run.
+pub fn build_thread_pool(threads: Option<usize>, pin_threads: bool) -> Result<rayon::ThreadPool> {
+    let threads = threads.unwrap_or_else(rayon::current_num_threads);
+    let mut builder = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .stack_size(THREAD_STACK_SIZE);
+
+    if pin_threads {
+        match core_affinity::get_core_ids() {
+            Some(cores) if !cores.is_empty() => {
+                eprintln!(
+                    "Pinning {} worker threads across {} cores",
+                    threads,
+                    cores.len()
+                );
+                builder = builder.start_handler(move |index| {
+                    let core = cores[index % cores.len()];
+                    if !core_affinity::set_for_current(core) {
+                        eprintln!(
+                            "Warning: could not pin worker {} to core {}",
+                            index, core.id
+                        );
+                    }
+                });
+            }
+            _ => eprintln!("Warning: thread pinning is not supported here, continuing unpinned"),
+        }
+    }
+
+    match builder.build() {
+        Ok(pool) => Ok(pool),
+        Err(e) => {
+            eprintln!(
+                "Warning: could not build a pool of {} threads ({}), using rayon's defaults",
+                threads, e
+            );
+            Ok(rayon::ThreadPoolBuilder::new().build()?)
+        }
+    }
+}
+
+/// Generates keys as `config` would on every worker for `window`, without
+/// matching or saving them, and returns the keys/s. Used for estimates
+/// before the real run, whose own rate only shows once it is under way.
+pub fn sample_rate(config: &Config, window: Duration) -> Result<f64> {
+    let uid = UserID::from("Vanity Sample <sample@example.com>");
+    let (layout, suite) = (config.key_layout(), config.cipher_suite());
+    let pool = build_thread_pool(config.threads, false)?;
+    let start = Instant::now();
+    let generated: usize = pool
+        .broadcast(|_| {
+            let mut generated = 0;
+            while start.elapsed() < window {
+                if generate_key(&uid, layout, suite).is_ok() {
+                    generated += 1;
+                }
+            }
+            generated
+        })
+        .into_iter()
+        .sum();
+    Ok(generated as f64 / start.elapsed().as_secs_f64())
+}
+
+/// A key whose fingerprint matched, as handed to [`mine_with`] callbacks.
+#[derive(Clone)]
+pub struct MinerResult {
+    pub cert: Cert,
+    pub revocation: Signature,
+    /// The primary key's fingerprint.
+    pub fingerprint: String,
+    /// What the pattern was found in, per `--match-key` and `--id-form`.
+    /// The same as `fingerprint` by default.
+    pub identifier: String,
+    /// The matched text and where it starts, in hex digits from the start
+    /// of `identifier`.
+    pub pattern: String,
+    pub offset: usize,
+    /// The `pattern_groups` entry the match counted towards, if any.
+    pub group: Option<String>,
+    /// The `--identities` user ID the key was made for, if any.
+    pub identity: Option<String>,
+    /// Why the key matched, from [`crate::Matcher::label`], if the matcher says.
+    pub label: Option<String>,
+}
+
+#[cfg(test)]
+impl MinerResult {
+    /// A freshly generated key, dressed up as a match at the fixed window.
+    pub(crate) fn generated(uid: &UserID) -> Self {
+        let (cert, revocation, fingerprint) = generate_key(
+            uid,
+            crate::keygen::KeyLayout::PrimaryOnly,
+            sequoia_openpgp::cert::CipherSuite::Cv25519,
+        )
+        .unwrap();
+        Self {
+            cert,
+            revocation,
+            identifier: fingerprint.clone(),
+            pattern: fingerprint[24..32].to_string(),
+            offset: 24,
+            fingerprint,
+            group: None,
+            identity: None,
+            label: None,
+        }
+    }
+}
+
+/// Runs the parallel search described by `config`, calling `on_match` for
+/// every matching key, and returns the final statistics.
+///
+/// `on_match` runs on whichever worker thread found the key, possibly on
+/// several workers at once, hence the `Sync` bound. It should hand slow work
+/// off elsewhere rather than block, since mining on that worker waits for it.
+pub fn mine_with<F>(config: &Config, on_match: F) -> Result<Stats>
+where
+    F: Fn(&MinerResult) + Sync,
+{
+    let stats = Stats::new();
+    let result = search(config, &stats, &on_match);
+    stats.done.store(true, Ordering::Relaxed);
+    result.map(|()| stats)
+}
+
+/// Mines with the binary's behaviour: announces each match on stdout and
+/// exports it to `config.export_dir`, updating the shared `stats` as it goes.
+pub fn mine_keys(config: Arc<Config>, stats: Arc<Stats>) -> Result<()> {
+    let writer = KeyWriter::spawn(Arc::clone(&config), Arc::clone(&stats))?;
+    #[cfg(feature = "webhook")]
+    let webhook = config.webhook.clone().map(crate::webhook::Webhook::spawn);
+    #[cfg(feature = "coordinator")]
+    let coordinator = config
+        .coordinator
+        .clone()
+        .map(|url| crate::coordinator::Coordinator::spawn(url, Arc::clone(&stats)))
+        .transpose()
+        .map_err(Error::InvalidOperation)?;
+
+    let result = search(&config, &stats, &|result: &MinerResult| {
+        // Status goes to stderr when stdout is reserved, so that is the
+        // stream whose terminal decides on color.
+        let stream = if config.stdout_reserved() {
+            Stream::Stderr
+        } else {
+            Stream::Stdout
+        };
+        let case = config.hex_case;
+        let highlighted = highlight(
+            &case.apply(&result.identifier),
+            result.offset,
+            result.pattern.len(),
+            stream,
+        );
+        let mut message = if result.identifier == result.fingerprint {
+            format!(
+                "\nMATCH FOUND! Key: {} Pattern: {}",
+                highlighted,
+                case.apply(&result.pattern)
+            )
+        } else {
+            format!(
+                "\nMATCH FOUND! Key: {} Identifier: {} Pattern: {}",
+                case.apply(&result.fingerprint),
+                highlighted,
+                case.apply(&result.pattern)
+            )
+        };
+        if let Some(label) = &result.label {
+            message.push_str(&format!(" ({})", label));
+        }
+        if let Some(identity) = &result.identity {
+            message.push_str(&format!(" Identity: {}", identity));
+        }
+        if visual_entropy(&result.pattern) < LOW_VISUAL_ENTROPY {
+            message.push_str(&format!(
+                "\nNote: {} looks repetitive, but only the public fingerprint is patterned; \
+                 the secret key is as random as any other.",
+                result.pattern
+            ));
+        }
+        #[cfg(feature = "qr")]
+        if config.qr {
+            if let Ok(code) = crate::qr::render_text(&result.fingerprint) {
+                message.push('\n');
+                message.push_str(&code);
+            }
+        }
+
+        // Keep stdout clean for key material or JSON progress.
+        if config.stdout_reserved() {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+        #[cfg(feature = "webhook")]
+        if let Some(webhook) = &webhook {
+            webhook.notify(result);
+        }
+        #[cfg(feature = "coordinator")]
+        if let Some(coordinator) = &coordinator {
+            coordinator.record(result);
+        }
+        let mut fields = format!(
+            "\"fingerprint\":\"{}\",\"pattern\":\"{}\"",
+            result.fingerprint, result.pattern
+        );
+        if let Some(identity) = &result.identity {
+            fields.push_str(&format!(",\"identity\":{}", audit::json_string(identity)));
+        }
+        audit::record("match", &fields);
+        writer.save(result.clone());
+    });
+
+    #[cfg(feature = "webhook")]
+    if let Some(webhook) = webhook {
+        webhook.finish();
+    }
+    #[cfg(feature = "coordinator")]
+    if let Some(coordinator) = coordinator {
+        coordinator.finish();
+    }
+    writer.finish()?;
+    result
+}
+
+/// Like [`mine_with`], but on an existing `pool` and counting into the
+/// caller's `stats`, so a long-lived process can run search after search
+/// on the same warm workers.
+pub fn mine_in<F>(
+    pool: &rayon::ThreadPool,
+    config: &Config,
+    stats: &Stats,
+    on_match: F,
+) -> Result<()>
+where
+    F: Fn(&MinerResult) + Sync,
+{
+    let result = search_in(pool, config, stats, &on_match);
+    stats.done.store(true, Ordering::Relaxed);
+    result
+}
+
+fn search<F>(config: &Config, stats: &Stats, on_match: &F) -> Result<()>
+where
+    F: Fn(&MinerResult) + Sync,
+{
+    let pool = build_thread_pool(config.threads, config.pin_threads)?;
+    search_in(&pool, config, stats, on_match)
+}
+
+fn search_in<F>(
+    pool: &rayon::ThreadPool,
+    config: &Config,
+    stats: &Stats,
+    on_match: &F,
+) -> Result<()>
+where
+    F: Fn(&MinerResult) + Sync,
+{
+    check_backend(config)?;
+    let identities = Identities::from_config(config, stats);
+    let uid = &identities.uids[0].0;
+    let matcher = build_matcher(config).map_err(Error::InvalidArgument)?;
+    let scorer = config
+        .min_score
+        .map(|_| Scorer::from_config(config))
+        .transpose()
+        .map_err(Error::InvalidArgument)?;
+    let schedule = config
+        .run_between
+        .as_deref()
+        .map(str::parse::<Schedule>)
+        .transpose()
+        .map_err(Error::InvalidArgument)?;
+    let groups = PatternGroups::from_config(config);
+    for group in &config.pattern_groups {
+        stats.group_found.entry(group.name.clone()).or_insert(0);
+    }
+    let seen = Seen::from_config(config)?;
+    let transform =
+        (config.transform != TransformKind::None).then(|| build_transform(config.transform));
+
+    let (layout, suite) = (config.key_layout(), config.cipher_suite());
+
+    // Show what the workers are about to generate before committing to a
+    // long run. The dumped key is thrown away and not counted.
+    if config.debug_dump_first {
+        let (cert, _, _) = generate_key(uid, layout, suite)?;
+        let cert = finalize_cert(cert, config)?;
+        eprintln!("First generated key:");
+        dump_cert(&cert, &mut std::io::stderr())?;
+    }
+
+    // Spin up every worker and let the allocator arenas fill before the
+    // clock starts, so the reported rate is the steady-state one.
+    if config.warmup {
+        pool.broadcast(|_| {
+            for _ in 0..WARMUP_KEYS_PER_THREAD {
+                let _ = generate_key(uid, layout, suite);
+            }
+        });
+        stats.restart_clock();
+    }
+
+    // --stop-when-exhausted: set once every pattern has matched.
+    let exhaust_at = config.stop_when_exhausted.then(|| matcher.pattern_count());
+    let exhausted = AtomicBool::new(false);
+
+    let batches = BatchSizer::calibrate(|| {
+        let _ = generate_key(uid, layout, suite);
+    });
+    let running = || {
+        stats.keys_checked.load(Ordering::Relaxed) < config.total_keys
+            && !stats.limit_reached(identities.run_limit())
+            && !identities.all_done(stats)
+            && !stats.groups_satisfied(&config.pattern_groups)
+            && !stats.aborted()
+            && !stats.stopped()
+            && !exhausted.load(Ordering::Relaxed)
+    };
+    let check_key = |uid: &UserID, identity: &str| -> Result<()> {
+        let (cert, revocation, key_id) = generate_with_retry(stats, config.max_error_rate, || {
+            generate_key(uid, layout, suite)
+        })?;
+        let identifier = match (config.match_key, config.match_id_form()) {
+            (MatchKey::Primary, IdForm::Fingerprint) => None,
+            (match_key, id_form) => key_identifier(&cert, match_key, id_form),
+        };
+        let identifier = match &transform {
+            Some(transform) => Some(transform.apply(identifier.as_deref().unwrap_or(&key_id))),
+            None => identifier,
+        };
+        let matched = matcher
+            .find(identifier.as_deref().unwrap_or(&key_id))
+            .map(|(offset, pattern)| (offset, pattern.to_string()));
+        // Matches for groups that already met their target are
+        // dropped without finalizing the key.
+        let matched = matched.filter(|(_, pattern)| {
+            groups.is_empty()
+                || groups
+                    .containing(pattern)
+                    .any(|(name, limit)| !stats.group_full(name, limit))
+        });
+        if let Some((offset, pattern)) = matched {
+            let label = matcher.label(&pattern);
+            let candidate = MinerResult {
+                cert,
+                revocation,
+                pattern,
+                identifier: identifier.unwrap_or_else(|| key_id.clone()),
+                fingerprint: key_id,
+                offset,
+                group: None,
+                identity: identities.per_identity.then(|| identity.to_string()),
+                label,
+            };
+            let accepted = accept_match(config, scorer.as_ref(), candidate)
+                .filter(|result| seen.insert(&result.fingerprint))
+                .and_then(|result| {
+                    let fingerprint = result.fingerprint.clone();
+                    let claimed = claim_limits(&groups, &identities, stats, identity, result);
+                    if claimed.is_none() {
+                        seen.remove(&fingerprint);
+                    }
+                    claimed
+                });
+            if let Some(result) = accepted {
+                stats.match_offsets[offset].fetch_add(1, Ordering::Relaxed);
+                *stats
+                    .pattern_found
+                    .entry(result.pattern.clone())
+                    .or_insert(0) += 1;
+                if exhaust_at.is_some_and(|count| stats.pattern_found.len() >= count)
+                    && !exhausted.swap(true, Ordering::Relaxed)
+                {
+                    eprintln!("\nEvery pattern has matched at least once, stopping");
+                }
+                on_match(&result);
+            }
+        }
+        stats.keys_checked.fetch_add(1, Ordering::Relaxed);
+        stats.count_thread_attempt();
+        Ok(())
+    };
+
+    let result = pool.install(|| {
+        iter::repeat(())
+            .par_bridge()
+            .take_any_while(|_| running())
+            .try_for_each(|_| -> Result<()> {
+                let Some((uid, identity)) = identities.next(stats) else {
+                    return Ok(());
+                };
+                let size = batches.size();
+                let mut checked = 0;
+                let mut busy = Duration::ZERO;
+                while checked < size && running() {
+                    while stats.paused.load(Ordering::Relaxed) {
+                        std::thread::sleep(PAUSE_POLL);
+                    }
+                    if let Some(schedule) = &schedule {
+                        wait_for_schedule(schedule, &stats.sleeping);
+                    }
+
+                    let started = Instant::now();
+                    check_key(uid, identity)?;
+                    busy += started.elapsed();
+                    checked += 1;
+                }
+                batches.record(checked, busy);
+                Ok(())
+            })
+    });
+
+    pool.broadcast(|_| stats.flush_thread_count());
+    result?;
+    match stats.abort_reason.lock().clone() {
+        Some(reason) => Err(Error::InvalidOperation(reason).into()),
+        None => Ok(()),
+    }
+}
+
+/// The user IDs a run mines for, handed to batches in turn. With
+/// `--identities`, `--max-matches` is each identity's own target, and one
+/// that has reached it is skipped.
+struct Identities {
+    uids: Vec<(UserID, String)>,
+    per_identity: bool,
+    limit: Option<usize>,
+    next: AtomicUsize,
+}
+
+impl Identities {
+    fn from_config(config: &Config, stats: &Stats) -> Self {
+        let per_identity = !config.identities.is_empty();
+        let uids = config
+            .user_ids()
+            .into_iter()
+            .map(|uid| (UserID::from(uid.as_str()), uid))
+            .collect::<Vec<_>>();
+        if per_identity {
+            for (_, identity) in &uids {
+                stats.identity_found.entry(identity.clone()).or_insert(0);
+            }
+        }
+        Self {
+            uids,
+            per_identity,
+            limit: config.match_limit(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// The `--max-matches` limit on the run as a whole, which only applies
+    /// without `--identities`.
+    fn run_limit(&self) -> Option<usize> {
+        self.limit.filter(|_| !self.per_identity)
+    }
+
+    /// The next identity still short of its target, or `None` once all
+    /// are done.
+    fn next(&self, stats: &Stats) -> Option<&(UserID, String)> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..self.uids.len())
+            .map(|i| &self.uids[(start + i) % self.uids.len()])
+            .find(|(_, identity)| !self.per_identity || !stats.identity_full(identity, self.limit))
+    }
+
+    fn all_done(&self, stats: &Stats) -> bool {
+        self.per_identity
+            && self
+                .uids
+                .iter()
+                .all(|(_, identity)| stats.identity_full(identity, self.limit))
+    }
+
+    fn claim(&self, stats: &Stats, identity: &str) -> bool {
+        !self.per_identity || stats.claim_identity_match(identity, self.limit)
+    }
+
+    /// Takes back a `claim` for a match that was turned away after all.
+    fn unclaim(&self, stats: &Stats, identity: &str) {
+        if self.per_identity {
+            if let Some(mut found) = stats.identity_found.get_mut(identity) {
+                *found = found.saturating_sub(1);
+            }
+        }
+    }
+}
+
+/// Fingerprints already matched, so a repeat is not saved twice, seeded
+/// with any `--exclude-keyring` ones. Empty and never filled with
+/// `--no-dedup`.
+///
+/// Workers check it as soon as a match passes the filters, before it counts
+/// towards `keys_found` or any target, so a duplicate leaves nothing behind
+/// in the stats and is never announced. A `DashSet` shards itself by hash
+/// and only matches reach it, so the workers hardly ever contend for it,
+/// even with loose patterns (see `cargo bench --bench dedup`).
+struct Seen(Option<DashSet<String>>);
+
+impl Seen {
+    fn from_config(config: &Config) -> Result<Self> {
+        if !config.dedup {
+            return Ok(Self(None));
+        }
+        let Some(path) = &config.exclude_keyring else {
+            return Ok(Self(Some(DashSet::new())));
+        };
+        let excluded = keyring_fingerprints(path)?;
+        eprintln!(
+            "Excluding {} fingerprints already in {}",
+            excluded.len(),
+            path.display()
+        );
+        Ok(Self(Some(excluded.into_iter().collect())))
+    }
+
+    /// Records `fingerprint`, returning whether it is new. A repeat is
+    /// reported and should be skipped.
+    fn insert(&self, fingerprint: &str) -> bool {
+        let Some(set) = &self.0 else {
+            return true;
+        };
+        if set.insert(fingerprint.to_string()) {
+            return true;
+        }
+        let message = format!("Skipping duplicate key {}", fingerprint);
+        eprintln!("\n{}", message);
+        audit::warning(&message);
+        false
+    }
+
+    /// Takes back the `insert` of a match that was turned away after all,
+    /// since it won't be saved.
+    fn remove(&self, fingerprint: &str) {
+        if let Some(set) = &self.0 {
+            set.remove(fingerprint);
+        }
+    }
+}
+
+/// Sizes the batches workers take so each takes about
+/// `TARGET_BATCH_TIME`, following the observed time per key as it changes
+/// with the layout, the machine's load or thermal throttling.
+struct BatchSizer {
+    size: AtomicUsize,
+}
+
+impl BatchSizer {
+    /// Starts from the time `generate` takes for `CALIBRATION_KEYS` keys.
+    fn calibrate(mut generate: impl FnMut()) -> Self {
+        let started = Instant::now();
+        for _ in 0..CALIBRATION_KEYS {
+            generate();
+        }
+        Self {
+            size: AtomicUsize::new(Self::ideal(CALIBRATION_KEYS, started.elapsed())),
+        }
+    }
+
+    fn ideal(keys: usize, busy: Duration) -> usize {
+        let per_key = busy.as_secs_f64() / keys as f64;
+        // A zero per-key time gives infinity, which saturates to the cap.
+        ((TARGET_BATCH_TIME.as_secs_f64() / per_key) as usize).clamp(1, MAX_BATCH_SIZE)
+    }
+
+    fn size(&self) -> usize {
+        self.size.load(Ordering::Relaxed)
+    }
+
+    /// Moves the size halfway towards what `busy` for `keys` keys says
+    /// would hit the target. Workers racing here just lose an update.
+    fn record(&self, keys: usize, busy: Duration) {
+        if keys == 0 {
+            return;
+        }
+        let size = (self.size() + Self::ideal(keys, busy)) / 2;
+        self.size.store(size, Ordering::Relaxed);
+    }
+}
+
+/// Calls `generate` until it produces a key, counting each failure with
+/// [`Stats::generation_failed`]. A failed attempt is not a checked key, so
+/// retrying here keeps every iteration to exactly one usable key and lets
+/// `keys_checked` reach `total_keys`. Duplicate fingerprints are real keys
+/// and are left to [`Seen`] rather than retried.
+fn generate_with_retry<T>(
+    stats: &Stats,
+    max_rate: f64,
+    mut generate: impl FnMut() -> Result<T>,
+) -> Result<T> {
+    let mut failures = 0;
+    loop {
+        match generate() {
+            Ok(key) => return Ok(key),
+            Err(e) => {
+                failures += 1;
+                if failures >= MAX_CONSECUTIVE_FAILURES {
+                    // Still an attempt, so it counts like the others.
+                    stats.generation_errors.fetch_add(1, Ordering::Relaxed);
+                    return Err(e.context(format!("{} key generations in a row failed", failures)));
+                }
+                stats.generation_failed(e, max_rate)?;
+            }
+        }
+    }
+}
+
+/// Blocks the calling worker while the local time is outside `schedule`.
+/// `sleeping` is set meanwhile, and makes sure only one worker announces
+/// each transition.
+fn wait_for_schedule(schedule: &Schedule, sleeping: &AtomicBool) {
+    if schedule.is_active_now() {
+        if sleeping.swap(false, Ordering::Relaxed) {
+            eprintln!("\nInside --run-between {}, resuming", schedule);
+        }
+        return;
+    }
+
+    if !sleeping.swap(true, Ordering::Relaxed) {
+        eprintln!("\nOutside --run-between {}, sleeping", schedule);
+    }
+    while !schedule.is_active_now() {
+        std::thread::sleep(SCHEDULE_POLL);
+    }
+}
+
+/// `identifier` with the `len` characters at `offset`, the matched window,
+/// in bold green. Plain text unless `stream` is a terminal and `NO_COLOR`
+/// isn't set, so logs and pipes get no escape codes.
+fn highlight(identifier: &str, offset: usize, len: usize, stream: Stream) -> String {
+    let end = (offset + len).min(identifier.len());
+    let start = offset.min(end);
+    format!(
+        "{}{}{}",
+        &identifier[..start],
+        identifier[start..end]
+            .if_supports_color(stream, |window| window.style(Style::new().green().bold())),
+        &identifier[end..]
+    )
+}
+
+/// Counts `result` towards the first of its pattern groups that still
+/// needs matches, recording which in `result.group`. Returns `None` if they
+/// have all met their targets. Without `pattern_groups` everything passes.
+fn claim_group(
+    groups: &PatternGroups,
+    stats: &Stats,
+    mut result: MinerResult,
+) -> Option<MinerResult> {
+    if groups.is_empty() {
+        return Some(result);
+    }
+    let (name, _) = groups
+        .containing(&result.pattern)
+        .find(|&(name, limit)| stats.claim_group_match(name, limit))?;
+    result.group = Some(name.to_string());
+    Some(result)
+}
+
+/// Counts `result` towards its pattern group, its identity and the run's
+/// `--max-matches`, in that order, returning `None` if any of them is
+/// already full. A refusal undoes the claims before it, so a match that
+/// won't be saved doesn't count towards anything. The group goes first
+/// since it is the likeliest to refuse, and the others only refuse once
+/// their target is met and mining is ending.
+fn claim_limits(
+    groups: &PatternGroups,
+    identities: &Identities,
+    stats: &Stats,
+    identity: &str,
+    result: MinerResult,
+) -> Option<MinerResult> {
+    let result = claim_group(groups, stats, result)?;
+    if identities.claim(stats, identity) {
+        if stats.claim_match(identities.run_limit()) {
+            return Some(result);
+        }
+        identities.unclaim(stats, identity);
+    }
+    if let Some(group) = &result.group {
+        stats.unclaim_group_match(group);
+    }
+    None
+}
+
+/// Applies the match-time cert adjustments and filters to a candidate whose
+/// fingerprint matched, returning `None` if it should be skipped.
+fn accept_match(
+    config: &Config,
+    scorer: Option<&Scorer>,
+    candidate: MinerResult,
+) -> Option<MinerResult> {
+    if let (Some(scorer), Some(min_score)) = (scorer, config.min_score) {
+        if scorer.score_fingerprint(&candidate.fingerprint) < min_score {
+            return None;
+        }
+    }
+
+    let cert = match finalize_cert(candidate.cert, config) {
+        Ok(cert) => cert,
+        Err(e) => {
+            let message = format!("Skipping key {}: {}", candidate.fingerprint, e);
+            eprintln!("\n{}", message);
+            audit::warning(&message);
+            return None;
+        }
+    };
+
+    if config.compliance {
+        if let Err(e) = check_compliance(&cert) {
+            let message = format!(
+                "Skipping non-compliant key {}: {}",
+                candidate.fingerprint, e
+            );
+            eprintln!("\n{}", message);
+            audit::warning(&message);
+            return None;
+        }
+    }
+
+    Some(MinerResult { cert, ..candidate })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A generator that fails `failures` times (`None`: forever) before
+    /// returning its call count, counting every call in `calls`.
+    fn flaky(calls: &Cell<usize>, failures: Option<usize>) -> impl FnMut() -> Result<usize> + '_ {
+        move || {
+            calls.set(calls.get() + 1);
+            match failures {
+                Some(failures) if calls.get() > failures => Ok(calls.get()),
+                _ => Err(anyhow::anyhow!("degenerate key")),
+            }
+        }
+    }
+
+    #[test]
+    fn first_key_needs_no_retry() {
+        let stats = Stats::new();
+        let calls = Cell::new(0);
+        assert_eq!(
+            generate_with_retry(&stats, 0.5, flaky(&calls, Some(0))).unwrap(),
+            1
+        );
+        assert_eq!(calls.get(), 1);
+        assert_eq!(stats.generation_errors.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn failures_are_retried_and_counted_but_not_checked() {
+        let stats = Stats::new();
+        stats.keys_checked.store(1000, Ordering::Relaxed);
+        let calls = Cell::new(0);
+        assert_eq!(
+            generate_with_retry(&stats, 0.5, flaky(&calls, Some(3))).unwrap(),
+            4
+        );
+        assert_eq!(calls.get(), 4);
+        assert_eq!(stats.generation_errors.load(Ordering::Relaxed), 3);
+        assert_eq!(stats.keys_checked.load(Ordering::Relaxed), 1000);
+    }
+
+    #[test]
+    fn gives_up_once_failures_exceed_the_max_rate() {
+        let stats = Stats::new();
+        let calls = Cell::new(0);
+        let error = generate_with_retry(&stats, 0.5, flaky(&calls, None)).unwrap_err();
+        assert_eq!(calls.get(), MIN_ERRORS_TO_ABORT);
+        assert_eq!(
+            stats.generation_errors.load(Ordering::Relaxed),
+            MIN_ERRORS_TO_ABORT
+        );
+        assert_eq!(
+            error.to_string(),
+            format!("{0} of {0} key generations failed", MIN_ERRORS_TO_ABORT)
+        );
+    }
+
+    #[test]
+    fn gives_up_after_too_many_failures_in_a_row() {
+        // A max rate of 1 never aborts on its own, leaving only the cap.
+        let stats = Stats::new();
+        let calls = Cell::new(0);
+        let error = generate_with_retry(&stats, 1.0, flaky(&calls, None)).unwrap_err();
+        assert_eq!(calls.get(), MAX_CONSECUTIVE_FAILURES);
+        assert_eq!(
+            stats.generation_errors.load(Ordering::Relaxed),
+            MAX_CONSECUTIVE_FAILURES
+        );
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "{} key generations in a row failed",
+                MAX_CONSECUTIVE_FAILURES
+            )
+        );
+    }
+
+    #[test]
+    fn seen_skips_repeats_until_taken_back() {
+        let seen = Seen::from_config(&Config::default()).unwrap();
+        assert!(seen.insert("0123456789ABCDEF0123456789ABCDEF01234567"));
+        assert!(!seen.insert("0123456789ABCDEF0123456789ABCDEF01234567"));
+        seen.remove("0123456789ABCDEF0123456789ABCDEF01234567");
+        assert!(seen.insert("0123456789ABCDEF0123456789ABCDEF01234567"));
+    }
+
+    #[test]
+    fn no_dedup_lets_repeats_through() {
+        let config = Config {
+            dedup: false,
+            ..Config::default()
+        };
+        let seen = Seen::from_config(&config).unwrap();
+        assert!(seen.insert("0123456789ABCDEF0123456789ABCDEF01234567"));
+        assert!(seen.insert("0123456789ABCDEF0123456789ABCDEF01234567"));
+    }
+
+    /// A config whose one pattern group, "g", takes `group_max` matches on
+    /// `pattern`, with `max_matches` on the run as a whole.
+    fn grouped(pattern: &str, group_max: usize, max_matches: Option<usize>) -> Config {
+        Config {
+            name: "Test".to_string(),
+            email: "test@example.com".to_string(),
+            max_matches,
+            pattern_groups: vec![PatternGroup {
+                name: "g".to_string(),
+                patterns: vec![pattern.to_string()],
+                max_matches: group_max,
+            }],
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn full_group_refuses_without_counting_the_match() {
+        let result = MinerResult::generated(&UserID::from("Test <test@example.com>"));
+        let config = grouped(&result.pattern, 1, None);
+        let stats = Stats::new();
+        let groups = PatternGroups::from_config(&config);
+        let identities = Identities::from_config(&config, &stats);
+        let identity = &identities.uids[0].1;
+
+        let claimed = claim_limits(&groups, &identities, &stats, identity, result.clone());
+        assert_eq!(claimed.unwrap().group.as_deref(), Some("g"));
+        assert!(claim_limits(&groups, &identities, &stats, identity, result).is_none());
+        assert_eq!(stats.keys_found.load(Ordering::Relaxed), 1);
+        assert_eq!(*stats.group_found.get("g").unwrap(), 1);
+    }
+
+    #[test]
+    fn max_matches_refusal_takes_back_the_group_claim() {
+        let result = MinerResult::generated(&UserID::from("Test <test@example.com>"));
+        let config = grouped(&result.pattern, 2, Some(1));
+        let stats = Stats::new();
+        let groups = PatternGroups::from_config(&config);
+        let identities = Identities::from_config(&config, &stats);
+        let identity = &identities.uids[0].1;
+
+        assert!(claim_limits(&groups, &identities, &stats, identity, result.clone()).is_some());
+        assert!(claim_limits(&groups, &identities, &stats, identity, result).is_none());
+        assert_eq!(stats.keys_found.load(Ordering::Relaxed), 1);
+        assert_eq!(*stats.group_found.get("g").unwrap(), 1);
+        assert!(!stats.groups_satisfied(&config.pattern_groups));
+    }
+
+    #[test]
+    fn identity_claims_can_be_taken_back() {
+        let config = Config {
+            identities: vec!["Alice <alice@example.com>".to_string()],
+            max_matches: Some(1),
+            ..Config::default()
+        };
+        let stats = Stats::new();
+        let identities = Identities::from_config(&config, &stats);
+        let identity = &identities.uids[0].1;
+
+        assert!(identities.claim(&stats, identity));
+        assert!(!identities.claim(&stats, identity));
+        identities.unclaim(&stats, identity);
+        assert_eq!(*stats.identity_found.get(identity.as_str()).unwrap(), 0);
+        assert!(identities.claim(&stats, identity));
+    }
+}
@@ -0,0 +1,746 @@
+use crate::{
+    audit,
+    config::{Config, LowRateAction, ProgressFormat},
+    export::write_atomically,
+    matcher::Matcher,
+    miner::Stats,
+};
+use chrono::Local;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+
+const PROGRESS_UPDATE_MS: u64 = 100;
+/// How often JSON and plain progress lines are written.
+const JSON_PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+const HISTOGRAM_WIDTH: usize = 40;
+/// Patterns listed in the summary's leaderboard.
+const LEADERBOARD_SIZE: usize = 10;
+/// Per-second rate samples shown in the progress bar's sparkline.
+const SPARKLINE_LEN: usize = 20;
+const SPARKLINE_INTERVAL: Duration = Duration::from_secs(1);
+const SPARKLINE_BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+/// Per-second samples `--min-rate` averages over.
+const LOW_RATE_WINDOW: usize = 10;
+/// Time after the start before `--min-rate` is checked, for threads to
+/// spin up and the first batches to be sized.
+const LOW_RATE_GRACE: Duration = Duration::from_secs(30);
+
+/// Reports progress until the run completes, as an indicatif bar or as
+/// JSON lines depending on `--progress-format`. `match_probability` is the
+/// chance a single key matches, used to show how the number of matches
+/// compares with what chance predicts: a ratio far from 1 over a long run
+/// points at a matcher bug or a broken RNG.
+pub fn display_progress(config: Arc<Config>, stats: Arc<Stats>, match_probability: f64) {
+    display_progress_while(&config, &stats, match_probability, &|| {
+        running(&config, &stats)
+    });
+}
+
+/// [`display_progress`] with the stop condition supplied by the caller:
+/// reporting carries on until `keep_going` returns false, which it is
+/// asked at least once per `JSON_PROGRESS_INTERVAL`.
+fn display_progress_while(
+    config: &Config,
+    stats: &Stats,
+    match_probability: f64,
+    keep_going: &dyn Fn() -> bool,
+) {
+    let mut watchers = Watchers {
+        stats_file: config.stats_file.clone().map(StatsFile::new),
+        drought: config
+            .max_attempts_per_match
+            .map(|factor| DroughtWatch::new(factor, match_probability, stats)),
+        low_rate: config
+            .min_rate
+            .map(|min_rate| RateWatch::new(min_rate, config.on_low_rate, stats)),
+    };
+    match config.progress_format {
+        ProgressFormat::Bar => {
+            display_bar(config, stats, match_probability, &mut watchers, keep_going)
+        }
+        ProgressFormat::Json => display_json(config, stats, &mut watchers, keep_going),
+        ProgressFormat::Plain => display_plain(config, stats, &mut watchers, keep_going),
+    }
+    if let Some(stats_file) = watchers.stats_file.as_mut() {
+        stats_file.write(stats);
+    }
+}
+
+/// The progress thread's periodic checks, ticked by whichever display is
+/// running.
+struct Watchers {
+    stats_file: Option<StatsFile>,
+    drought: Option<DroughtWatch>,
+    low_rate: Option<RateWatch>,
+}
+
+impl Watchers {
+    fn tick(&mut self, stats: &Stats) {
+        if let Some(stats_file) = self.stats_file.as_mut() {
+            stats_file.tick(stats);
+        }
+        if let Some(drought) = self.drought.as_mut() {
+            drought.tick(stats);
+        }
+        if let Some(low_rate) = self.low_rate.as_mut() {
+            low_rate.tick(stats);
+        }
+    }
+}
+
+/// The `{"checked":...}` fields shared by JSON progress lines and the
+/// stats file.
+fn progress_fields(stats: &Stats) -> String {
+    let checked = stats.keys_checked.load(Ordering::Relaxed);
+    let elapsed = stats.elapsed();
+    format!(
+        "\"checked\":{},\"found\":{},\"errors\":{},\"rate\":{},\"elapsed\":{:.3}",
+        checked,
+        stats.keys_found.load(Ordering::Relaxed),
+        stats.generation_errors.load(Ordering::Relaxed),
+        keys_per_second(stats.checked_this_run(), elapsed),
+        elapsed.as_secs_f64()
+    )
+}
+
+/// `--stats-file`: the latest progress as a JSON object, for dashboards
+/// that poll a file. It's rewritten once per [`JSON_PROGRESS_INTERVAL`].
+struct StatsFile {
+    path: PathBuf,
+    last_write: Option<Instant>,
+}
+
+impl StatsFile {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            last_write: None,
+        }
+    }
+
+    /// Rewrites the file if an interval has passed since the last write.
+    fn tick(&mut self, stats: &Stats) {
+        if self
+            .last_write
+            .is_none_or(|last| last.elapsed() >= JSON_PROGRESS_INTERVAL)
+        {
+            self.write(stats);
+        }
+    }
+
+    /// Writes under a temporary name and renames it into place, so readers
+    /// never see a partly written file.
+    fn write(&mut self, stats: &Stats) {
+        self.last_write = Some(Instant::now());
+        let json = format!("{{{}}}\n", progress_fields(stats));
+        if let Err(e) = write_atomically(&self.path, json) {
+            eprintln!("\nError writing stats file {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// `--max-attempts-per-match`: warns, once per dry spell, when `factor`
+/// times the keys a match is expected to take have gone by without one.
+/// By chance that only happens about e^-factor of the time, so it usually
+/// means the pattern can't match as configured.
+struct DroughtWatch {
+    factor: f64,
+    threshold: usize,
+    last_found: usize,
+    since: usize,
+    warned: bool,
+}
+
+impl DroughtWatch {
+    fn new(factor: f64, match_probability: f64, stats: &Stats) -> Self {
+        Self {
+            factor,
+            threshold: (factor / match_probability).ceil() as usize,
+            last_found: stats.keys_found.load(Ordering::Relaxed),
+            since: stats.keys_checked.load(Ordering::Relaxed),
+            warned: false,
+        }
+    }
+
+    fn tick(&mut self, stats: &Stats) {
+        let found = stats.keys_found.load(Ordering::Relaxed);
+        let checked = stats.keys_checked.load(Ordering::Relaxed);
+        if found != self.last_found {
+            self.last_found = found;
+            self.since = checked;
+            self.warned = false;
+        } else if !self.warned && checked - self.since >= self.threshold {
+            self.warned = true;
+            eprintln!(
+                "\nWarning: no match in {} keys, {}x the number a match should take. \
+                 Check the patterns' case, length and position (--group, --anywhere, \
+                 --id-form, --match-key).",
+                checked - self.since,
+                self.factor
+            );
+            audit::warning(&format!("No match in {} keys", checked - self.since));
+        }
+    }
+}
+
+/// `--min-rate`: compares the keys/s over the last `LOW_RATE_WINDOW`
+/// samples with the minimum, once `LOW_RATE_GRACE` has passed so startup
+/// doesn't count. A low rate is reported once until it recovers, or with
+/// `--on-low-rate abort` stops the run. Time paused for a full disk or
+/// outside `--run-between` is left out.
+struct RateWatch {
+    min_rate: u64,
+    action: LowRateAction,
+    samples: VecDeque<f64>,
+    last_checked: usize,
+    last_sample: Instant,
+    warned: bool,
+}
+
+impl RateWatch {
+    fn new(min_rate: u64, action: LowRateAction, stats: &Stats) -> Self {
+        Self {
+            min_rate,
+            action,
+            samples: VecDeque::with_capacity(LOW_RATE_WINDOW),
+            last_checked: stats.keys_checked.load(Ordering::Relaxed),
+            last_sample: Instant::now(),
+            warned: false,
+        }
+    }
+
+    fn tick(&mut self, stats: &Stats) {
+        let elapsed = self.last_sample.elapsed();
+        if elapsed < SPARKLINE_INTERVAL {
+            return;
+        }
+        let checked = stats.keys_checked.load(Ordering::Relaxed);
+        let rate = (checked - self.last_checked) as f64 / elapsed.as_secs_f64();
+        self.last_checked = checked;
+        self.last_sample = Instant::now();
+        if stats.paused.load(Ordering::Relaxed) || stats.sleeping.load(Ordering::Relaxed) {
+            self.samples.clear();
+            return;
+        }
+        if self.samples.len() == LOW_RATE_WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(rate);
+        if self.samples.len() < LOW_RATE_WINDOW || stats.elapsed() < LOW_RATE_GRACE {
+            return;
+        }
+
+        let average = self.samples.iter().sum::<f64>() / LOW_RATE_WINDOW as f64;
+        if average >= self.min_rate as f64 {
+            self.warned = false;
+            return;
+        }
+        let message = format!(
+            "only {:.0} keys/s over the last {}s, below --min-rate {}",
+            average, LOW_RATE_WINDOW, self.min_rate
+        );
+        match self.action {
+            LowRateAction::Abort => stats.abort(message),
+            LowRateAction::Warn if !self.warned => {
+                self.warned = true;
+                eprintln!(
+                    "\nWarning: {}. A worker may be stuck or the machine throttling.",
+                    message
+                );
+                audit::warning(&message);
+            }
+            LowRateAction::Warn => {}
+        }
+    }
+}
+
+/// The keys/s of each of the last few seconds, for a sparkline showing
+/// whether throughput is holding up or the machine is throttling, which
+/// the run's average rate hides.
+struct RateHistory {
+    samples: VecDeque<u64>,
+    last_checked: usize,
+    last_sample: Instant,
+}
+
+impl RateHistory {
+    fn new(checked: usize) -> Self {
+        Self {
+            samples: VecDeque::with_capacity(SPARKLINE_LEN),
+            last_checked: checked,
+            last_sample: Instant::now(),
+        }
+    }
+
+    /// Takes a sample if an interval has passed, dropping the oldest once
+    /// the history is full.
+    fn tick(&mut self, checked: usize) {
+        let elapsed = self.last_sample.elapsed();
+        if elapsed < SPARKLINE_INTERVAL {
+            return;
+        }
+        let rate = (checked - self.last_checked) as f64 / elapsed.as_secs_f64();
+        if self.samples.len() == SPARKLINE_LEN {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(rate as u64);
+        self.last_checked = checked;
+        self.last_sample = Instant::now();
+    }
+
+    /// The samples as bars scaled to the highest one.
+    fn sparkline(&self) -> String {
+        let max = self.samples.iter().copied().max().unwrap_or(0).max(1);
+        self.samples
+            .iter()
+            .map(|&rate| {
+                let level = (rate * (SPARKLINE_BARS.len() as u64 - 1)).div_ceil(max);
+                SPARKLINE_BARS[level as usize]
+            })
+            .collect()
+    }
+}
+
+/// Time until `--max-matches` is reached at the match rate seen so far, or
+/// before this run's first match, at the rate `match_probability` predicts
+/// for the current keys/s. `None` until there is a rate to go on.
+fn eta_to_target(stats: &Stats, target: usize, match_probability: f64) -> Option<Duration> {
+    let found = stats.found_this_run();
+    let elapsed = stats.elapsed().as_secs_f64();
+    let remaining = target.saturating_sub(stats.keys_found.load(Ordering::Relaxed)) as f64;
+    if elapsed == 0.0 {
+        return None;
+    }
+    let matches_per_second = if found > 0 {
+        found as f64 / elapsed
+    } else {
+        stats.checked_this_run() as f64 / elapsed * match_probability
+    };
+    // Far-off estimates overflow a Duration and are as good as unknown.
+    Duration::try_from_secs_f64(remaining / matches_per_second).ok()
+}
+
+/// A rough duration such as `~45s`, `~3m20s` or `~2h05m`.
+fn format_eta(eta: Duration) -> String {
+    let secs = eta.as_secs();
+    if secs < 60 {
+        format!("~{}s", secs)
+    } else if secs < 3600 {
+        format!("~{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("~{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    }
+}
+
+/// Whether the progress loops should keep going. Every loop re-checks this
+/// at least once per `JSON_PROGRESS_INTERVAL`, so the thread exits within
+/// one interval of `total_keys` being checked, or of the miner setting
+/// `stats.done`, which the caller must do however mining ends (match
+/// limit, abort or error) for the thread to be joinable.
+fn running(config: &Config, stats: &Stats) -> bool {
+    stats.keys_checked.load(Ordering::Relaxed) < config.total_keys
+        && !stats.done.load(Ordering::Relaxed)
+}
+
+fn keys_per_second(checked: usize, elapsed: Duration) -> u64 {
+    if elapsed.as_secs() > 0 {
+        checked as u64 / elapsed.as_secs()
+    } else {
+        0
+    }
+}
+
+fn display_bar(
+    config: &Config,
+    stats: &Stats,
+    match_probability: f64,
+    watchers: &mut Watchers,
+    keep_going: &dyn Fn() -> bool,
+) {
+    // A bar towards an unlimited total would never visibly move.
+    let pb = if config.is_unlimited() {
+        let pb = ProgressBar::new_spinner();
+        pb.set_style(
+            ProgressStyle::default_spinner()
+                .template("{spinner:.green} [{elapsed_precise}] {pos} keys {msg}")
+                .unwrap(),
+        );
+        pb
+    } else {
+        let pb = ProgressBar::new(config.total_keys as u64);
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template(
+                    "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}",
+                )
+                .unwrap()
+                .progress_chars("#>-"),
+        );
+        pb
+    };
+
+    let mut history = RateHistory::new(stats.keys_checked.load(Ordering::Relaxed));
+    while keep_going() {
+        let current = stats.keys_checked.load(Ordering::Relaxed);
+        let found = stats.keys_found.load(Ordering::Relaxed);
+        pb.set_position(current as u64);
+
+        let speed = keys_per_second(stats.checked_this_run(), stats.elapsed());
+        history.tick(current);
+
+        let expected = current as f64 * match_probability;
+        let divergence = if expected >= 1.0 {
+            format!(", {:.2}x", found as f64 / expected)
+        } else {
+            String::new()
+        };
+
+        // With --identities each one needs --max-matches of its own.
+        let target = match config
+            .match_limit()
+            .map(|limit| limit * config.identities.len().max(1))
+        {
+            Some(target) => format!(
+                " | found {}/{}, ETA to {}: {}",
+                found,
+                target,
+                target,
+                eta_to_target(stats, target, match_probability)
+                    .map_or_else(|| "?".to_string(), format_eta)
+            ),
+            None => String::new(),
+        };
+
+        let errors = match stats.generation_errors.load(Ordering::Relaxed) {
+            0 => String::new(),
+            errors => format!(" | Errors: {}", errors),
+        };
+
+        pb.set_message(format!(
+            "({}/s {}) | Found: {} (expected {:.1}{}){}{}",
+            speed,
+            history.sparkline(),
+            found,
+            expected,
+            divergence,
+            target,
+            errors
+        ));
+        watchers.tick(stats);
+        std::thread::sleep(Duration::from_millis(PROGRESS_UPDATE_MS));
+    }
+
+    pb.finish_with_message("Done!");
+}
+
+/// Emits one `{"event":"progress",...}` line per interval for a wrapping
+/// process to consume, on stderr when stdout is carrying key material.
+fn display_json(
+    config: &Config,
+    stats: &Stats,
+    watchers: &mut Watchers,
+    keep_going: &dyn Fn() -> bool,
+) {
+    let mut out = json_output(config);
+    loop {
+        let finished = !keep_going();
+        let _ = writeln!(out, "{{\"event\":\"progress\",{}}}", progress_fields(stats));
+        let _ = out.flush();
+        watchers.tick(stats);
+
+        if finished {
+            break;
+        }
+        std::thread::sleep(JSON_PROGRESS_INTERVAL);
+    }
+}
+
+/// `--plain-progress`: one timestamped line of counts per interval, with no
+/// control codes, for logs.
+fn display_plain(
+    config: &Config,
+    stats: &Stats,
+    watchers: &mut Watchers,
+    keep_going: &dyn Fn() -> bool,
+) {
+    let mut out = status_output(config);
+    loop {
+        let finished = !keep_going();
+        let checked = stats.keys_checked.load(Ordering::Relaxed);
+        let _ = writeln!(
+            out,
+            "{} checked {} found {} ({} keys/s)",
+            Local::now().format("%Y-%m-%d %H:%M:%S"),
+            checked,
+            stats.keys_found.load(Ordering::Relaxed),
+            keys_per_second(stats.checked_this_run(), stats.elapsed())
+        );
+        let _ = out.flush();
+        watchers.tick(stats);
+
+        if finished {
+            break;
+        }
+        std::thread::sleep(JSON_PROGRESS_INTERVAL);
+    }
+}
+
+/// Where human-readable status goes: stdout, unless `--stdout` has
+/// claimed it for key material or `--progress-format json` for its lines.
+pub fn status_output(config: &Config) -> Box<dyn Write> {
+    if config.stdout_reserved() {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    }
+}
+
+/// Where `--progress-format json` lines go: stdout, unless `--stdout` has
+/// claimed it for key material.
+pub fn json_output(config: &Config) -> Box<dyn Write> {
+    if config.stdout {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    }
+}
+
+/// `total_keys` for JSON output, `null` when unlimited.
+pub fn total_keys_json(config: &Config) -> String {
+    if config.is_unlimited() {
+        "null".to_string()
+    } else {
+        config.total_keys.to_string()
+    }
+}
+
+/// The single line a controlling process reads first in JSON mode, before
+/// any progress output, to learn the run's parameters.
+pub fn print_handshake(
+    config: &Config,
+    matcher: &dyn Matcher,
+    threads: usize,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "{{\"event\":\"start\",\"total_keys\":{},\"patterns\":{},\"threads\":{}}}",
+        total_keys_json(config),
+        matcher.pattern_count(),
+        threads
+    )?;
+    out.flush()
+}
+
+/// Lists each worker's keys/s, slowest first. A large spread between the
+/// slowest and fastest points at scheduling or affinity problems.
+fn print_thread_breakdown(stats: &Stats, out: &mut dyn Write) -> io::Result<()> {
+    let mut counts: Vec<usize> = stats
+        .thread_checked
+        .iter()
+        .map(|entry| *entry.value())
+        .collect();
+    if counts.len() < 2 {
+        return Ok(());
+    }
+    counts.sort_unstable();
+
+    let elapsed = stats.elapsed();
+    writeln!(out, "\nPer-thread keys/s:")?;
+    for (index, &count) in counts.iter().enumerate() {
+        writeln!(out, "  {:>3} | {}", index, keys_per_second(count, elapsed))?;
+    }
+
+    let (slowest, fastest) = (counts[0], counts[counts.len() - 1]);
+    if slowest > 0 {
+        writeln!(
+            out,
+            "Fastest thread checked {:.2}x as many keys as the slowest",
+            fastest as f64 / slowest as f64
+        )?;
+    }
+    Ok(())
+}
+
+/// The most matched patterns, most first and alphabetically on ties. Only
+/// shown when more than one pattern matched, as otherwise it repeats the
+/// match count.
+fn print_leaderboard(stats: &Stats, out: &mut dyn Write) -> io::Result<()> {
+    let mut patterns: Vec<(String, usize)> = stats
+        .pattern_found
+        .iter()
+        .map(|entry| (entry.key().clone(), *entry.value()))
+        .collect();
+    if patterns.len() < 2 {
+        return Ok(());
+    }
+    patterns.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    writeln!(
+        out,
+        "\nMost matched patterns ({} of {} matched):",
+        patterns.len().min(LEADERBOARD_SIZE),
+        patterns.len()
+    )?;
+    for (rank, (pattern, found)) in patterns.iter().take(LEADERBOARD_SIZE).enumerate() {
+        writeln!(out, "  {:>2}. {:<16} {}", rank + 1, pattern, found)?;
+    }
+    Ok(())
+}
+
+/// Writes the end-of-run summary to `out`, which is stderr when stdout is
+/// carrying key material.
+pub fn print_summary(stats: &Stats, show_offsets: bool, out: &mut dyn Write) -> io::Result<()> {
+    let checked = stats.keys_checked.load(Ordering::Relaxed);
+    let found = stats.keys_found.load(Ordering::Relaxed);
+    writeln!(
+        out,
+        "\nChecked {} keys in {:.1}s, found {} matches",
+        checked,
+        stats.elapsed().as_secs_f64(),
+        found
+    )?;
+    let errors = stats.generation_errors.load(Ordering::Relaxed);
+    if errors > 0 {
+        writeln!(out, "{} keys failed to generate", errors)?;
+    }
+
+    print_thread_breakdown(stats, out)?;
+
+    let mut groups: Vec<(String, usize)> = stats
+        .group_found
+        .iter()
+        .map(|entry| (entry.key().clone(), *entry.value()))
+        .collect();
+    if !groups.is_empty() {
+        groups.sort();
+        writeln!(out, "\nMatches per pattern group:")?;
+        for (name, found) in groups {
+            writeln!(out, "  {}: {}", name, found)?;
+        }
+    }
+
+    let mut identities: Vec<(String, usize)> = stats
+        .identity_found
+        .iter()
+        .map(|entry| (entry.key().clone(), *entry.value()))
+        .collect();
+    if !identities.is_empty() {
+        identities.sort();
+        writeln!(out, "\nMatches per identity:")?;
+        for (identity, found) in identities {
+            writeln!(out, "  {}: {}", identity, found)?;
+        }
+    }
+
+    print_leaderboard(stats, out)?;
+
+    if !show_offsets || found == 0 {
+        return Ok(());
+    }
+
+    let counts: Vec<usize> = stats
+        .match_offsets
+        .iter()
+        .map(|count| count.load(Ordering::Relaxed))
+        .collect();
+    let max = counts.iter().copied().max().unwrap_or(0).max(1);
+
+    writeln!(out, "\nMatch offsets:")?;
+    for (offset, &count) in counts.iter().enumerate().filter(|(_, count)| **count > 0) {
+        let bar = "#".repeat((count * HISTOGRAM_WIDTH).div_ceil(max));
+        writeln!(
+            out,
+            "  {:>2} | {:<width$} {}",
+            offset,
+            bar,
+            count,
+            width = HISTOGRAM_WIDTH
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UNLIMITED_TOTAL_KEYS;
+    use std::{
+        sync::{atomic::AtomicBool, mpsc},
+        thread,
+    };
+
+    /// How long the progress thread gets to notice it should stop: one
+    /// interval, plus slack for a loaded test machine.
+    const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn plain_config(total_keys: usize) -> Config {
+        Config {
+            total_keys,
+            progress_format: ProgressFormat::Plain,
+            ..Config::default()
+        }
+    }
+
+    /// Runs `display_progress` on its own thread, calls `finish` and fails
+    /// unless the thread then returns within `STOP_TIMEOUT`.
+    fn assert_stops(config: Config, finish: impl FnOnce(&Stats)) {
+        let stats = Arc::new(Stats::new());
+        let (sender, receiver) = mpsc::channel();
+        let handle = {
+            let stats = Arc::clone(&stats);
+            thread::spawn(move || {
+                display_progress(Arc::new(config), stats, 1e-9);
+                let _ = sender.send(());
+            })
+        };
+        finish(&stats);
+        receiver
+            .recv_timeout(STOP_TIMEOUT)
+            .expect("progress thread didn't stop");
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn stops_once_total_keys_are_checked() {
+        assert_stops(plain_config(1000), |stats| {
+            stats.keys_checked.store(1000, Ordering::Relaxed)
+        });
+    }
+
+    #[test]
+    fn stops_once_the_miner_is_done() {
+        assert_stops(plain_config(UNLIMITED_TOTAL_KEYS), |stats| {
+            stats.done.store(true, Ordering::Relaxed)
+        });
+    }
+
+    #[test]
+    fn reports_until_the_stop_condition_says_otherwise() {
+        let config = plain_config(UNLIMITED_TOTAL_KEYS);
+        let stats = Stats::new();
+        let stop = AtomicBool::new(false);
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            let (config, stats, stop) = (&config, &stats, &stop);
+            scope.spawn(move || {
+                display_progress_while(config, stats, 1e-9, &|| !stop.load(Ordering::Relaxed));
+                let _ = sender.send(());
+            });
+            assert!(
+                receiver.recv_timeout(JSON_PROGRESS_INTERVAL * 2).is_err(),
+                "progress thread stopped before being told to"
+            );
+            stop.store(true, Ordering::Relaxed);
+            receiver
+                .recv_timeout(STOP_TIMEOUT)
+                .expect("progress thread didn't stop");
+        });
+    }
+}
@@ -0,0 +1,32 @@
+//! QR codes of a match's fingerprint, for showing a vanity key off or
+//! checking it from a phone.
+
+use image::Luma;
+use qrcode::{render::unicode, QrCode};
+use sequoia_openpgp::{Error, Result};
+use std::path::Path;
+
+fn qr_error(e: impl ToString) -> anyhow::Error {
+    Error::InvalidOperation(e.to_string()).into()
+}
+
+/// The code as text, two modules per character, light on dark so it scans
+/// from a terminal with a dark background.
+pub fn render_text(fingerprint: &str) -> Result<String> {
+    Ok(QrCode::new(fingerprint)
+        .map_err(qr_error)?
+        .render::<unicode::Dense1x2>()
+        .dark_color(unicode::Dense1x2::Light)
+        .light_color(unicode::Dense1x2::Dark)
+        .build())
+}
+
+/// Saves the code as a PNG at `path`.
+pub fn write_png(fingerprint: &str, path: &Path) -> Result<()> {
+    QrCode::new(fingerprint)
+        .map_err(qr_error)?
+        .render::<Luma<u8>>()
+        .build()
+        .save(path)
+        .map_err(qr_error)
+}
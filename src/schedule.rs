@@ -0,0 +1,59 @@
+use chrono::{Local, NaiveTime, Timelike};
+use std::{fmt, str::FromStr};
+
+/// A daily `HH:MM-HH:MM` window of local time that mining is allowed in,
+/// as given to `--run-between`. A window whose end is before its start
+/// crosses midnight, so `22:00-06:00` covers the night.
+#[derive(Clone, Copy)]
+pub struct Schedule {
+    start: u32,
+    end: u32,
+}
+
+impl Schedule {
+    /// Whether `minute` (minutes since midnight) falls inside the window.
+    /// The start is inclusive and the end exclusive.
+    pub fn contains(&self, minute: u32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+
+    pub fn is_active_now(&self) -> bool {
+        let now = Local::now();
+        self.contains(now.hour() * 60 + now.minute())
+    }
+}
+
+fn parse_time(time: &str) -> Result<u32, String> {
+    let time = NaiveTime::parse_from_str(time.trim(), "%H:%M")
+        .map_err(|_| format!("Invalid time {:?}, expected HH:MM", time))?;
+    Ok(time.hour() * 60 + time.minute())
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| format!("Invalid schedule {:?}, expected HH:MM-HH:MM", s))?;
+        let schedule = Self {
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        };
+        if schedule.start == schedule.end {
+            return Err(format!("Schedule {:?} is empty", s));
+        }
+        Ok(schedule)
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let time = |minute: u32| format!("{:02}:{:02}", minute / 60, minute % 60);
+        write!(f, "{}-{}", time(self.start), time(self.end))
+    }
+}
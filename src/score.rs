@@ -0,0 +1,185 @@
+use crate::config::Config;
+use serde::Deserialize;
+use std::{collections::BTreeMap, fs};
+
+/// Rules for scoring how "pretty" a fingerprint is, so `--min-score` can
+/// keep only the best of the matches. Loaded from a TOML file with
+/// `--score-rules`; see `score_rules.example.toml` for the format.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scorer {
+    /// Points for each occurrence of a hex substring anywhere in the
+    /// fingerprint, e.g. `DEAD = 10`.
+    pub patterns: BTreeMap<String, u32>,
+    /// Points per character of a run of one repeated digit, for runs of at
+    /// least `min_run` characters.
+    pub repeat_bonus: u32,
+    pub min_run: usize,
+}
+
+impl Default for Scorer {
+    fn default() -> Self {
+        Self {
+            patterns: BTreeMap::new(),
+            repeat_bonus: 1,
+            min_run: 3,
+        }
+    }
+}
+
+impl Scorer {
+    /// Loads the rules in `path`, normalising patterns to uppercase to
+    /// match the fingerprint's hex form.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read score rules {}: {}", path, e))?;
+        let mut scorer: Self =
+            toml::from_str(&text).map_err(|e| format!("Invalid score rules {}: {}", path, e))?;
+
+        scorer.patterns = scorer
+            .patterns
+            .into_iter()
+            .map(|(pattern, points)| (pattern.to_ascii_uppercase(), points))
+            .collect();
+        if let Some(pattern) = scorer
+            .patterns
+            .keys()
+            .find(|pattern| pattern.is_empty() || !pattern.chars().all(|c| c.is_ascii_hexdigit()))
+        {
+            return Err(format!(
+                "Invalid score rules {}: {:?} is not a hex pattern",
+                path, pattern
+            ));
+        }
+        Ok(scorer)
+    }
+
+    /// The scorer a run uses: the `--score-rules` file if given, otherwise
+    /// the built-in repetition-only rules.
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        match &config.score_rules {
+            Some(path) => Self::load(&path.to_string_lossy()),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn score_fingerprint(&self, fingerprint: &str) -> u32 {
+        let pattern_points: u32 = self
+            .patterns
+            .iter()
+            .map(|(pattern, points)| fingerprint.matches(pattern.as_str()).count() as u32 * points)
+            .sum();
+
+        let bytes = fingerprint.as_bytes();
+        let repeat_points: u32 = bytes
+            .chunk_by(|a, b| a == b)
+            .filter(|run| run.len() >= self.min_run.max(2))
+            .map(|run| run.len() as u32 * self.repeat_bonus)
+            .sum();
+
+        pattern_points + repeat_points
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const PLAIN: &str = "0123456789ABCDEF0123456789ABCDEF01234567";
+    const EXAMPLE_RULES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/score_rules.example.toml");
+
+    /// Writes `rules` to a file of its own under the system temp dir.
+    fn rules_file(name: &str, rules: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "vanity-pgp-miner-{}-{}.toml",
+            name,
+            std::process::id()
+        ));
+        fs::write(&path, rules).unwrap();
+        path
+    }
+
+    #[test]
+    fn default_rules_score_runs_of_three_or_more() {
+        let scorer = Scorer::default();
+        assert_eq!(scorer.score_fingerprint(PLAIN), 0);
+        assert_eq!(
+            scorer.score_fingerprint("AAAA0123456789ABCDEF0123456789ABCDEF0123"),
+            4
+        );
+        assert_eq!(
+            scorer.score_fingerprint("AA0123456789ABCDEF0123456789ABCDEF012345"),
+            0
+        );
+    }
+
+    #[test]
+    fn min_run_raises_the_bar_for_repeats() {
+        let scorer = Scorer {
+            min_run: 5,
+            ..Scorer::default()
+        };
+        assert_eq!(
+            scorer.score_fingerprint("AAAA0123456789ABCDEF0123456789ABCDEF0123"),
+            0
+        );
+        assert_eq!(
+            scorer.score_fingerprint("AAAAA123456789ABCDEF0123456789ABCDEF0123"),
+            5
+        );
+    }
+
+    #[test]
+    fn patterns_score_every_occurrence() {
+        let scorer = Scorer {
+            patterns: BTreeMap::from([("DEAD".to_string(), 10), ("BEEF".to_string(), 3)]),
+            repeat_bonus: 0,
+            min_run: 3,
+        };
+        assert_eq!(
+            scorer.score_fingerprint("DEADBEEFDEAD0123456789ABCDEF0123456789AB"),
+            23
+        );
+        assert_eq!(scorer.score_fingerprint(PLAIN), 0);
+    }
+
+    #[test]
+    fn example_rules_add_patterns_and_repeats() {
+        let scorer = Scorer::load(EXAMPLE_RULES).unwrap();
+        assert_eq!(scorer.repeat_bonus, 2);
+        assert_eq!(scorer.min_run, 3);
+        assert_eq!(
+            scorer.score_fingerprint("DEADBEEFDEAD0123456789ABCDEF0123456789AB"),
+            30
+        );
+        // CAFE, BABE, F00D, 1337 and C0DE, plus 2 points a digit for 0000.
+        assert_eq!(
+            scorer.score_fingerprint("CAFEBABE0000F00D1337C0DE0123456789ABCDEF"),
+            49
+        );
+    }
+
+    #[test]
+    fn load_uppercases_patterns() {
+        let path = rules_file("score-lowercase", "[patterns]\ndead = 7\n");
+        let scorer = Scorer::load(&path.to_string_lossy()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(scorer.patterns, BTreeMap::from([("DEAD".to_string(), 7)]));
+    }
+
+    #[test]
+    fn load_rejects_bad_rules() {
+        for (name, rules) in [
+            ("score-non-hex", "[patterns]\nNOPE = 1\n"),
+            ("score-empty-pattern", "[patterns]\n\"\" = 1\n"),
+            ("score-unknown-field", "bonus = 1\n"),
+        ] {
+            let path = rules_file(name, rules);
+            let result = Scorer::load(&path.to_string_lossy());
+            fs::remove_file(&path).unwrap();
+            assert!(result.is_err(), "{:?} was accepted", rules);
+        }
+        assert!(Scorer::load("/nonexistent/score_rules.toml").is_err());
+    }
+}
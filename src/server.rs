@@ -0,0 +1,174 @@
+//! `--serve`: a long-lived miner for trying pattern sets one after another
+//! without paying for startup each time. The thread pool, with its warm
+//! allocator arenas and per-thread key templates, is built once and reused
+//! by every search.
+//!
+//! Clients connect over TCP and send one command per line:
+//!
+//! * `MINE <total_keys> <pattern>...` searches up to `total_keys` keys for
+//!   the patterns, in place of the `--pattern` list or built-ins. The reply
+//!   is `OK <description>` (or `ERR <message>`), a
+//!   `MATCH <fingerprint> <pattern> <offset>` line per match as it is found,
+//!   and `DONE <checked> <found> <seconds>` at the end. Matches are saved to
+//!   the export directory as in a normal run.
+//! * `QUIT` closes the connection.
+//!
+//! Everything else in the config (identity, layout, limits) is the one the
+//! server was started with. One client is served at a time, since a search
+//! already uses every worker. There's no authentication, so only loopback
+//! addresses are accepted.
+
+use crate::{
+    config::Config,
+    export::KeyWriter,
+    matcher::build_matcher,
+    miner::{build_thread_pool, mine_in, MinerResult, Stats},
+};
+use parking_lot::Mutex;
+use sequoia_openpgp::{Error, Result};
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{atomic::Ordering, Arc},
+};
+
+/// Serves `MINE` requests on `addr` until the process is stopped.
+pub fn run(config: Config, addr: &str) -> Result<()> {
+    let addrs = loopback_addrs(addr)?;
+    let pool = build_thread_pool(config.threads, config.pin_threads)?;
+    let listener = TcpListener::bind(&addrs[..])?;
+    println!(
+        "Serving searches on {} with {} workers",
+        listener.local_addr()?,
+        pool.current_num_threads()
+    );
+
+    let mut config = config;
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Connection failed: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = serve_client(&pool, &mut config, stream) {
+            eprintln!("Client error: {}", e);
+        }
+    }
+    Ok(())
+}
+
+/// Resolves `addr`, refusing it if any address it names is reachable from
+/// other machines: anyone who can connect can fill the export directory.
+fn loopback_addrs(addr: &str) -> Result<Vec<SocketAddr>> {
+    let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+    if addrs.is_empty() || addrs.iter().any(|addr| !addr.ip().is_loopback()) {
+        return Err(Error::InvalidArgument(format!(
+            "--serve {} is not a loopback address; the server has no authentication, \
+             so bind it to 127.0.0.1 or ::1",
+            addr
+        ))
+        .into());
+    }
+    Ok(addrs)
+}
+
+fn serve_client(pool: &rayon::ThreadPool, config: &mut Config, stream: TcpStream) -> Result<()> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut out = stream;
+    for line in reader.lines() {
+        let line = line?;
+        let mut words = line.split_whitespace();
+        match words.next().map(str::to_ascii_uppercase).as_deref() {
+            Some("MINE") => {
+                let Some(total_keys) = words.next().and_then(|n| n.parse().ok()) else {
+                    writeln!(out, "ERR usage: MINE <total_keys> <pattern>...")?;
+                    continue;
+                };
+                let patterns: Vec<String> = words.map(str::to_string).collect();
+                search(pool, config, total_keys, patterns, &mut out)?;
+                // Only the first search needs to warm the workers up.
+                config.warmup = false;
+            }
+            Some("QUIT") => break,
+            Some(command) => writeln!(out, "ERR unknown command {}", command)?,
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// Runs one `MINE` request, replying on `out`. Problems with the request
+/// itself are reported to the client; only a broken connection or a
+/// failed export is an error for the server.
+fn search(
+    pool: &rayon::ThreadPool,
+    base: &Config,
+    total_keys: usize,
+    patterns: Vec<String>,
+    out: &mut TcpStream,
+) -> Result<()> {
+    let mut config = base.clone();
+    config.total_keys = total_keys;
+    if !patterns.is_empty() {
+        config.patterns = Some(patterns);
+    }
+    if let Err(e) = config.validate() {
+        writeln!(out, "ERR {}", e.to_string().replace('\n', "; "))?;
+        return Ok(());
+    }
+    let description = match build_matcher(&config) {
+        Ok(matcher) => matcher.describe(),
+        Err(e) => {
+            writeln!(out, "ERR {}", e)?;
+            return Ok(());
+        }
+    };
+    writeln!(out, "OK {}", description)?;
+
+    let config = Arc::new(config);
+    let stats = Arc::new(Stats::new());
+    let writer = KeyWriter::spawn(Arc::clone(&config), Arc::clone(&stats))?;
+    let client = Mutex::new(out.try_clone()?);
+    let result = mine_in(pool, &config, &stats, |result: &MinerResult| {
+        // A client that hung up still gets its keys saved.
+        let _ = writeln!(
+            client.lock(),
+            "MATCH {} {} {}",
+            result.fingerprint,
+            result.pattern,
+            result.offset
+        );
+        writer.save(result.clone());
+    });
+    writer.finish()?;
+    if let Err(e) = result {
+        writeln!(out, "ERR {}", e)?;
+        return Ok(());
+    }
+    writeln!(
+        out,
+        "DONE {} {} {:.1}",
+        stats.keys_checked.load(Ordering::Relaxed),
+        stats.keys_found.load(Ordering::Relaxed),
+        stats.elapsed().as_secs_f64()
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_loopback_addresses_are_served() {
+        for addr in ["127.0.0.1:7879", "[::1]:7879", "127.0.0.2:0"] {
+            assert!(loopback_addrs(addr).is_ok(), "{}", addr);
+        }
+        for addr in ["0.0.0.0:7879", "[::]:7879", "192.168.1.10:7879"] {
+            let error = loopback_addrs(addr).unwrap_err().to_string();
+            assert!(error.contains("not a loopback address"), "{}", error);
+        }
+    }
+}
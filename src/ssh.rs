@@ -0,0 +1,59 @@
+//! OpenSSH export of a matched key's authentication subkey, so the same
+//! identity can log in over SSH without going through gpg-agent.
+
+use crate::keygen::{ed25519_seed, ED25519_SEED_LEN};
+use sequoia_openpgp::{policy::StandardPolicy, Cert, Error, Result};
+use ssh_key::{private::Ed25519Keypair, LineEnding, PrivateKey};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+fn ssh_error(e: ssh_key::Error) -> anyhow::Error {
+    Error::InvalidOperation(e.to_string()).into()
+}
+
+/// Writes the authentication subkey of `cert` to `dir` as
+/// `id_vanity_TAG` and `id_vanity_TAG.pub`, where `tag` is what the key's
+/// other files are named by, returning their paths.
+pub fn write_ssh_keys(cert: &Cert, dir: &Path, tag: &str) -> Result<Vec<PathBuf>> {
+    let policy = StandardPolicy::new();
+    let valid_cert = cert.with_policy(&policy, None)?;
+    let subkey = valid_cert
+        .keys()
+        .subkeys()
+        .for_authentication()
+        .secret()
+        .next()
+        .ok_or_else(|| Error::InvalidArgument("key has no authentication subkey".into()))?;
+
+    let seed = ed25519_seed(subkey.key())?;
+    let seed: &[u8; ED25519_SEED_LEN] = seed[..]
+        .try_into()
+        .map_err(|_| Error::InvalidOperation("unexpected Ed25519 seed length".into()))?;
+    let mut private = PrivateKey::from(Ed25519Keypair::from_seed(seed));
+    if let Ok(userid) = valid_cert.primary_userid() {
+        private.set_comment(String::from_utf8_lossy(userid.userid().value()));
+    }
+
+    fs::create_dir_all(dir)?;
+    let private_path = dir.join(format!("id_vanity_{}", tag));
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // ssh refuses private keys that other users can read.
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(&private_path)?.write_all(
+        private
+            .to_openssh(LineEnding::LF)
+            .map_err(ssh_error)?
+            .as_bytes(),
+    )?;
+
+    let public_path = dir.join(format!("id_vanity_{}.pub", tag));
+    let public = private.public_key().to_openssh().map_err(ssh_error)?;
+    fs::write(&public_path, public + "\n")?;
+
+    Ok(vec![private_path, public_path])
+}
@@ -0,0 +1,134 @@
+use crate::{
+    config::Config,
+    export::write_atomically,
+    matcher::{FingerprintSet, PatternCache},
+    miner::Stats,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::Path,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5);
+const CHECKPOINT_POLL: Duration = Duration::from_millis(100);
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// What `--state` persists between runs so an interrupted search can pick
+/// up its counts, and keep numbering exported keys, where it left off.
+#[derive(Serialize, Deserialize)]
+pub struct State {
+    /// Identifies the search the counts belong to; see [`search_hash`].
+    pub search_hash: String,
+    pub keys_checked: usize,
+    pub keys_found: usize,
+}
+
+/// A stable hash of everything that decides what counts as a match, so a
+/// state file can't be resumed by a run looking for something else.
+/// FNV-1a is used because it is stable across builds, unlike the hashers
+/// used for the pattern set itself.
+pub fn search_hash(config: &Config) -> Result<String, String> {
+    let mut description = format!(
+        "anywhere={} group={:?} mask={:?} dont_care={:?} match_key={:?} id_form={:?} transform={:?}\n",
+        config.match_anywhere,
+        config.group,
+        config.mask,
+        config.dont_care,
+        config.match_key as u8,
+        config.match_id_form() as u8,
+        config.transform as u8
+    );
+    if let Some(predicate) = &config.whole_fingerprint_predicate {
+        description.push_str(predicate);
+    } else if let Some(path) = &config.fingerprint_set {
+        let fingerprints = FingerprintSet::load(path)?;
+        description.push_str(&fingerprints.sorted().join(","));
+    } else if config.mask.is_none() {
+        let patterns = PatternCache::from_config(config)?;
+        let mut patterns: Vec<&str> = patterns
+            .patterns_by_probability()
+            .into_iter()
+            .map(|(pattern, _)| pattern)
+            .collect();
+        patterns.sort_unstable();
+        description.push_str(&patterns.join(","));
+    }
+
+    Ok(fnv_hash(&description))
+}
+
+/// FNV-1a of `text` as 16 hex digits.
+pub fn fnv_hash(text: &str) -> String {
+    let hash = text.bytes().fold(FNV_OFFSET, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(FNV_PRIME)
+    });
+    format!("{:016x}", hash)
+}
+
+/// Loads the state at `path` into `stats`, if there is one. A state file
+/// from a different search is refused rather than mixed in.
+pub fn resume(path: &Path, search_hash: &str, stats: &Stats) -> Result<(), String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(format!("Cannot read state {}: {}", path.display(), e)),
+    };
+    let state: State =
+        toml::from_str(&text).map_err(|e| format!("Invalid state {}: {}", path.display(), e))?;
+
+    if state.search_hash != search_hash {
+        return Err(format!(
+            "State file {} was written for a different pattern set or mask. \
+             Remove it or pass another --state path to start afresh.",
+            path.display()
+        ));
+    }
+
+    stats
+        .keys_checked
+        .store(state.keys_checked, Ordering::Relaxed);
+    stats.keys_found.store(state.keys_found, Ordering::Relaxed);
+    stats
+        .resumed_checked
+        .store(state.keys_checked, Ordering::Relaxed);
+    stats
+        .resumed_found
+        .store(state.keys_found, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Writes the current counts to `path`. The file is written under a
+/// temporary name and renamed over the old one, so being killed mid-write
+/// leaves the previous checkpoint intact rather than a truncated file.
+pub fn save(path: &Path, search_hash: &str, stats: &Stats) -> io::Result<()> {
+    let state = State {
+        search_hash: search_hash.to_string(),
+        keys_checked: stats.keys_checked.load(Ordering::Relaxed),
+        keys_found: stats.keys_found.load(Ordering::Relaxed),
+    };
+    let text = toml::to_string(&state).map_err(io::Error::other)?;
+    write_atomically(path, text)
+}
+
+/// Checkpoints the state every few seconds until the run is done, then
+/// once more with the final counts.
+pub fn checkpoint_loop(path: &Path, search_hash: &str, stats: &Stats) {
+    let mut last_save = Instant::now();
+    while !stats.done.load(Ordering::Relaxed) {
+        std::thread::sleep(CHECKPOINT_POLL);
+        if last_save.elapsed() >= CHECKPOINT_INTERVAL {
+            if let Err(e) = save(path, search_hash, stats) {
+                eprintln!("\nError saving state {}: {}", path.display(), e);
+            }
+            last_save = Instant::now();
+        }
+    }
+
+    if let Err(e) = save(path, search_hash, stats) {
+        eprintln!("\nError saving state {}: {}", path.display(), e);
+    }
+}
@@ -0,0 +1,119 @@
+use crate::config::TransformKind;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Rewrites a key's hex identifier into the form patterns are matched
+/// against, giving vanity alphabets other than hex.
+pub trait Transform: Send + Sync {
+    /// Transforms an uppercase hex identifier.
+    fn apply(&self, hex: &str) -> String;
+
+    /// Length of the output for a `hex_len`-digit identifier.
+    fn output_len(&self, hex_len: usize) -> usize;
+
+    /// Number of distinct symbols the output uses, for match odds.
+    fn radix(&self) -> u32;
+
+    /// Whether `c` can appear in the output, for validating patterns.
+    fn is_symbol(&self, c: char) -> bool;
+
+    fn name(&self) -> &'static str;
+}
+
+/// The identity transform: plain uppercase hex.
+pub struct Hex;
+
+impl Transform for Hex {
+    fn apply(&self, hex: &str) -> String {
+        hex.to_string()
+    }
+
+    fn output_len(&self, hex_len: usize) -> usize {
+        hex_len
+    }
+
+    fn radix(&self) -> u32 {
+        16
+    }
+
+    fn is_symbol(&self, c: char) -> bool {
+        c.is_ascii_hexdigit()
+    }
+
+    fn name(&self) -> &'static str {
+        "hex"
+    }
+}
+
+/// RFC 4648 base32 of the identifier's bytes, without padding: 32 symbols
+/// for a fingerprint, from `A`-`Z` and `2`-`7`.
+pub struct Base32;
+
+impl Transform for Base32 {
+    fn apply(&self, hex: &str) -> String {
+        let mut out = String::with_capacity(self.output_len(hex.len()));
+        let (mut buffer, mut bits) = (0u32, 0);
+        for digit in hex.chars().filter_map(|c| c.to_digit(16)) {
+            buffer = buffer << 4 | digit;
+            bits += 4;
+            if bits >= 5 {
+                bits -= 5;
+                out.push(BASE32_ALPHABET[(buffer >> bits & 0x1F) as usize] as char);
+            }
+        }
+        if bits > 0 {
+            out.push(BASE32_ALPHABET[(buffer << (5 - bits) & 0x1F) as usize] as char);
+        }
+        out
+    }
+
+    fn output_len(&self, hex_len: usize) -> usize {
+        (hex_len * 4).div_ceil(5)
+    }
+
+    fn radix(&self) -> u32 {
+        32
+    }
+
+    fn is_symbol(&self, c: char) -> bool {
+        BASE32_ALPHABET.contains(&(c.to_ascii_uppercase() as u8))
+    }
+
+    fn name(&self) -> &'static str {
+        "base32"
+    }
+}
+
+/// The hex identifier read backwards, for patterns that read from the
+/// end. Still hex, so every hex pattern and option applies.
+pub struct Reverse;
+
+impl Transform for Reverse {
+    fn apply(&self, hex: &str) -> String {
+        hex.chars().rev().collect()
+    }
+
+    fn output_len(&self, hex_len: usize) -> usize {
+        hex_len
+    }
+
+    fn radix(&self) -> u32 {
+        16
+    }
+
+    fn is_symbol(&self, c: char) -> bool {
+        c.is_ascii_hexdigit()
+    }
+
+    fn name(&self) -> &'static str {
+        "hex"
+    }
+}
+
+pub fn build_transform(kind: TransformKind) -> Box<dyn Transform> {
+    match kind {
+        TransformKind::None => Box::new(Hex),
+        TransformKind::Base32 => Box::new(Base32),
+        TransformKind::Reverse => Box::new(Reverse),
+    }
+}
@@ -0,0 +1,206 @@
+use crate::{
+    config::{IdForm, TransformKind},
+    keygen::keygrip,
+    transform::build_transform,
+};
+use sequoia_openpgp::{parse::Parse, Cert, Result};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// File name prefixes `save_key` gives public and private keys, under
+/// either `--name-by` scheme.
+const PUBLIC_PREFIXES: [&str; 2] = ["public_key_", "pub_"];
+const PRIVATE_PREFIXES: [&str; 2] = ["private_key_", "priv_"];
+
+/// What `verify` found in an export directory.
+pub struct VerifyReport {
+    /// Lines of found_keys.txt checked.
+    pub entries: usize,
+    /// Key files parsed.
+    pub files: usize,
+    /// One message per discrepancy or unreadable file.
+    pub problems: Vec<String>,
+}
+
+/// One line of found_keys.txt, as written by `save_key`.
+struct LogEntry {
+    index: usize,
+    identifier: String,
+    pattern: String,
+    offset: usize,
+    fingerprint: String,
+}
+
+/// Parses `[N] ID - Matched pattern: P at offset O`, taking the primary
+/// fingerprint from the ` - Primary fingerprint F` note when there is one.
+/// Other notes are ignored, and `--hex-case lower` is undone.
+fn parse_log_line(line: &str) -> Option<LogEntry> {
+    let (index, rest) = line.strip_prefix('[')?.split_once("] ")?;
+    let mut notes = rest.split(" - ");
+    let identifier = notes.next()?;
+    let (pattern, offset) = notes
+        .next()?
+        .strip_prefix("Matched pattern: ")?
+        .split_once(" at offset ")?;
+    let fingerprint = notes
+        .find_map(|note| note.strip_prefix("Primary fingerprint "))
+        .unwrap_or(identifier);
+    Some(LogEntry {
+        index: index.parse().ok()?,
+        identifier: identifier.to_ascii_uppercase(),
+        pattern: pattern.to_ascii_uppercase(),
+        offset: offset.parse().ok()?,
+        fingerprint: fingerprint.to_ascii_uppercase(),
+    })
+}
+
+/// Every identifier a run could have matched `cert` by: each key's
+/// fingerprint, long and short key ID and keygrip, in hex and each
+/// `--transform`.
+fn possible_identifiers(cert: &Cert) -> HashSet<String> {
+    let transforms = [
+        TransformKind::None,
+        TransformKind::Base32,
+        TransformKind::Reverse,
+    ]
+    .map(build_transform);
+    let mut identifiers = HashSet::new();
+    for key in cert.keys() {
+        let hex = key.key().fingerprint().to_hex();
+        for id_form in [IdForm::Fingerprint, IdForm::Long, IdForm::Short] {
+            let id = &hex[hex.len() - id_form.hex_len()..];
+            identifiers.extend(transforms.iter().map(|transform| transform.apply(id)));
+        }
+        if let Some(grip) = keygrip(key.key()) {
+            identifiers.extend(transforms.iter().map(|transform| transform.apply(&grip)));
+        }
+    }
+    identifiers
+}
+
+/// The key files in `dir` and its `--max-files-per-dir` subdirectories.
+fn key_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if path.is_dir() && name.chars().all(|c| c.is_ascii_digit()) {
+            files.extend(key_files(&path)?);
+        } else if name.ends_with(".asc")
+            && PUBLIC_PREFIXES
+                .iter()
+                .chain(&PRIVATE_PREFIXES)
+                .any(|prefix| name.starts_with(prefix))
+        {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Re-reads an export directory and checks it against its found_keys.txt:
+/// every key file must parse, private files must hold the secret keys,
+/// every logged match must have both files, its identifier must belong to
+/// the key and contain the recorded pattern at the recorded offset, and no
+/// key may be missing from the log.
+pub fn verify_export_dir(dir: &Path) -> Result<VerifyReport> {
+    let mut problems = Vec::new();
+
+    // Fingerprint -> (public files, private files, cert).
+    let mut keys: HashMap<String, (Vec<PathBuf>, Vec<PathBuf>, Cert)> = HashMap::new();
+    let files = key_files(dir)?;
+    for path in &files {
+        let cert = match Cert::from_file(path) {
+            Ok(cert) => cert,
+            Err(e) => {
+                problems.push(format!("{}: cannot parse key: {}", path.display(), e));
+                continue;
+            }
+        };
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let private = PRIVATE_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix));
+        if private && !cert.is_tsk() {
+            problems.push(format!("{}: has no secret key", path.display()));
+        }
+
+        let entry = keys
+            .entry(cert.fingerprint().to_hex())
+            .or_insert_with(|| (Vec::new(), Vec::new(), cert));
+        if private {
+            entry.1.push(path.clone());
+        } else {
+            entry.0.push(path.clone());
+        }
+    }
+
+    let log = fs::read_to_string(dir.join("found_keys.txt"))?;
+    let mut logged = HashSet::new();
+    let mut entries = 0;
+    for (number, line) in log.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        entries += 1;
+        let Some(entry) = parse_log_line(line) else {
+            problems.push(format!("found_keys.txt line {}: malformed", number + 1));
+            continue;
+        };
+        let label = format!("[{}] {}", entry.index, entry.fingerprint);
+        logged.insert(entry.fingerprint.clone());
+
+        let matched = entry
+            .identifier
+            .get(entry.offset..)
+            .is_some_and(|rest| rest.starts_with(&entry.pattern));
+        if !matched {
+            problems.push(format!(
+                "{}: {} does not have {} at offset {}",
+                label, entry.identifier, entry.pattern, entry.offset
+            ));
+        }
+
+        let Some((public, private, cert)) = keys.get(&entry.fingerprint) else {
+            problems.push(format!("{}: no key files found", label));
+            continue;
+        };
+        if public.is_empty() {
+            problems.push(format!("{}: public key file missing", label));
+        }
+        if private.is_empty() {
+            problems.push(format!("{}: private key file missing", label));
+        }
+        if !possible_identifiers(cert).contains(&entry.identifier) {
+            problems.push(format!(
+                "{}: {} is not an identifier of the key",
+                label, entry.identifier
+            ));
+        }
+    }
+
+    let mut unlogged: Vec<_> = keys
+        .iter()
+        .filter(|(fingerprint, _)| !logged.contains(*fingerprint))
+        .collect();
+    unlogged.sort_by_key(|(fingerprint, _)| *fingerprint);
+    for (fingerprint, (public, private, _)) in unlogged {
+        for path in public.iter().chain(private) {
+            problems.push(format!(
+                "{}: key {} is not in found_keys.txt",
+                path.display(),
+                fingerprint
+            ));
+        }
+    }
+
+    Ok(VerifyReport {
+        entries,
+        files: files.len(),
+        problems,
+    })
+}
@@ -0,0 +1,68 @@
+//! `--webhook`: a JSON POST per match, for chat and alerting integrations.
+
+use crate::miner::MinerResult;
+use chrono::{SecondsFormat, Utc};
+use std::{
+    sync::mpsc::{self, SyncSender, TrySendError},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// Notifications that may wait for a slow endpoint before new ones are
+/// dropped.
+const WEBHOOK_QUEUE_DEPTH: usize = 32;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Posts match notifications from a background thread, so a slow or dead
+/// endpoint never holds up mining. When the queue is full, notifications
+/// are dropped with a warning instead of waiting.
+pub struct Webhook {
+    sender: SyncSender<String>,
+    handle: JoinHandle<()>,
+}
+
+impl Webhook {
+    pub fn spawn(url: String) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<String>(WEBHOOK_QUEUE_DEPTH);
+        let handle = thread::spawn(move || {
+            let agent = ureq::AgentBuilder::new().timeout(WEBHOOK_TIMEOUT).build();
+            for payload in receiver {
+                if let Err(e) = agent
+                    .post(&url)
+                    .set("Content-Type", "application/json")
+                    .send_string(&payload)
+                {
+                    eprintln!("\nWebhook to {} failed: {}", url, e);
+                }
+            }
+        });
+        Self { sender, handle }
+    }
+
+    /// Queues `{"fingerprint":...,"pattern":...,"timestamp":...}` for
+    /// `result`. The fields are hex or base32 so need no escaping.
+    pub fn notify(&self, result: &MinerResult) {
+        let payload = format!(
+            "{{\"fingerprint\":\"{}\",\"pattern\":\"{}\",\"timestamp\":\"{}\"}}",
+            result.fingerprint,
+            result.pattern,
+            Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+        );
+        match self.sender.try_send(payload) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => eprintln!(
+                "\nWebhook queue full, dropping notification for {}",
+                result.fingerprint
+            ),
+            Err(TrySendError::Disconnected(_)) => {
+                eprintln!("\nWebhook thread has stopped, dropping notification")
+            }
+        }
+    }
+
+    /// Waits for the queued notifications to be sent.
+    pub fn finish(self) {
+        drop(self.sender);
+        let _ = self.handle.join();
+    }
+}
@@ -0,0 +1,151 @@
+//! The `init` subcommand: asks a newcomer what they want, one question at a
+//! time, and turns the answers into a `Config`, to mine with straight away
+//! or save for `--config`. Every answer maps onto an ordinary option, so
+//! the saved file is also a worked example of the flags.
+
+use crate::{
+    config::{Config, DEFAULT_TOTAL_KEYS},
+    matcher::{build_matcher, MAX_PATTERN_LEN, MIN_PATTERN_LEN},
+};
+use dialoguer::{theme::ColorfulTheme, Input, Select};
+use std::fs;
+
+const DEFAULT_CONFIG_PATH: &str = "miner.toml";
+
+/// Runs the wizard. Returns the config to mine with, or `None` if it was
+/// written to a file instead.
+pub fn run() -> Result<Option<Config>, String> {
+    let theme = ColorfulTheme::default();
+    let mut config = Config::default();
+
+    config.name = Input::with_theme(&theme)
+        .with_prompt("Your name")
+        .interact_text()
+        .map_err(prompt_error)?;
+    config.email = Input::with_theme(&theme)
+        .with_prompt("Your email")
+        .interact_text()
+        .map_err(prompt_error)?;
+
+    let position = Select::with_theme(&theme)
+        .with_prompt("Where in the fingerprint should the pattern be?")
+        .items(&[
+            "Digits 25-32, the first half of the long key ID (the default)",
+            "At the very start (--group 1)",
+            "Anywhere (--anywhere, more matches, less prominent)",
+        ])
+        .default(0)
+        .interact()
+        .map_err(prompt_error)?;
+    match position {
+        1 => config.group = Some(1),
+        2 => config.match_anywhere = true,
+        _ => {}
+    }
+
+    let source = Select::with_theme(&theme)
+        .with_prompt("What should it spell?")
+        .items(&[
+            "Any of the built-in patterns (hexspeak, repeats, sequences)",
+            "My own pattern of 4 to 16 digits, e.g. C0FFEE (each extra digit is 16x rarer)",
+        ])
+        .default(0)
+        .interact()
+        .map_err(prompt_error)?;
+    if source == 1 {
+        let pattern: String = Input::with_theme(&theme)
+            .with_prompt(format!(
+                "Pattern ({} to {} hex digits, 0-9 and A-F)",
+                MIN_PATTERN_LEN, MAX_PATTERN_LEN
+            ))
+            .validate_with(|pattern: &String| -> Result<(), String> {
+                let len = pattern.len();
+                if !(MIN_PATTERN_LEN..=MAX_PATTERN_LEN).contains(&len) {
+                    return Err(format!(
+                        "Must be {} to {} digits long",
+                        MIN_PATTERN_LEN, MAX_PATTERN_LEN
+                    ));
+                }
+                if !pattern.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err("Only 0-9 and A-F can appear in a fingerprint".to_string());
+                }
+                Ok(())
+            })
+            .interact_text()
+            .map_err(prompt_error)?;
+        config.patterns = Some(vec![pattern.to_ascii_uppercase()]);
+    }
+
+    let layout = Select::with_theme(&theme)
+        .with_prompt("Key type")
+        .items(&[
+            "Ed25519 signing key with an X25519 encryption subkey (recommended)",
+            "Certify-only Ed25519 key with signing and encryption subkeys (--best-practice)",
+            "A single RSA-3072 key for everything (--monolithic, much slower)",
+        ])
+        .default(0)
+        .interact()
+        .map_err(prompt_error)?;
+    match layout {
+        1 => config.best_practice = true,
+        2 => config.monolithic = true,
+        _ => {}
+    }
+
+    config.validate().map_err(|e| e.to_string())?;
+    let matcher = build_matcher(&config)?;
+    let per_match = (1.0 / matcher.match_probability()).ceil() as usize;
+    println!(
+        "About 1 in {} keys will match {}.",
+        per_match,
+        matcher.describe()
+    );
+    config.max_matches = Some(
+        Input::with_theme(&theme)
+            .with_prompt("How many matching keys do you want?")
+            .default(1usize)
+            .validate_with(|count: &usize| -> Result<(), &str> {
+                if *count == 0 {
+                    Err("Must be at least 1")
+                } else {
+                    Ok(())
+                }
+            })
+            .interact_text()
+            .map_err(prompt_error)?,
+    );
+    // Enough keys for a good chance at every match, but at least the
+    // usual default.
+    config.total_keys = Input::with_theme(&theme)
+        .with_prompt("Give up after checking how many keys?")
+        .default(DEFAULT_TOTAL_KEYS.max(per_match.saturating_mul(3)))
+        .interact_text()
+        .map_err(prompt_error)?;
+
+    let action = Select::with_theme(&theme)
+        .with_prompt("Now")
+        .items(&["Start mining", "Save these settings to a config file"])
+        .default(0)
+        .interact()
+        .map_err(prompt_error)?;
+    if action == 0 {
+        return Ok(Some(config));
+    }
+
+    let path: String = Input::with_theme(&theme)
+        .with_prompt("Config file")
+        .default(DEFAULT_CONFIG_PATH.to_string())
+        .interact_text()
+        .map_err(prompt_error)?;
+    let toml = toml::to_string(&config).map_err(|e| e.to_string())?;
+    fs::write(&path, toml).map_err(|e| format!("Cannot write {}: {}", path, e))?;
+    println!(
+        "Wrote {}. Mine with: vanity-pgp-miner --config {}",
+        path, path
+    );
+    Ok(None)
+}
+
+fn prompt_error(error: dialoguer::Error) -> String {
+    format!("init: {}", error)
+}
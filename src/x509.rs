@@ -0,0 +1,66 @@
+//! Export of a matched key's primary key as PKCS#8 and a self-signed X.509
+//! certificate, for S/MIME and other non-OpenPGP tools.
+//!
+//! Only the key material carries over. The vanity pattern is part of the
+//! OpenPGP fingerprint, which X.509 doesn't use, and the certificate is
+//! self-signed, so mail clients need it trusted by hand. The primary key is
+//! Ed25519, which many S/MIME clients still don't support; sequoia has no
+//! X.509 support of its own, so the certificate is built with `rcgen`.
+
+use crate::{
+    config::Config,
+    keygen::{ed25519_seed, ED25519_SEED_LEN},
+};
+use rcgen::{CertificateParams, DistinguishedName, DnType, KeyPair, SanType};
+use sequoia_openpgp::{crypto::mem::Protected, Cert, Error, Result};
+
+/// DER of an Ed25519 `OneAsymmetricKey` (RFC 8410) up to the 32-byte seed.
+const ED25519_PKCS8_PREFIX: [u8; 16] = [
+    0x30, 0x2e, 0x02, 0x01, 0x00, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x04, 0x22, 0x04, 0x20,
+];
+
+pub struct X509Export {
+    /// The primary secret key as a PKCS#8 PEM, wiped when dropped.
+    pub key_pem: Protected,
+    /// A self-signed certificate for the configured name and email.
+    pub cert_pem: String,
+}
+
+/// The primary key of `cert` as PKCS#8 DER. Only unencrypted Ed25519 keys,
+/// as the miner generates, are supported.
+pub fn pkcs8_der(cert: &Cert) -> Result<Protected> {
+    let key = cert.primary_key().key().clone().parts_into_secret()?;
+    let seed = ed25519_seed(&key)?;
+
+    let mut der = Vec::with_capacity(ED25519_PKCS8_PREFIX.len() + ED25519_SEED_LEN);
+    der.extend_from_slice(&ED25519_PKCS8_PREFIX);
+    der.extend_from_slice(&seed);
+    Ok(der.into())
+}
+
+pub fn export(cert: &Cert, config: &Config) -> Result<X509Export> {
+    let der = pkcs8_der(cert)?;
+    let key_pair =
+        KeyPair::try_from(&der[..]).map_err(|e| Error::InvalidOperation(e.to_string()))?;
+
+    let mut params = CertificateParams::default();
+    params.distinguished_name = DistinguishedName::new();
+    params
+        .distinguished_name
+        .push(DnType::CommonName, config.name.clone());
+    params.subject_alt_names = vec![SanType::Rfc822Name(
+        config
+            .email
+            .clone()
+            .try_into()
+            .map_err(|e: rcgen::Error| Error::InvalidArgument(e.to_string()))?,
+    )];
+    let x509 = params
+        .self_signed(&key_pair)
+        .map_err(|e| Error::InvalidOperation(e.to_string()))?;
+
+    Ok(X509Export {
+        key_pem: key_pair.serialize_pem().into_bytes().into(),
+        cert_pem: x509.pem(),
+    })
+}
@@ -0,0 +1,112 @@
+//! Checks that a discarded candidate's secret key doesn't survive in freed
+//! heap memory. A wrapping allocator copies aside every block the test
+//! thread frees while a key is generated and then dropped, reallocations
+//! included, and the copies are searched for the key's secret. sequoia
+//! keeps secrets sealed and zeroes its plaintext buffers before freeing
+//! them, so it should not be found.
+//!
+//! This lives in its own test binary because it needs its own global
+//! allocator.
+
+use sequoia_openpgp::{cert::CipherSuite, packet::UserID};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::{Cell, UnsafeCell},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use vanity_pgp_miner::{
+    generate_key,
+    keygen::{ed25519_seed, KeyLayout, ED25519_SEED_LEN},
+};
+
+const CAPTURE_SIZE: usize = 16 << 20;
+
+/// Copies of the freed blocks, back to back.
+struct Capture {
+    bytes: UnsafeCell<[u8; CAPTURE_SIZE]>,
+    len: AtomicUsize,
+    overflowed: AtomicBool,
+}
+
+// Each block gets its own range of `bytes` from `len`, and they are only
+// read once capturing has stopped.
+unsafe impl Sync for Capture {}
+
+static CAPTURE: Capture = Capture {
+    bytes: UnsafeCell::new([0; CAPTURE_SIZE]),
+    len: AtomicUsize::new(0),
+    overflowed: AtomicBool::new(false),
+};
+
+thread_local! {
+    /// Set while the test thread's frees are being captured. Other
+    /// threads, like the test harness's, are left alone.
+    static CAPTURING: Cell<bool> = const { Cell::new(false) };
+}
+
+struct CapturingAllocator;
+
+unsafe impl GlobalAlloc for CapturingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if CAPTURING.try_with(Cell::get).unwrap_or(false) {
+            let start = CAPTURE.len.fetch_add(layout.size(), Ordering::Relaxed);
+            if start + layout.size() <= CAPTURE_SIZE {
+                let copy = (CAPTURE.bytes.get() as *mut u8).add(start);
+                ptr.copy_to_nonoverlapping(copy, layout.size());
+            } else {
+                CAPTURE.overflowed.store(true, Ordering::Relaxed);
+            }
+        }
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CapturingAllocator = CapturingAllocator;
+
+fn capture<T>(f: impl FnOnce() -> T) -> T {
+    CAPTURING.with(|capturing| capturing.set(true));
+    let result = f();
+    CAPTURING.with(|capturing| capturing.set(false));
+    result
+}
+
+#[test]
+fn discarded_secrets_are_not_left_in_freed_memory() {
+    let uid = UserID::from("Test <test@example.com>");
+    let (cert, revocation, _) =
+        capture(|| generate_key(&uid, KeyLayout::PrimaryOnly, CipherSuite::Cv25519).unwrap());
+
+    let key = cert
+        .primary_key()
+        .key()
+        .clone()
+        .parts_into_secret()
+        .unwrap();
+    let mut seed = [0; ED25519_SEED_LEN];
+    seed.copy_from_slice(&ed25519_seed(&key).unwrap());
+    drop(key);
+
+    // What a worker does with a candidate that didn't match.
+    capture(|| drop((cert, revocation)));
+
+    assert!(
+        !CAPTURE.overflowed.load(Ordering::Relaxed),
+        "more than {} bytes were freed",
+        CAPTURE_SIZE
+    );
+    let len = CAPTURE.len.load(Ordering::Relaxed);
+    assert!(len > 0, "nothing was freed");
+    let freed = unsafe { &(*CAPTURE.bytes.get())[..len] };
+    // The scalar is stored without leading zero bytes, so only search for
+    // its tail. Half of it is still far too long to turn up by chance.
+    let tail = &seed[ED25519_SEED_LEN / 2..];
+    assert!(
+        !freed.windows(tail.len()).any(|window| window == tail),
+        "the secret key was found in freed memory"
+    );
+}